@@ -0,0 +1,113 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use anyhow::{Error, Result};
+use num_bigint::BigUint;
+
+// A big number is a `ManyNum` value of limbs of `base_bits` bits each, with the least significant
+// limb first, so that it is the deepest on the stack.
+
+impl DSL {
+    pub fn alloc_input_biguint(
+        &mut self,
+        data_type: impl ToString,
+        value: &BigUint,
+        limbs: usize,
+        base_bits: u32,
+    ) -> Result<usize> {
+        let limbs = to_limbs(value, limbs, base_bits)?;
+        self.alloc_input(data_type, Element::ManyNum(limbs))
+    }
+
+    pub fn get_biguint(&self, idx: usize, base_bits: u32) -> Result<BigUint> {
+        match self.memory.get(&idx) {
+            Some(MemoryEntry {
+                data: Element::ManyNum(v),
+                ..
+            }) => from_limbs(v, base_bits),
+            _ => Err(Error::msg(
+                "Cannot read the requested data in memory as an array of numbers",
+            )),
+        }
+    }
+}
+
+pub fn to_limbs(value: &BigUint, limbs: usize, base_bits: u32) -> Result<Vec<i32>> {
+    check_base_bits(base_bits)?;
+    if value.bits() > limbs as u64 * base_bits as u64 {
+        return Err(Error::msg(
+            "The value does not fit in the given number of limbs",
+        ));
+    }
+
+    let mask = BigUint::from((1u32 << base_bits) - 1);
+    let mut res = vec![];
+    for i in 0..limbs {
+        let limb = (value >> (i as u64 * base_bits as u64)) & &mask;
+        res.push(limb.iter_u32_digits().next().unwrap_or(0) as i32);
+    }
+    Ok(res)
+}
+
+pub fn from_limbs(limbs: &[i32], base_bits: u32) -> Result<BigUint> {
+    check_base_bits(base_bits)?;
+
+    let mut res = BigUint::from(0u32);
+    for &limb in limbs.iter().rev() {
+        if limb < 0 || (limb as u32) >> base_bits != 0 {
+            return Err(Error::msg("A limb is out of the range of the base"));
+        }
+        res = (res << base_bits) + BigUint::from(limb as u32);
+    }
+    Ok(res)
+}
+
+fn check_base_bits(base_bits: u32) -> Result<()> {
+    // a limb must be a non-negative script number
+    if base_bits == 0 || base_bits > 31 {
+        return Err(Error::msg("The number of bits of a limb must be between 1 and 31"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::biguint::{from_limbs, to_limbs};
+    use crate::dsl::{Element, ElementType, DSL};
+    use num_bigint::BigUint;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_biguint_round_trip() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut dsl = DSL::new();
+        dsl.add_data_type("u256", ElementType::ManyNum(9)).unwrap();
+
+        for _ in 0..10 {
+            let bytes = (0..32).map(|_| prng.gen()).collect::<Vec<u8>>();
+            let value = BigUint::from_bytes_le(&bytes);
+
+            let idx = dsl.alloc_input_biguint("u256", &value, 9, 29).unwrap();
+            assert_eq!(dsl.get_biguint(idx, 29).unwrap(), value);
+
+            let limbs = to_limbs(&value, 9, 29).unwrap();
+            assert!(limbs.iter().all(|&limb| (0..1 << 29).contains(&limb)));
+            assert_eq!(dsl.memory.get(&idx).unwrap().data, Element::ManyNum(limbs));
+        }
+    }
+
+    #[test]
+    fn test_biguint_out_of_range() {
+        let mut dsl = DSL::new();
+        dsl.add_data_type("u256", ElementType::ManyNum(8)).unwrap();
+
+        // 2^256 - 1 needs 9 limbs of 29 bits, and 8 limbs of 32 bits cannot be script numbers
+        let max = (BigUint::from(1u32) << 256u32) - 1u32;
+        assert!(dsl.alloc_input_biguint("u256", &max, 8, 29).is_err());
+        assert!(to_limbs(&max, 8, 32).is_err());
+        assert_eq!(from_limbs(&to_limbs(&max, 9, 29).unwrap(), 29).unwrap(), max);
+
+        assert!(from_limbs(&[1 << 29], 29).is_err());
+        assert!(from_limbs(&[-1], 29).is_err());
+    }
+}
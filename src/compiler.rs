@@ -1,44 +1,420 @@
-use crate::dsl::{TraceEntry, DSL};
-use crate::script::CompiledProgram;
+use crate::data_type::{encoded_data_type, NumEncoding};
+use crate::dsl::{Element, ElementType, MemoryEntry, TraceEntry, DSL};
+use crate::script::{instruction_kind, CompileReport, CompiledProgram};
 use crate::stack::Stack;
 use crate::treepp::*;
 use anyhow::{Error, Result};
-use bitcoin::opcodes::Ordinary::{OP_2DROP, OP_DROP, OP_FROMALTSTACK};
+use bitcoin::opcodes::Ordinary::{OP_2DROP, OP_DROP, OP_FROMALTSTACK, OP_TOALTSTACK};
+use bitcoin::opcodes::{all, Class, ClassifyContext};
+use bitcoin::script::Instruction;
 use bitcoin::ScriptBuf;
 use crate::functions::AcceptableFunctionMetadata;
 use crate::options::Options;
+use crate::passes::TracePass;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 pub struct Compiler;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptimizeFor {
+    ScriptSize,
+    StackDepth,
+}
+
+/// Each level runs the passes of the previous one and more, in this order:
+/// - `None` runs nothing, which gives the same script as without any optimization.
+/// - `Size` eliminates the dead code and merges consecutive picks and rolls.
+/// - `Balanced` also folds the functions of constants into constants, before eliminating the dead
+///   code. The folded constants may be larger than the computation they replace, as for hashes.
+/// - `Aggressive` also reorders the trace for a lower stack depth, as `OptimizeFor::StackDepth`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    #[default]
+    None,
+    Size,
+    Balanced,
+    Aggressive,
+}
+
+/// The internal consistency checks of the compiler, each level including the ones below it.
+/// - `None` runs no checks beyond those needed to compile.
+/// - `Fast` checks the stack balance, that the stack model has as many output elements as the
+///   outputs, the altstack balance, that the script leaves the altstack with only the outputs that
+///   are to be left there, and the contiguity, that the script fragments of the trace steps follow
+///   the order of the trace within the script.
+/// - `Full` also runs the altstack check and the determinism check on every gadget, checks the
+///   budget, rejecting a stack deeper than the 1000 elements allowed by consensus, and runs the
+///   program on its own inputs and hints, which together take more time than compiling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VerifyLevel {
+    None,
+    #[default]
+    Fast,
+    Full,
+}
+
+#[derive(Clone, Default)]
+pub struct CompilerOptions {
+    pub optimize_for: Option<OptimizeFor>,
+    pub metadata: Option<serde_json::Value>,
+    /// Stop after the outputs are moved to the altstack and the main stack is cleared. The altstack
+    /// then holds all the output elements, with the first element of the first output at its top, so
+    /// that `OP_FROMALTSTACK`s return the outputs in order, each value's elements in order.
+    pub leave_output_in_altstack: bool,
+    pub validate_registry: bool,
+    /// Before each trace entry, if the main stack holds more elements than this, move the values
+    /// that are needed the furthest in the future to the altstack, and drop those that are no longer
+    /// needed. Note that the 1000-element limit of Bitcoin counts the altstack as well, so spilling
+    /// reduces the depth of the main stack, while only the dropping saves on the overall limit.
+    pub spill_when_over: Option<usize>,
+    /// Start the script by checking that the witness has exactly the elements of the hints and inputs.
+    pub check_witness_len: bool,
+    /// Start the script by checking that the witness has at least the elements of the hints and
+    /// inputs, so that a short witness fails at once rather than on a stack underflow later. Extra
+    /// witness elements pass this check, but the script still fails with them, as they are left
+    /// below the outputs, against the clean stack rule.
+    pub check_min_witness_len: bool,
+    /// After each function call, check that the outputs it marks as range-checked are valid 4-byte
+    /// script numbers, as the arithmetic opcodes may produce larger results that are only rejected
+    /// when they are used as inputs.
+    pub check_num_ranges: bool,
+    pub opt_level: OptLevel,
+    /// Leave the outputs as a single string, their elements concatenated in order, which uses
+    /// OP_CAT and therefore requires `op_cat_enabled`.
+    pub concat_output: bool,
+    /// OP_CAT is disabled on Bitcoin mainnet, but available on some signets and sidechains, so the
+    /// compiler only emits it when this is set.
+    pub op_cat_enabled: bool,
+    /// Reject the trace entries whose scripts use an opcode that the profile disables, which is
+    /// OP_CAT unless `op_cat_enabled` is set.
+    pub check_opcodes: bool,
+    /// Run the compiled program on its own inputs and hints before returning it.
+    pub self_check: bool,
+    /// Keep the decisions of the compiler on every value that it moves, in `CompiledProgram::decisions`.
+    pub record_decisions: bool,
+    /// Push each constant right before the first trace entry that uses it, or before the outputs
+    /// if it is only an output, and not at all if it is never used.
+    pub lazy_constants: bool,
+    /// Run the script generator twice for each distinct function, references, and options, and
+    /// reject the program if the two scripts differ, as caching and reproducible builds assume that
+    /// the generators are pure. Meant for testing gadgets, as it compiles each call twice.
+    pub check_determinism: bool,
+    /// Reject the functions and embedded scripts that do not leave the altstack at the depth they
+    /// find it, as the compiler keeps spilled values and the outputs there.
+    pub check_altstack: bool,
+    pub verify_level: VerifyLevel,
+    /// Start a new segment once the current one has at least this many bytes, at the end of a
+    /// trace step. The boundaries are kept in `CompileReport::segment_boundaries`.
+    pub segment_size: Option<usize>,
+    /// Also mark each segment boundary in the script with `SEGMENT_MARKER`.
+    pub mark_segments: bool,
+    /// Keep the kinds of opcodes that the script of each function emits, not counting the moves
+    /// of its inputs, in `CompiledProgram::coverage`.
+    pub record_coverage: bool,
+}
+
+/// Four OP_NOPs, which leave the stack as it is. Tooling finds the segment boundaries with
+/// `find_segment_boundaries`.
+pub const SEGMENT_MARKER: [u8; 4] = [0x61; 4];
+
+pub fn find_segment_boundaries(script: &bitcoin::Script) -> Vec<usize> {
+    // the offsets of the markers, which are only looked for at instruction boundaries, so that
+    // the same bytes inside a push do not count, and a longer run of OP_NOPs counts once for
+    // every four of them
+    let mut res = vec![];
+    let mut run_start = 0;
+    let mut run_len = 0;
+    for (offset, instruction) in script.instruction_indices() {
+        if matches!(instruction, Ok(Instruction::Op(all::OP_NOP))) {
+            if run_len == 0 {
+                run_start = offset;
+            }
+            run_len += 1;
+            if run_len == SEGMENT_MARKER.len() {
+                res.push(run_start);
+                run_len = 0;
+            }
+        } else {
+            run_len = 0;
+        }
+    }
+    res
+}
+
+/// How the compiler brings a value to where it is used. `position` is the depth of the value in
+/// the compiler's stack model, and `distance` is the depth it is accessed at, which also counts
+/// the inputs already copied above it. The outputs are moved at the step after the last one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileDecision {
+    pub step: usize,
+    pub idx: usize,
+    pub kind: DecisionKind,
+    pub position: usize,
+    pub distance: usize,
+    pub len: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecisionKind {
+    Pick,
+    Roll,
+    /// passed to the gadget by its position, without moving it
+    Ref,
+    /// moved to the altstack, or dropped if it is no longer needed, when spilling
+    Spill,
+    Drop,
+}
+
+impl CompilerOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_optimize_for(mut self, optimize_for: OptimizeFor) -> Self {
+        self.optimize_for = Some(optimize_for);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn with_output_left_in_altstack(mut self) -> Self {
+        self.leave_output_in_altstack = true;
+        self
+    }
+
+    pub fn with_registry_validation(mut self) -> Self {
+        self.validate_registry = true;
+        self
+    }
+
+    pub fn with_spill_when_over(mut self, threshold: usize) -> Self {
+        self.spill_when_over = Some(threshold);
+        self
+    }
+
+    pub fn with_witness_len_check(mut self) -> Self {
+        self.check_witness_len = true;
+        self
+    }
+
+    pub fn with_min_witness_len_check(mut self) -> Self {
+        self.check_min_witness_len = true;
+        self
+    }
+
+    pub fn with_num_range_check(mut self) -> Self {
+        self.check_num_ranges = true;
+        self
+    }
+
+    pub fn with_opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    pub fn with_concat_output(mut self) -> Self {
+        self.concat_output = true;
+        self
+    }
+
+    pub fn with_op_cat(mut self) -> Self {
+        self.op_cat_enabled = true;
+        self
+    }
+
+    pub fn with_opcode_check(mut self) -> Self {
+        self.check_opcodes = true;
+        self
+    }
+
+    pub fn with_self_check(mut self) -> Self {
+        self.self_check = true;
+        self
+    }
+
+    pub fn with_decision_log(mut self) -> Self {
+        self.record_decisions = true;
+        self
+    }
+
+    pub fn with_coverage_map(mut self) -> Self {
+        self.record_coverage = true;
+        self
+    }
+
+    pub fn with_lazy_constants(mut self) -> Self {
+        self.lazy_constants = true;
+        self
+    }
+
+    pub fn with_determinism_check(mut self) -> Self {
+        self.check_determinism = true;
+        self
+    }
+
+    pub fn with_altstack_check(mut self) -> Self {
+        self.check_altstack = true;
+        self
+    }
+
+    pub fn with_verify_level(mut self, verify_level: VerifyLevel) -> Self {
+        self.verify_level = verify_level;
+        self
+    }
+
+    fn with_checks_of_verify_level(mut self) -> Self {
+        if self.verify_level == VerifyLevel::Full {
+            self.check_altstack = true;
+            self.check_determinism = true;
+            self.self_check = true;
+        }
+        self
+    }
+
+    pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = Some(segment_size);
+        self
+    }
+
+    pub fn with_segment_markers(mut self) -> Self {
+        self.mark_segments = true;
+        self
+    }
+}
+
+// the state of the compiler after the trace, before the outputs are moved to the altstack
+struct CompiledPrefix {
+    dsl: DSL,
+    input: Vec<MemoryEntry>,
+    stack: Stack,
+    script: Vec<u8>,
+    report: CompileReport,
+    trace_byte_ranges: Vec<(usize, usize)>,
+    decisions: Vec<CompileDecision>,
+    coverage: BTreeMap<String, BTreeSet<String>>,
+    num_steps: usize,
+}
+
+impl CompiledPrefix {
+    fn duplicate(&self) -> Result<CompiledPrefix> {
+        Ok(CompiledPrefix {
+            dsl: self.dsl.clone(),
+            input: self.input.clone(),
+            stack: self.stack.duplicate()?,
+            script: self.script.clone(),
+            report: self.report.clone(),
+            trace_byte_ranges: self.trace_byte_ranges.clone(),
+            decisions: self.decisions.clone(),
+            coverage: self.coverage.clone(),
+            num_steps: self.num_steps,
+        })
+    }
+}
+
 impl Compiler {
+    pub fn compile_optimized(dsl: DSL, optimize_for: OptimizeFor) -> Result<CompiledProgram> {
+        Self::compile_with(dsl, CompilerOptions::new().with_optimize_for(optimize_for))
+    }
+
     pub fn compiler(dsl: DSL) -> Result<CompiledProgram> {
+        Self::compile_with(dsl, CompilerOptions::new())
+    }
+
+    pub fn compile_checked(dsl: DSL, compiler_options: CompilerOptions) -> Result<CompiledProgram> {
+        Self::compile_with(dsl, compiler_options.with_altstack_check())
+    }
+
+    pub fn compile_with_passes(mut dsl: DSL, passes: &[Box<dyn TracePass>]) -> Result<CompiledProgram> {
+        for pass in passes.iter() {
+            pass.run(&mut dsl)?;
+        }
+        Self::compiler(dsl)
+    }
+
+    pub fn compile_with(dsl: DSL, compiler_options: CompilerOptions) -> Result<CompiledProgram> {
+        let compiler_options = compiler_options.with_checks_of_verify_level();
+        let prefix = Self::compile_prefix(dsl, &compiler_options)?;
+        let output = prefix.dsl.output.clone();
+        Self::compile_outputs(prefix, output, compiler_options)
+    }
+
+    fn compile_prefix(mut dsl: DSL, compiler_options: &CompilerOptions) -> Result<CompiledPrefix> {
+        // everything up to the outputs, which are left where the trace leaves them
+        if compiler_options.validate_registry {
+            dsl.validate_registry()?;
+        }
+        dsl.check_outputs_produced()?;
+        if compiler_options.concat_output {
+            if !compiler_options.op_cat_enabled {
+                return Err(Error::msg(
+                    "Concatenating the outputs requires OP_CAT, which is not enabled",
+                ));
+            }
+            if compiler_options.leave_output_in_altstack {
+                return Err(Error::msg(
+                    "The outputs cannot be both concatenated and left in the altstack",
+                ));
+            }
+        }
+        let opt_level = compiler_options.opt_level;
+        if opt_level >= OptLevel::Balanced {
+            dsl.fold_constants()?;
+        }
+        if opt_level >= OptLevel::Size {
+            dsl.eliminate_dead_code()?;
+        }
+        if compiler_options.optimize_for == Some(OptimizeFor::StackDepth) || opt_level == OptLevel::Aggressive {
+            dsl.schedule_for_stack_depth()?;
+        }
+        let merge_rolls = compiler_options.optimize_for.is_some() || opt_level >= OptLevel::Size;
+
         // step 1: count the last visit of all the memory entries
         let num_memory_entries = dsl.memory_last_idx;
         let mut last_visit = vec![-1isize; num_memory_entries];
 
+        // the time advances with every function call or embedded script, as in the script generation
         let mut cur_time = 0;
         for trace_entry in dsl.trace.iter() {
             match trace_entry {
-                TraceEntry::FunctionCall(_, inputs) => {
-                    for &i in inputs.iter() {
+                TraceEntry::AllocatedConstant(_)
+                | TraceEntry::AllocatedHint(_)
+                | TraceEntry::RelativeTimelock(_)
+                | TraceEntry::AbsoluteTimelock(_) => {}
+                _ => {
+                    for &i in trace_entry.input_idxs().iter() {
                         last_visit[i] = cur_time;
                     }
                     cur_time += 1;
                 }
-                _ => {}
             }
         }
 
-        // step 2: allocate all the inputs
+        // step 2: allocate all the inputs, which are all the memory entries if nothing follows them
+        let num_inputs = dsl.num_inputs.unwrap_or(dsl.memory_last_idx);
         let mut input = vec![];
-        if let Some(num_inputs) = dsl.num_inputs {
-            for i in 0..num_inputs {
-                input.push(dsl.memory.get(&i).unwrap().clone())
-            }
+        for i in 0..num_inputs {
+            input.push(dsl.memory.get(&i).unwrap().clone())
         }
 
         // step 3: initialize the stack
-        let mut stack = Stack::new(dsl.memory_last_idx);
+        // the values pushed by the prologues are tracked after the memory entries, one per function
+        let mut prologue_idxs = HashMap::new();
+        for trace_entry in dsl.trace.iter() {
+            if let TraceEntry::FunctionCall(function_name, _)
+            | TraceEntry::FunctionCallWithOptions(function_name, _, _) = trace_entry
+            {
+                let function_metadata = dsl.function_registry.map.get(function_name);
+                if function_metadata.is_some_and(|v| v.prologue_script().is_some())
+                    && !prologue_idxs.contains_key(function_name)
+                {
+                    prologue_idxs.insert(function_name.clone(), dsl.memory_last_idx + prologue_idxs.len());
+                }
+            }
+        }
+        let mut stack = Stack::new(dsl.memory_last_idx + prologue_idxs.len());
         for (i, input_entry) in input.iter().enumerate() {
             stack.push_to_stack(i, input_entry.data.len())?;
         }
@@ -46,114 +422,200 @@ impl Compiler {
         // step 3: generate the script
         let mut script = Vec::<u8>::new();
 
-        let mut cur_time = 0;
-        let mut allocated_idx = dsl.num_inputs.unwrap_or_default();
+        // the bytes of the script that each trace entry generates, which are empty for some
+        let mut trace_byte_ranges = vec![(0, 0); dsl.trace.len()];
 
-        for trace_entry in dsl.trace.iter() {
+        // timelocks do not depend on the stack, so they are all checked at the start of the script
+        for (step, trace_entry) in dsl.trace.iter().enumerate() {
+            let start = script.len();
             match trace_entry {
-                TraceEntry::FunctionCall(function_name, inputs) => {
-                    let function_metadata = dsl
-                        .function_registry
-                        .map
-                        .get(&function_name.to_string())
-                        .unwrap();
+                TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_) => {
+                    script.extend_from_slice(timelock_script(trace_entry).as_bytes());
+                }
+                _ => {}
+            }
+            trace_byte_ranges[step] = (start, script.len());
+        }
 
-                    let input = match function_metadata {
-                        AcceptableFunctionMetadata::FunctionWithoutOptions(v) => &v.input,
-                        AcceptableFunctionMetadata::FunctionWithOptions(v) => &v.input
-                    };
+        let witness_len = dsl
+            .hint
+            .iter()
+            .chain(input.iter())
+            .map(|entry| entry.data.len())
+            .sum::<usize>();
+        if compiler_options.check_witness_len {
+            script.extend_from_slice(
+                script! {
+                    OP_DEPTH { witness_len } OP_EQUALVERIFY
+                }
+                .as_bytes(),
+            );
+        }
+        if compiler_options.check_min_witness_len {
+            script.extend_from_slice(
+                script! {
+                    OP_DEPTH { witness_len } OP_GREATERTHANOREQUAL OP_VERIFY
+                }
+                .as_bytes(),
+            );
+        }
 
-                    let mut deferred_ref = vec![];
-                    let mut num_cloned_input_elements = 0;
-                    for (i, (&input_idx, input_type)) in inputs
-                        .iter()
-                        .zip(input.iter())
-                        .enumerate()
-                    {
-                        let input_type_name = dsl.memory.get(&input_idx).unwrap().data_type.clone();
+        let mut cur_time = 0;
+        let mut allocated_idx = num_inputs;
 
-                        let input_metadata = dsl
-                            .data_type_registry
-                            .map
-                            .get(&input_type_name.to_string())
-                            .unwrap();
+        let mut report = CompileReport {
+            max_stack_depth: stack.get_num_elements_in_stack()?,
+            ..Default::default()
+        };
 
-                        if input_type.starts_with("&") {
-                            deferred_ref.push(input_idx);
-                            // do not obtain the location of the ref-only element before we clone other inputs.
-                        } else {
-                            let len = input_metadata.element_type.len();
-                            let pos = stack.get_relative_position(input_idx)?;
-                            let distance = pos + num_cloned_input_elements;
+        // the trace steps that use each memory entry, for choosing what to spill
+        let num_steps = dsl.trace.len();
+        let mut uses = vec![vec![]; num_memory_entries];
+        for (step, trace_entry) in dsl.trace.iter().enumerate() {
+            for &i in trace_entry.input_idxs().iter() {
+                uses[i].push(step);
+            }
+        }
+        let mut spilled = vec![];
+        let mut decisions = vec![];
+        let mut pending_constants = vec![];
+        let mut checked_generators: Vec<(String, Vec<usize>, Options)> = vec![];
+        let mut segment_start = 0;
+        let mut coverage: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
 
-                            if last_visit[input_idx] == cur_time
-                                && !inputs[i..].contains(&input_idx)
-                                && !dsl.output.contains(&input_idx)
-                            {
-                                // roll
-                                stack.pull(input_idx)?;
-                                script.extend_from_slice(roll_script(distance, len).as_bytes());
-                                num_cloned_input_elements += len;
-                            } else {
-                                // pick
-                                script.extend_from_slice(pick_script(distance, len).as_bytes());
-                                num_cloned_input_elements += len;
-                            }
-                        }
-                    }
+        for (step, trace_entry) in dsl.trace.iter().enumerate() {
+            let start = script.len();
+            for &idx in trace_entry.input_idxs().iter() {
+                if let Some(i) = pending_constants.iter().position(|&pending| pending == idx) {
+                    pending_constants.remove(i);
+                    push_constant(&dsl, idx, &mut stack, &mut script, &mut report)?;
+                }
+            }
 
-                    // It takes into the account of the elements that disappear due to pull,
-                    // but it doesn't consider elements that are just copied/moved near the function stack.
-                    let mut ref_positions = vec![];
-                    for &input_idx in deferred_ref.iter() {
-                        ref_positions.push(stack.get_relative_position(input_idx)?);
+            if let Some(threshold) = compiler_options.spill_when_over {
+                let needed = trace_entry.input_idxs();
+
+                // the altstack returns the values in the reverse order of spilling
+                while spilled.iter().any(|(idx, _)| needed.contains(idx)) {
+                    restore_spilled(&mut stack, &mut spilled, &mut script)?;
+                }
+                report.max_stack_depth = report.max_stack_depth.max(stack.get_num_elements_in_stack()?);
+
+                while stack.get_num_elements_in_stack()? > threshold {
+                    let mut candidate: Option<(usize, usize)> = None;
+                    for (idx, idx_uses) in uses.iter().enumerate() {
+                        if !stack.is_present(idx)? || needed.contains(&idx) {
+                            continue;
+                        }
+                        let next_use = match idx_uses.iter().find(|&&t| t >= step) {
+                            Some(&t) => t,
+                            None if dsl.output.contains(&idx) => num_steps,
+                            None => usize::MAX,
+                        };
+                        let is_better = match candidate {
+                            Some((best_next_use, _)) => next_use > best_next_use,
+                            None => true,
+                        };
+                        if is_better {
+                            candidate = Some((next_use, idx));
+                        }
                     }
 
-                    match function_metadata {
-                        AcceptableFunctionMetadata::FunctionWithoutOptions(v) => {
-                            script.extend_from_slice((v.script_generator)(&ref_positions)?.as_bytes());
+                    let (next_use, idx) = match candidate {
+                        Some(v) => v,
+                        None => break,
+                    };
+                    let pos = stack.get_relative_position(idx)?;
+                    let len = stack.get_length(idx)?;
+                    stack.pull(idx)?;
+                    script.extend_from_slice(roll_script(pos, len, merge_rolls, &mut report).as_bytes());
+                    decisions.push(CompileDecision {
+                        step,
+                        idx,
+                        kind: if next_use == usize::MAX { DecisionKind::Drop } else { DecisionKind::Spill },
+                        position: pos,
+                        distance: pos,
+                        len,
+                    });
+                    if next_use == usize::MAX {
+                        for _ in 0..len {
+                            script.push(OP_DROP.to_u8());
                         }
-                        AcceptableFunctionMetadata::FunctionWithOptions(v) => {
-                            script.extend_from_slice((v.script_generator)(&ref_positions, &Options::new())?.as_bytes());
+                    } else {
+                        for _ in 0..len {
+                            script.push(OP_TOALTSTACK.to_u8());
                         }
+                        spilled.push((idx, len));
+                        report.num_spills += 1;
                     }
+                }
+            }
 
-                    let output = match function_metadata {
-                        AcceptableFunctionMetadata::FunctionWithoutOptions(v) => &v.output,
-                        AcceptableFunctionMetadata::FunctionWithOptions(v) => &v.output,
+            match trace_entry {
+                TraceEntry::FunctionCall(_, inputs)
+                | TraceEntry::FunctionCallWithOptions(_, inputs, _)
+                | TraceEntry::EmbeddedScript(_, inputs, _) => {
+                    // an embedded script is handled as a function without references whose script is given
+                    let function_metadata = match trace_entry {
+                        TraceEntry::FunctionCall(function_name, _)
+                        | TraceEntry::FunctionCallWithOptions(function_name, _, _) => Some(
+                            dsl.function_registry
+                                .map
+                                .get(&function_name.to_string())
+                                .unwrap(),
+                        ),
+                        _ => None,
                     };
 
-                    // push the corresponding outputs
-                    for output_type in output.iter() {
-                        let data_type_metadata = dsl
-                            .data_type_registry
-                            .map
-                            .get(&output_type.to_string())
-                            .unwrap();
-                        stack
-                            .push_to_stack(allocated_idx, data_type_metadata.element_type.len())?;
-                        allocated_idx += 1;
-                    }
+                    let options = match trace_entry {
+                        TraceEntry::FunctionCallWithOptions(_, _, options) => {
+                            if !matches!(function_metadata, Some(AcceptableFunctionMetadata::FunctionWithOptions(_))) {
+                                return Err(Error::msg("The function does not offer options"));
+                            }
+                            options.clone()
+                        }
+                        _ => Options::new(),
+                    };
 
-                    cur_time += 1;
-                }
-                TraceEntry::FunctionCallWithOptions(function_name, inputs, options) => {
-                    let function_metadata = dsl
-                        .function_registry
-                        .map
-                        .get(&function_name.to_string())
-                        .unwrap();
+                    let (input, num_outputs) = match (function_metadata, trace_entry) {
+                        (Some(AcceptableFunctionMetadata::FunctionWithoutOptions(v)), _) => (v.input.clone(), v.output.len()),
+                        (Some(AcceptableFunctionMetadata::FunctionWithOptions(v)), _) => {
+                            (v.input.clone(), v.output_types(&options).len())
+                        }
+                        (_, TraceEntry::EmbeddedScript(_, _, output_types)) => (vec!["any"; inputs.len()], output_types.len()),
+                        _ => unreachable!(),
+                    };
 
-                    let function_metadata = match function_metadata {
-                        AcceptableFunctionMetadata::FunctionWithOptions(v) => v,
-                        _ => return Err(Error::msg("The function does not offer options")),
+                    // the prologue goes below the copies of the inputs, the first time the function is called
+                    let prologue = match trace_entry {
+                        TraceEntry::FunctionCall(function_name, _)
+                        | TraceEntry::FunctionCallWithOptions(function_name, _, _) => prologue_idxs
+                            .get(function_name)
+                            .map(|&idx| (idx, function_metadata.unwrap().prologue_script().unwrap())),
+                        _ => None,
                     };
+                    if let Some((prologue_idx, prologue_script)) = prologue {
+                        if !stack.is_present(prologue_idx)? {
+                            let prologue_script = prologue_script();
+                            stack.push_to_stack(prologue_idx, prologue_len(&prologue_script)?)?;
+                            script.extend_from_slice(prologue_script.as_bytes());
+                            report.max_stack_depth = report.max_stack_depth.max(stack.get_num_elements_in_stack()?);
+                        }
+                    }
+
+                    // a value that is also passed by reference must stay in place
+                    let ref_inputs = inputs
+                        .iter()
+                        .zip(input.iter())
+                        .filter(|(_, input_type)| input_type.starts_with("&"))
+                        .map(|(&input_idx, _)| input_idx)
+                        .collect::<Vec<usize>>();
 
                     let mut deferred_ref = vec![];
                     let mut num_cloned_input_elements = 0;
                     for (i, (&input_idx, input_type)) in inputs
                         .iter()
-                        .zip(function_metadata.input.iter())
+                        .zip(input.iter())
                         .enumerate()
                     {
                         let input_type_name = dsl.memory.get(&input_idx).unwrap().data_type.clone();
@@ -172,19 +634,36 @@ impl Compiler {
                             let pos = stack.get_relative_position(input_idx)?;
                             let distance = pos + num_cloned_input_elements;
 
-                            if last_visit[input_idx] == cur_time
-                                && !inputs[i..].contains(&input_idx)
+                            let kind = if last_visit[input_idx] == cur_time
+                                && !inputs[i + 1..].contains(&input_idx)
+                                && !ref_inputs.contains(&input_idx)
                                 && !dsl.output.contains(&input_idx)
                             {
                                 // roll
                                 stack.pull(input_idx)?;
-                                script.extend_from_slice(roll_script(distance, len).as_bytes());
+                                script.extend_from_slice(roll_script(distance, len, merge_rolls, &mut report).as_bytes());
                                 num_cloned_input_elements += len;
+                                report.rolled_values += 1;
+                                DecisionKind::Roll
                             } else {
                                 // pick
-                                script.extend_from_slice(pick_script(distance, len).as_bytes());
+                                script.extend_from_slice(pick_script(distance, len, merge_rolls, &mut report).as_bytes());
                                 num_cloned_input_elements += len;
-                            }
+                                report.picked_values += 1;
+                                DecisionKind::Pick
+                            };
+                            decisions.push(CompileDecision {
+                                step,
+                                idx: input_idx,
+                                kind,
+                                position: pos,
+                                distance,
+                                len,
+                            });
+                            report.max_access_depth = report.max_access_depth.max(distance);
+                            report.max_stack_depth = report
+                                .max_stack_depth
+                                .max(stack.get_num_elements_in_stack()? + num_cloned_input_elements);
                         }
                     }
 
@@ -192,41 +671,129 @@ impl Compiler {
                     // but it doesn't consider elements that are just copied/moved near the function stack.
                     let mut ref_positions = vec![];
                     for &input_idx in deferred_ref.iter() {
-                        ref_positions.push(stack.get_relative_position(input_idx)?);
+                        let pos = stack.get_relative_position(input_idx)?;
+                        report.max_access_depth = report.max_access_depth.max(pos + num_cloned_input_elements);
+                        ref_positions.push(pos);
+                        decisions.push(CompileDecision {
+                            step,
+                            idx: input_idx,
+                            kind: DecisionKind::Ref,
+                            position: pos,
+                            distance: pos + num_cloned_input_elements,
+                            len: stack.get_length(input_idx)?,
+                        });
+                    }
+                    if let Some((prologue_idx, _)) = prologue {
+                        let pos = stack.get_relative_position(prologue_idx)?;
+                        report.max_access_depth = report.max_access_depth.max(pos + num_cloned_input_elements);
+                        ref_positions.push(pos);
                     }
 
-                    script.extend_from_slice((function_metadata.script_generator)(&ref_positions, &options)?.as_bytes());
+                    let function_script = match (function_metadata, trace_entry) {
+                        (Some(function_metadata), _) => {
+                            generate_script(function_metadata, &ref_positions, &options)?
+                        }
+                        (_, TraceEntry::EmbeddedScript(embedded_script, _, _)) => embedded_script.clone(),
+                        _ => unreachable!(),
+                    };
+                    if compiler_options.check_altstack {
+                        // the altstack used in a branch cannot be counted, so such a script is not
+                        // checked here, and is left to the simulation
+                        let effect = altstack_effect(function_script.as_bytes()).unwrap_or(0);
+                        if effect != 0 {
+                            let name = match trace_entry {
+                                TraceEntry::FunctionCall(function_name, _)
+                                | TraceEntry::FunctionCallWithOptions(function_name, _, _) => {
+                                    format!("The function {}", function_name)
+                                }
+                                _ => "The embedded script".to_string(),
+                            };
+                            return Err(Error::msg(format!(
+                                "{} at the trace step {} changes the depth of the altstack by {}",
+                                name, step, effect
+                            )));
+                        }
+                    }
+                    if let (
+                        true,
+                        Some(function_metadata),
+                        TraceEntry::FunctionCall(function_name, _)
+                        | TraceEntry::FunctionCallWithOptions(function_name, _, _),
+                    ) = (compiler_options.check_determinism, function_metadata, trace_entry)
+                    {
+                        let key = (function_name.clone(), ref_positions.clone(), options.clone());
+                        if !checked_generators.contains(&key) {
+                            let again = generate_script(function_metadata, &ref_positions, &options)?;
+                            if again.as_bytes() != function_script.as_bytes() {
+                                return Err(Error::msg(format!(
+                                    "The script generator of the function {} is not deterministic",
+                                    function_name
+                                )));
+                            }
+                            checked_generators.push(key);
+                        }
+                    }
+                    if compiler_options.record_coverage {
+                        let name = match trace_entry {
+                            TraceEntry::FunctionCall(function_name, _)
+                            | TraceEntry::FunctionCallWithOptions(function_name, _, _) => function_name.to_string(),
+                            _ => "an embedded script".to_string(),
+                        };
+                        coverage
+                            .entry(name)
+                            .or_default()
+                            .extend(function_script.instructions().map(|instruction| instruction_kind(&instruction)));
+                    }
+                    script.extend_from_slice(function_script.as_bytes());
 
-                    // push the corresponding outputs
-                    for output_type in function_metadata.output.iter() {
+                    // push the corresponding outputs, whose types are the actual ones if declared as `any`
+                    let first_output_idx = allocated_idx;
+                    for _ in 0..num_outputs {
+                        let data_type = &dsl.memory.get(&allocated_idx).unwrap().data_type;
                         let data_type_metadata = dsl
                             .data_type_registry
                             .map
-                            .get(&output_type.to_string())
+                            .get(&data_type.to_string())
                             .unwrap();
                         stack
                             .push_to_stack(allocated_idx, data_type_metadata.element_type.len())?;
                         allocated_idx += 1;
                     }
+                    report.max_stack_depth = report.max_stack_depth.max(stack.get_num_elements_in_stack()?);
+
+                    if compiler_options.check_num_ranges {
+                        let range_checked_outputs = match function_metadata {
+                            Some(AcceptableFunctionMetadata::FunctionWithoutOptions(v)) => v.range_checked_outputs.as_slice(),
+                            Some(AcceptableFunctionMetadata::FunctionWithOptions(v)) => v.range_checked_outputs.as_slice(),
+                            None => &[],
+                        };
+                        for &j in range_checked_outputs.iter() {
+                            let idx = first_output_idx + j;
+                            let data_type = &dsl.memory.get(&idx).unwrap().data_type;
+                            let element_type = &dsl.data_type_registry.map.get(data_type).unwrap().element_type;
+                            if !matches!(element_type, ElementType::Num | ElementType::ManyNum(_)) {
+                                return Err(Error::msg("Only numbers can be range-checked"));
+                            }
+
+                            // numeric opcodes fail on anything that is not a 4-byte script number
+                            let pos = stack.get_relative_position(idx)?;
+                            for distance in pos + 1 - element_type.len()..=pos {
+                                script.extend_from_slice(pick_script(distance, 1, false, &mut report).as_bytes());
+                                script.extend_from_slice(script! { OP_ABS OP_DROP }.as_bytes());
+                            }
+                        }
+                    }
 
                     cur_time += 1;
                 }
+                TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_) => {}
                 TraceEntry::AllocatedConstant(idx) => {
-                    let data_type = &dsl.memory.get(idx).unwrap().data_type;
-                    let input_metadata = dsl
-                        .data_type_registry
-                        .map
-                        .get(&data_type.to_string())
-                        .unwrap();
-                    stack.push_to_stack(*idx, input_metadata.element_type.len())?;
                     allocated_idx += 1;
-
-                    script.extend_from_slice(
-                        script! {
-                            { dsl.memory.get(idx).unwrap() }
-                        }
-                        .as_bytes(),
-                    );
+                    if !compiler_options.lazy_constants {
+                        push_constant(&dsl, *idx, &mut stack, &mut script, &mut report)?;
+                    } else if last_visit[*idx] != -1 || dsl.output.contains(idx) {
+                        pending_constants.push(*idx);
+                    }
                 }
                 TraceEntry::AllocatedHint(idx) => {
                     let data_type = &dsl.memory.get(idx).unwrap().data_type;
@@ -238,24 +805,97 @@ impl Compiler {
                     let len = input_metadata.element_type.len();
                     stack.push_to_stack(*idx, len)?;
                     allocated_idx += 1;
+                    report.max_stack_depth = report.max_stack_depth.max(stack.get_num_elements_in_stack()?);
 
-                    script.extend_from_slice(
-                        script! {
-                            for _ in 0..len {
-                                OP_DEPTH OP_1SUB OP_ROLL
-                            }
-                        }
-                        .as_bytes(),
-                    );
+                    script.extend_from_slice(hint_script(len).as_bytes());
+                    report.op_roll_count += len;
+                }
+            }
+
+            if !matches!(trace_entry, TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_)) {
+                trace_byte_ranges[step] = (start, script.len());
+            }
+
+            if let Some(segment_size) = compiler_options.segment_size {
+                if script.len() - segment_start >= segment_size && step + 1 < num_steps {
+                    // the marker, if any, starts the next segment
+                    report.segment_boundaries.push(script.len());
+                    if compiler_options.mark_segments {
+                        script.extend_from_slice(&SEGMENT_MARKER);
+                    }
+                    segment_start = script.len();
+                }
+            }
+        }
+
+        if compiler_options.check_opcodes && !compiler_options.op_cat_enabled {
+            for (step, &(start, end)) in trace_byte_ranges.iter().enumerate() {
+                let uses_op_cat = bitcoin::Script::from_bytes(&script[start..end])
+                    .instructions()
+                    .any(|instruction| matches!(instruction, Ok(Instruction::Op(all::OP_CAT))));
+                if uses_op_cat {
+                    let name = match &dsl.trace[step] {
+                        TraceEntry::FunctionCall(function_name, _)
+                        | TraceEntry::FunctionCallWithOptions(function_name, _, _) => function_name.to_string(),
+                        _ => "an embedded script".to_string(),
+                    };
+                    return Err(Error::msg(format!(
+                        "The trace step {} ({}) uses OP_CAT, which is not enabled",
+                        step, name
+                    )));
                 }
             }
         }
 
+        // bring back the spilled values, so that the altstack is empty again
+        while !spilled.is_empty() {
+            restore_spilled(&mut stack, &mut spilled, &mut script)?;
+        }
+
+        // the constants that are only outputs
+        for idx in pending_constants.drain(..) {
+            push_constant(&dsl, idx, &mut stack, &mut script, &mut report)?;
+        }
+
+        Ok(CompiledPrefix {
+            dsl,
+            input,
+            stack,
+            script,
+            report,
+            trace_byte_ranges,
+            decisions,
+            coverage,
+            num_steps,
+        })
+    }
+
+    fn compile_outputs(
+        prefix: CompiledPrefix,
+        output: Vec<usize>,
+        compiler_options: CompilerOptions,
+    ) -> Result<CompiledProgram> {
+        // the outputs must be among those the prefix is compiled for, which keeps them on the stack
+        let CompiledPrefix {
+            mut dsl,
+            input,
+            mut stack,
+            mut script,
+            mut report,
+            trace_byte_ranges,
+            mut decisions,
+            coverage,
+            num_steps,
+        } = prefix;
+        dsl.output = output;
+
         // step 4: move the desired output to the altstack
         let mut output_list_rev = dsl.output.clone();
         output_list_rev.reverse();
 
         let mut output_total_len = 0;
+        // the spilled values are all restored, so the altstack only holds the outputs moved so far
+        let mut altstack_depth = 0;
 
         for (i, &idx) in output_list_rev.iter().enumerate() {
             // for each entry, roll or pick the data and then save the data to the altstack
@@ -266,9 +906,25 @@ impl Compiler {
 
             let pos = stack.get_relative_position(idx)?;
             let len = stack.get_length(idx)?;
+            report.max_access_depth = report.max_access_depth.max(pos);
+
+            let kind = if output_list_rev[i + 1..].contains(&idx) {
+                DecisionKind::Pick
+            } else {
+                DecisionKind::Roll
+            };
+            decisions.push(CompileDecision {
+                step: num_steps,
+                idx,
+                kind,
+                position: pos,
+                distance: pos,
+                len,
+            });
 
-            if output_list_rev[i..].contains(&idx) {
+            if output_list_rev[i + 1..].contains(&idx) {
                 // pick
+                report.op_pick_count += len;
                 script.extend_from_slice(
                     script! {
                         for _ in 0..len {
@@ -283,6 +939,7 @@ impl Compiler {
             } else {
                 // roll
                 stack.pull(idx)?;
+                report.op_roll_count += len;
                 script.extend_from_slice(
                     script! {
                         for _ in 0..len {
@@ -296,6 +953,7 @@ impl Compiler {
                 );
             }
             output_total_len += len;
+            altstack_depth += len;
         }
 
         // clear all the remaining elements
@@ -307,36 +965,360 @@ impl Compiler {
             script.push(OP_DROP.to_u8());
         }
 
+        report.final_altstack_depth = altstack_depth;
+
         // recover the output from the altstack
-        for _ in 0..output_total_len {
-            script.push(OP_FROMALTSTACK.to_u8());
+        if !compiler_options.leave_output_in_altstack {
+            for _ in 0..output_total_len {
+                script.push(OP_FROMALTSTACK.to_u8());
+            }
         }
 
-        Ok(CompiledProgram {
-            input,
-            script: ScriptBuf::from_bytes(script),
-            hint: dsl.hint,
-        })
-    }
-}
-
-fn roll_script(distance: usize, len: usize) -> Script {
-    if distance == len - 1 {
-        script! {} // do nothing, it is already on the top of the stack
-    } else {
-        if distance == 1 {
-            script! {
-                for _ in 0..len {
-                    OP_SWAP
+        if compiler_options.check_altstack || compiler_options.verify_level >= VerifyLevel::Fast {
+            // scripts using the altstack in a branch cannot be counted, and are left to the simulation
+            let expected = if compiler_options.leave_output_in_altstack { output_total_len } else { 0 };
+            if let Some(depth) = altstack_effect(&script) {
+                if depth != expected as isize {
+                    return Err(Error::msg(format!(
+                        "The script leaves {} elements in the altstack instead of {}",
+                        depth, expected
+                    )));
                 }
             }
-        } else if distance == 2 {
-            script! {
-                for _ in 0..len {
-                    OP_ROT
+        }
+
+        // values of types with a custom encoding are given as their stack elements
+        let mut output = dsl
+            .output
+            .iter()
+            .map(|idx| encode_entry(&dsl, dsl.memory.get(idx).unwrap()))
+            .collect::<Result<Vec<MemoryEntry>>>()?;
+
+        if compiler_options.verify_level >= VerifyLevel::Fast {
+            let num_output_elements = output.iter().map(|entry| entry.data.len()).sum::<usize>();
+            if num_output_elements != output_total_len {
+                return Err(Error::msg(format!(
+                    "The stack model moves {} output elements, but the outputs have {}",
+                    output_total_len, num_output_elements
+                )));
+            }
+        }
+
+        if compiler_options.concat_output {
+            // the last element is on the top, so concatenating from the top keeps the order
+            let mut concatenated = vec![];
+            for entry in output.iter() {
+                for element in entry.data.to_witness() {
+                    concatenated.extend(element);
+                }
+            }
+            if concatenated.len() > 520 {
+                return Err(Error::msg(
+                    "The concatenated outputs exceed the 520-byte limit of a stack element",
+                ));
+            }
+
+            if output_total_len == 0 {
+                script.push(all::OP_PUSHBYTES_0.to_u8());
+            }
+            for _ in 1..output_total_len {
+                script.push(all::OP_CAT.to_u8());
+            }
+            output = vec![MemoryEntry::new("str", Element::Str(concatenated))];
+        }
+        let input = input
+            .iter()
+            .map(|entry| encode_entry(&dsl, entry))
+            .collect::<Result<Vec<MemoryEntry>>>()?;
+        let hint = dsl
+            .hint
+            .iter()
+            .map(|entry| encode_entry(&dsl, entry))
+            .collect::<Result<Vec<MemoryEntry>>>()?;
+
+        let mut program = CompiledProgram {
+            input,
+            script: ScriptBuf::from_bytes(script),
+            hint,
+            output,
+            metadata: compiler_options.metadata,
+            report,
+            trace_byte_ranges,
+            annotations: dsl.annotations.clone(),
+            trace: dsl.trace.clone(),
+            allocations: dsl.allocations(),
+            output_idxs: dsl.output.clone(),
+            output_in_altstack: compiler_options.leave_output_in_altstack,
+            decisions: if compiler_options.record_decisions { decisions } else { vec![] },
+            coverage,
+        };
+
+        program.report.script_size = program.script.len();
+        program.report.num_inputs = program.input.len();
+        program.report.num_constants = dsl
+            .trace
+            .iter()
+            .filter(|trace_entry| matches!(trace_entry, TraceEntry::AllocatedConstant(_)))
+            .count();
+        program.report.num_hints = program.hint.len();
+        program.report.num_shuffle_ops = program.num_shuffle_ops();
+        program.report.opcode_histogram = program.opcode_histogram();
+
+        if compiler_options.verify_level >= VerifyLevel::Fast {
+            // the timelocks are all checked at the start, and the other steps follow in trace order
+            let mut last = None;
+            for (step, &(start, end)) in program.trace_byte_ranges.iter().enumerate() {
+                if start == end
+                    || matches!(program.trace[step], TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_))
+                {
+                    continue;
+                }
+                if let Some((last_step, last_end)) = last {
+                    if start < last_end {
+                        return Err(Error::msg(format!(
+                            "The script fragment of the trace step {} does not follow that of the trace step {}",
+                            step, last_step
+                        )));
+                    }
+                }
+                if end > program.script.len() {
+                    return Err(Error::msg("A script fragment ends after the script"));
+                }
+                last = Some((step, end));
+            }
+        }
+        if compiler_options.verify_level == VerifyLevel::Full && program.report.max_stack_depth > 1000 {
+            return Err(Error::msg(format!(
+                "The stack reaches a depth of {}, over the limit of 1000 elements",
+                program.report.max_stack_depth
+            )));
+        }
+
+        if compiler_options.self_check {
+            program.self_check()?;
+        }
+
+        Ok(program)
+    }
+
+    pub fn compile_variants(
+        mut dsl: DSL,
+        variants: &[Vec<usize>],
+        compiler_options: CompilerOptions,
+    ) -> Result<Vec<CompiledProgram>> {
+        // Each variant replaces the outputs of the program. The trace is compiled once for the
+        // outputs of all the variants, which keeps them on the stack, and each variant then moves
+        // its own outputs from there, so the variants share the script up to their outputs. They
+        // are completed in parallel with the `parallel` feature, in the order given.
+        let compiler_options = compiler_options.with_checks_of_verify_level();
+        let positions = set_variant_outputs(&mut dsl, variants)?;
+        let prefix = Self::compile_prefix(dsl, &compiler_options)?;
+
+        // the passes may renumber the outputs, which are therefore taken by their positions
+        let compile_variant = |positions: &Vec<usize>| -> Result<CompiledProgram> {
+            let output = positions.iter().map(|&i| prefix.dsl.output[i]).collect();
+            Self::compile_outputs(prefix.duplicate()?, output, compiler_options.clone())
+        };
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            positions.par_iter().map(compile_variant).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            positions.iter().map(compile_variant).collect()
+        }
+    }
+
+    pub fn setup_script(dsl: DSL) -> Result<Script> {
+        // only the pushes of the constants and the pulls of the hints, in the order of the trace,
+        // which do not depend on the rest of the stack
+        let mut script = Vec::<u8>::new();
+        for trace_entry in dsl.trace.iter() {
+            match trace_entry {
+                TraceEntry::AllocatedConstant(idx) => {
+                    script.extend_from_slice(constant_script(&dsl, *idx)?.as_bytes());
+                }
+                TraceEntry::AllocatedHint(idx) => {
+                    let data_type = &dsl.memory.get(idx).unwrap().data_type;
+                    let len = dsl.data_type_len(data_type)?;
+                    script.extend_from_slice(hint_script(len).as_bytes());
+                }
+                _ => {}
+            }
+        }
+        Ok(Script::from_bytes(script))
+    }
+
+    pub fn compile_report(dsl: DSL) -> Result<(CompiledProgram, String)> {
+        let program = Self::compiler(dsl)?;
+        let report = program.report.to_string();
+        Ok((program, report))
+    }
+}
+
+fn set_variant_outputs(dsl: &mut DSL, variants: &[Vec<usize>]) -> Result<Vec<Vec<usize>>> {
+    // the outputs of all the variants, each once, and the positions of those of each variant
+    dsl.output = vec![];
+    let mut positions = vec![];
+    for output in variants.iter() {
+        let mut variant_positions = vec![];
+        for &idx in output.iter() {
+            let position = match dsl.output.iter().position(|&i| i == idx) {
+                Some(position) => position,
+                None => {
+                    let data_type = dsl
+                        .memory
+                        .get(&idx)
+                        .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+                        .data_type
+                        .clone();
+                    dsl.set_program_output(data_type, idx)?;
+                    dsl.output.len() - 1
+                }
+            };
+            variant_positions.push(position);
+        }
+        positions.push(variant_positions);
+    }
+    Ok(positions)
+}
+
+fn timelock_script(trace_entry: &TraceEntry) -> Script {
+    // the value is left on the stack by the check, so it is removed by OP_VERIFY, as Miniscript's
+    // `v:older` and `v:after` do, except for zero, which OP_VERIFY would reject and is dropped
+    match trace_entry {
+        TraceEntry::RelativeTimelock(sequence) => script! {
+            { *sequence as i64 } OP_CHECKSEQUENCEVERIFY
+            if *sequence == 0 { OP_DROP } else { OP_VERIFY }
+        },
+        TraceEntry::AbsoluteTimelock(height_or_time) => script! {
+            { *height_or_time as i64 } OP_CHECKLOCKTIMEVERIFY
+            if *height_or_time == 0 { OP_DROP } else { OP_VERIFY }
+        },
+        _ => Script::new(),
+    }
+}
+
+fn prologue_len(prologue_script: &Script) -> Result<usize> {
+    // a prologue only pushes constants, so that the number of elements is known
+    let mut len = 0;
+    for instruction in prologue_script.instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(_)) => len += 1,
+            Ok(Instruction::Op(opcode))
+                if matches!(opcode.classify(ClassifyContext::TapScript), Class::PushNum(_)) =>
+            {
+                len += 1
+            }
+            _ => return Err(Error::msg("A prologue must only push constants")),
+        }
+    }
+    Ok(len)
+}
+
+fn generate_script(
+    function_metadata: &AcceptableFunctionMetadata,
+    ref_positions: &[usize],
+    options: &Options,
+) -> Result<Script> {
+    match function_metadata {
+        AcceptableFunctionMetadata::FunctionWithoutOptions(v) => (v.script_generator)(ref_positions),
+        AcceptableFunctionMetadata::FunctionWithOptions(v) => {
+            (v.script_generator)(ref_positions, &options.with_defaults(&v.default_options))
+        }
+    }
+}
+
+fn push_constant(
+    dsl: &DSL,
+    idx: usize,
+    stack: &mut Stack,
+    script: &mut Vec<u8>,
+    report: &mut CompileReport,
+) -> Result<()> {
+    let entry = dsl.memory.get(&idx).unwrap();
+    let data_type_metadata = dsl.data_type_registry.map.get(&entry.data_type).unwrap();
+    stack.push_to_stack(idx, data_type_metadata.element_type.len())?;
+    report.max_stack_depth = report.max_stack_depth.max(stack.get_num_elements_in_stack()?);
+
+    script.extend_from_slice(constant_script(dsl, idx)?.as_bytes());
+    Ok(())
+}
+
+fn constant_script(dsl: &DSL, idx: usize) -> Result<Script> {
+    let entry = dsl.memory.get(&idx).unwrap();
+    let data_type_metadata = dsl.data_type_registry.map.get(&entry.data_type).unwrap();
+    Ok(match (data_type_metadata.encoder, data_type_metadata.num_encoding) {
+        (Some(encoder), _) => encoder(&entry.data),
+        (None, NumEncoding::Minimal) => script! { { entry } },
+        (None, NumEncoding::FixedWidth(_)) => {
+            let nums = entry.data.encode_nums(data_type_metadata.num_encoding)?;
+            script! {
+                for bytes in nums {
+                    { bytes }
+                }
+            }
+        }
+    })
+}
+
+fn hint_script(len: usize) -> Script {
+    // the hints are at the bottom of the stack, below the inputs, the first one the deepest
+    script! {
+        for _ in 0..len {
+            OP_DEPTH OP_1SUB OP_ROLL
+        }
+    }
+}
+
+fn restore_spilled(
+    stack: &mut Stack,
+    spilled: &mut Vec<(usize, usize)>,
+    script: &mut Vec<u8>,
+) -> Result<()> {
+    let (idx, len) = spilled.pop().unwrap();
+    for _ in 0..len {
+        script.push(OP_FROMALTSTACK.to_u8());
+    }
+    stack.restore_to_stack(idx, len)
+}
+
+fn roll_script(distance: usize, len: usize, merge_rolls: bool, report: &mut CompileReport) -> Script {
+    if distance == len - 1 {
+        script! {} // do nothing, it is already on the top of the stack
+    } else {
+        if distance == 1 {
+            script! {
+                for _ in 0..len {
+                    OP_SWAP
+                }
+            }
+        } else if distance == 2 {
+            script! {
+                for _ in 0..len {
+                    OP_ROT
+                }
+            }
+        } else if merge_rolls && (distance == 3 || distance == 5) && len >= 2 {
+            // every element rolls from the same distance, so two consecutive rolls
+            // from distance 3 (or 5) are exactly OP_2SWAP (or OP_2ROT)
+            report.op_roll_count += len % 2;
+            script! {
+                for _ in 0..len / 2 {
+                    if distance == 3 {
+                        OP_2SWAP
+                    } else {
+                        OP_2ROT
+                    }
+                }
+                if len % 2 == 1 {
+                    { distance } OP_ROLL
                 }
             }
         } else {
+            report.op_roll_count += len;
             script! {
                 for _ in 0..len {
                     { distance } OP_ROLL
@@ -346,7 +1328,44 @@ fn roll_script(distance: usize, len: usize) -> Script {
     }
 }
 
-fn pick_script(distance: usize, len: usize) -> Script {
+fn altstack_effect(script: &[u8]) -> Option<isize> {
+    // the net change in the depth, which also counts the elements taken from below the depth at the
+    // start, and is unknown if the altstack is used in a branch
+    let mut effect = 0isize;
+    let mut branches = 0usize;
+    for instruction in bitcoin::Script::from_bytes(script).instructions().flatten() {
+        match instruction {
+            Instruction::Op(all::OP_IF | all::OP_NOTIF) => branches += 1,
+            Instruction::Op(all::OP_ENDIF) => branches = branches.saturating_sub(1),
+            Instruction::Op(all::OP_TOALTSTACK) if branches == 0 => effect += 1,
+            Instruction::Op(all::OP_FROMALTSTACK) if branches == 0 => effect -= 1,
+            Instruction::Op(all::OP_TOALTSTACK | all::OP_FROMALTSTACK) => return None,
+            _ => {}
+        }
+    }
+    Some(effect)
+}
+
+pub(crate) fn encode_entry(dsl: &DSL, entry: &MemoryEntry) -> Result<MemoryEntry> {
+    let data_type_metadata = dsl.data_type_registry.map.get(&entry.data_type).unwrap();
+    if data_type_metadata.encoder.is_none() {
+        return Ok(entry.clone());
+    }
+
+    let mut elements = data_type_metadata.encode(&entry.data)?;
+    let data = if elements.len() == 1 {
+        Element::Str(elements.remove(0))
+    } else {
+        Element::ManyStr(elements)
+    };
+    Ok(MemoryEntry {
+        data_type: encoded_data_type(&entry.data_type),
+        data,
+        description: entry.description.clone(),
+    })
+}
+
+fn pick_script(distance: usize, len: usize, merge_rolls: bool, report: &mut CompileReport) -> Script {
     if distance == 0 {
         script! {
             for _ in 0..len {
@@ -354,12 +1373,36 @@ fn pick_script(distance: usize, len: usize) -> Script {
             }
         }
     } else if distance == 1 {
+        if merge_rolls && len >= 2 {
+            // two consecutive picks from distance 1 are exactly OP_2DUP
+            script! {
+                for _ in 0..len / 2 {
+                    OP_2DUP
+                }
+                if len % 2 == 1 {
+                    OP_OVER
+                }
+            }
+        } else {
+            script! {
+                for _ in 0..len {
+                    OP_OVER
+                }
+            }
+        }
+    } else if merge_rolls && distance == 3 && len >= 2 {
+        // two consecutive picks from distance 3 are exactly OP_2OVER
+        report.op_pick_count += len % 2;
         script! {
-            for _ in 0..len {
-                OP_OVER
+            for _ in 0..len / 2 {
+                OP_2OVER
+            }
+            if len % 2 == 1 {
+                3 OP_PICK
             }
         }
     } else {
+        report.op_pick_count += len;
         script! {
             for _ in 0..len {
                 { distance } OP_PICK
@@ -367,3 +1410,946 @@ fn pick_script(distance: usize, len: usize) -> Script {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{
+        find_segment_boundaries, pick_script, roll_script, CompileDecision, Compiler,
+        CompilerOptions, DecisionKind, OptLevel, VerifyLevel, SEGMENT_MARKER,
+    };
+    use crate::data_type::NumEncoding;
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::script::CompileReport;
+    use crate::dsl::{Element, ElementType, MemoryEntry, DSL};
+    use crate::functions::{FunctionMetadata, FunctionOutput};
+    use crate::{simulate, simulate_with_hints, test_program};
+    use crate::treepp::*;
+    use anyhow::Result;
+    use bitcoin_scriptexec::execute_script;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    #[test]
+    fn test_output_left_in_altstack() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[x, y]).unwrap()[0];
+        let d = dsl.execute("add", &[c, x]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        dsl.set_program_output("num", d).unwrap();
+
+        let program =
+            Compiler::compile_with(dsl, CompilerOptions::new().with_output_left_in_altstack())
+                .unwrap();
+
+        // the main stack is empty, and the altstack returns c and then d
+        let script = script! {
+            for entry in program.input.iter() {
+                { entry }
+            }
+            { program.script.clone() }
+            OP_DEPTH 0 OP_EQUALVERIFY
+            OP_FROMALTSTACK 3 OP_EQUALVERIFY
+            OP_FROMALTSTACK 4 OP_EQUALVERIFY
+            OP_TRUE
+        };
+        assert!(execute_script(script).success);
+    }
+
+    fn add_ref(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        let b = dsl.get_num(inputs[1])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a + b))],
+            new_hints: vec![],
+        })
+    }
+
+    fn add_ref_gadget(ref_positions: &[usize]) -> Result<Script> {
+        // the reference is below the copy of the first input
+        Ok(script! {
+            { ref_positions[0] + 1 } OP_PICK OP_ADD
+        })
+    }
+
+    #[test]
+    fn test_roll_at_last_use() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, a]).unwrap()[0];
+        dsl.set_program_output("num", d).unwrap();
+        dsl.set_program_output("num", c).unwrap();
+        dsl.set_program_output("num", d).unwrap();
+
+        // every value is moved at its last use, so nothing is left to drop at the end
+        let program = Compiler::compiler(dsl).unwrap();
+        assert!(!program.script.instructions().any(|instruction| matches!(
+            instruction,
+            Ok(bitcoin::script::Instruction::Op(
+                bitcoin::opcodes::all::OP_DROP | bitcoin::opcodes::all::OP_2DROP
+            ))
+        )));
+        simulate(&program).unwrap();
+
+        // the last use of x passes it both by value and by reference, so it cannot be rolled
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "add_ref",
+            FunctionMetadata::new(add_ref, add_ref_gadget, vec!["num", "&num"], vec!["num"]),
+        )
+        .unwrap();
+        let x = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let y = dsl.execute("add_ref", &[x, x]).unwrap()[0];
+        dsl.set_program_output("num", y).unwrap();
+        let program = Compiler::compiler(dsl).unwrap();
+        assert_eq!(program.output[0].data, Element::Num(6));
+        simulate(&program).unwrap();
+
+        // the embedded script uses x after the function call, so the call must not roll x
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(4)).unwrap();
+        let z = dsl.execute("add", &[x, y]).unwrap()[0];
+        let w = dsl
+            .embed_script(script! { OP_1ADD }, &[x], vec![("num", Element::Num(4))])
+            .unwrap()[0];
+        dsl.set_program_output("num", z).unwrap();
+        dsl.set_program_output("num", w).unwrap();
+        simulate(&Compiler::compiler(dsl).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_spill_when_over() {
+        let mut dsl = new_dsl_with_add();
+        let inputs = (1..=8)
+            .map(|v| dsl.alloc_input("num", Element::Num(v)).unwrap())
+            .collect::<Vec<usize>>();
+        let mut sum = dsl.execute("add", &[inputs[7], inputs[6]]).unwrap()[0];
+        for &input in inputs[..6].iter().rev() {
+            sum = dsl.execute("add", &[sum, input]).unwrap()[0];
+        }
+        dsl.set_program_output("num", sum).unwrap();
+
+        let baseline = Compiler::compiler(dsl.clone()).unwrap();
+        let program =
+            Compiler::compile_with(dsl, CompilerOptions::new().with_spill_when_over(4)).unwrap();
+
+        assert!(program.report.num_spills > 0);
+        assert!(program.report.max_stack_depth < baseline.report.max_stack_depth);
+        assert_eq!(program.output[0].data, Element::Num(36));
+        simulate(&program).unwrap();
+
+        // the values are moved to the altstack with the same rolls as the inputs, so none of them
+        // rolls from the top or from the distance of an OP_SWAP or OP_ROT
+        let trace_end = program.trace_byte_ranges.iter().map(|&(_, end)| end).max().unwrap();
+        let rolls = script! { 0 OP_ROLL 1 OP_ROLL 2 OP_ROLL };
+        for roll in rolls.as_bytes().chunks(2) {
+            assert!(!program.script.as_bytes()[..trace_end].windows(2).any(|w| w == roll));
+        }
+        let histogram = program.opcode_histogram();
+        assert_eq!(program.report.op_roll_count, histogram.get("OP_ROLL").copied().unwrap_or(0));
+    }
+
+    #[test]
+    fn test_pick_and_roll_counters() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, a]).unwrap()[0];
+        let e = dsl.execute("add", &[d, d]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+
+        // a is picked for c, and d is picked for its first use, while the last uses are rolls
+        let program = Compiler::compiler(dsl).unwrap();
+        assert_eq!(program.report.picked_values, 2);
+        assert_eq!(program.report.rolled_values, 4);
+        simulate(&program).unwrap();
+
+        // the same program counted per opcode, where most moves above are OP_OVER, OP_SWAP, OP_ROT
+        // or OP_DUP instead, and the add gadget has no OP_PICK or OP_ROLL of its own
+        let histogram = program.opcode_histogram();
+        assert_eq!(program.report.op_pick_count, histogram.get("OP_PICK").copied().unwrap_or(0));
+        assert_eq!(program.report.op_roll_count, histogram.get("OP_ROLL").copied().unwrap_or(0));
+    }
+
+    #[test]
+    fn test_fixed_width_constant() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type_with_num_encoding("u32", ElementType::Num, NumEncoding::FixedWidth(4))
+            .unwrap();
+        let a = dsl.alloc_constant("u32", Element::Num(1)).unwrap();
+        dsl.alloc_constant("u32", Element::Num(-2)).unwrap();
+        dsl.set_program_output("u32", a).unwrap();
+
+        let program = Compiler::compiler(dsl.clone()).unwrap();
+        assert!(program
+            .script
+            .as_bytes()
+            .starts_with(&[0x04, 0x01, 0x00, 0x00, 0x00, 0x04, 0x02, 0x00, 0x00, 0x80]));
+
+        dsl.add_data_type_with_num_encoding("u16", ElementType::Num, NumEncoding::FixedWidth(2))
+            .unwrap();
+        assert!(dsl.alloc_constant("u16", Element::Num(0x7fff)).is_ok());
+        assert!(dsl.alloc_constant("u16", Element::Num(0x8000)).is_err());
+        assert!(dsl
+            .add_data_type_with_num_encoding("bytes", ElementType::Str, NumEncoding::FixedWidth(4))
+            .is_err());
+    }
+
+    #[test]
+    fn test_merged_three_element_roll() {
+        let mut report = CompileReport::default();
+        let merged = roll_script(5, 3, true, &mut report);
+        assert_eq!(merged, script! { OP_2ROT 5 OP_ROLL });
+        assert_eq!(report.op_roll_count, 1);
+        assert!(merged.len() < roll_script(5, 3, false, &mut report).len());
+
+        // [1 2 3 4 5 6 7] => [1 5 6 7 2 3 4]
+        let script = script! {
+            1 2 3 4 5 6 7
+            { merged }
+            4 OP_EQUALVERIFY 3 OP_EQUALVERIFY 2 OP_EQUALVERIFY
+            7 OP_EQUALVERIFY 6 OP_EQUALVERIFY 5 OP_EQUALVERIFY
+            1 OP_EQUALVERIFY
+            OP_TRUE
+        };
+        assert!(execute_script(script).success);
+    }
+
+    #[test]
+    fn test_merged_three_element_pick() {
+        let mut report = CompileReport::default();
+        let merged = pick_script(3, 3, true, &mut report);
+        assert_eq!(merged, script! { OP_2OVER 3 OP_PICK });
+        assert_eq!(report.op_pick_count, 1);
+        assert!(merged.len() < pick_script(3, 3, false, &mut report).len());
+
+        // [1 2 3 4] => [1 2 3 4 1 2 3]
+        let script = script! {
+            1 2 3 4
+            { merged }
+            3 OP_EQUALVERIFY 2 OP_EQUALVERIFY 1 OP_EQUALVERIFY
+            4 OP_EQUALVERIFY 3 OP_EQUALVERIFY 2 OP_EQUALVERIFY
+            1 OP_EQUALVERIFY
+            OP_TRUE
+        };
+        assert!(execute_script(script).success);
+    }
+
+    fn add_sub(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        let b = dsl.get_num(inputs[1])?;
+        let c = dsl.get_num(inputs[2])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a - b + c))],
+            new_hints: vec![],
+        })
+    }
+
+    fn add_sub_gadget(ref_positions: &[usize]) -> Result<Script> {
+        // stack: a c, with b referenced below them
+        Ok(script! {
+            { ref_positions[0] + 2 } OP_PICK
+            OP_SUB OP_ADD
+        })
+    }
+
+    #[test]
+    fn test_interleaved_ref_input() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "add_sub",
+            FunctionMetadata::new(
+                add_sub,
+                add_sub_gadget,
+                vec!["num", "&num", "num"],
+                vec!["num"],
+            ),
+        )
+        .unwrap();
+
+        let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let c = dsl.alloc_input("num", Element::Num(5)).unwrap();
+        let d = dsl.alloc_input("num", Element::Num(7)).unwrap();
+
+        // b is referenced while both a, below it, and c, above it, are rolled
+        let e = dsl.execute("add_sub", &[a, b, c]).unwrap()[0];
+        let f = dsl.execute("add", &[e, d]).unwrap()[0];
+        let g = dsl.execute("add", &[f, b]).unwrap()[0];
+        dsl.set_program_output("num", g).unwrap();
+
+        test_program(dsl, script! { 22 }).unwrap();
+    }
+
+    fn wrapping_add(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        // the script computes the sum without wrapping, so the two disagree on overflow
+        let a = dsl.get_num(inputs[0])?;
+        let b = dsl.get_num(inputs[1])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a.wrapping_add(b)))],
+            new_hints: vec![],
+        })
+    }
+
+    fn wrapping_add_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! { OP_ADD })
+    }
+
+    fn new_dsl_with_wrapping_add(a_val: i32, b_val: i32) -> DSL {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "wrapping_add",
+            FunctionMetadata {
+                range_checked_outputs: vec![0],
+                ..FunctionMetadata::new(
+                    wrapping_add,
+                    wrapping_add_gadget,
+                    vec!["num", "num"],
+                    vec!["num"],
+                )
+            },
+        )
+        .unwrap();
+
+        let a = dsl.alloc_input("num", Element::Num(a_val)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(b_val)).unwrap();
+        let c = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.execute("wrapping_add", &[a, b]).unwrap();
+        dsl.set_program_output("num", c).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_num_range_check() {
+        let options = CompilerOptions::new().with_num_range_check();
+
+        let program = Compiler::compile_with(new_dsl_with_wrapping_add(100, 200), options.clone()).unwrap();
+        simulate(&program).unwrap();
+
+        // the 5-byte sum is dropped unused, which only fails with the check
+        let program = Compiler::compiler(new_dsl_with_wrapping_add(i32::MAX, 1)).unwrap();
+        simulate(&program).unwrap();
+
+        let program = Compiler::compile_with(new_dsl_with_wrapping_add(i32::MAX, 1), options).unwrap();
+        assert!(simulate(&program).is_err());
+    }
+
+    #[test]
+    fn test_empty_program() {
+        let program = Compiler::compiler(DSL::new()).unwrap();
+        assert!(program.script.is_empty());
+        assert!(program.input.is_empty());
+        assert!(program.hint.is_empty());
+        assert!(program.output.is_empty());
+    }
+
+    #[test]
+    fn test_inputs_as_outputs() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        dsl.set_program_output("num", b).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+
+        test_program(dsl, script! { 2 1 }).unwrap();
+    }
+
+    fn encode_twos_complement(data: &Element) -> Script {
+        match data {
+            Element::Num(v) => script! { { v.to_le_bytes().to_vec() } },
+            _ => unreachable!(),
+        }
+    }
+
+    fn decode_twos_complement(elements: &[Vec<u8>]) -> Result<Element> {
+        let bytes: [u8; 4] = elements[0]
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::Error::msg("The value must have 4 bytes"))?;
+        Ok(Element::Num(i32::from_le_bytes(bytes)))
+    }
+
+    #[test]
+    fn test_custom_encoding() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type_with_encoding(
+            "i32",
+            ElementType::Num,
+            encode_twos_complement,
+            decode_twos_complement,
+        )
+        .unwrap();
+
+        let a = dsl.alloc_input("i32", Element::Num(-2)).unwrap();
+        let b = dsl.alloc_constant("i32", Element::Num(1000)).unwrap();
+        dsl.set_program_output("i32", b).unwrap();
+        dsl.set_program_output("i32", a).unwrap();
+
+        let program = Compiler::compiler(dsl.clone()).unwrap();
+        assert_eq!(program.input[0].data, Element::Str(vec![0xfe, 0xff, 0xff, 0xff]));
+        assert_eq!(program.input[0].data_type, "i32#encoded");
+        assert!(program.script.as_bytes().starts_with(&[0x04, 0xe8, 0x03, 0x00, 0x00]));
+        simulate(&program).unwrap();
+
+        let witness = program.output[1].data.to_witness();
+        assert_eq!(dsl.decode("i32", &witness).unwrap(), Element::Num(-2));
+    }
+
+    #[test]
+    fn test_final_altstack_depth() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("pair", ElementType::ManyNum(2)).unwrap();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let p = dsl.alloc_input("pair", Element::ManyNum(vec![3, 4])).unwrap();
+        let z = dsl.execute("add", &[x, y]).unwrap()[0];
+        dsl.set_program_output("num", z).unwrap();
+        dsl.set_program_output("pair", p).unwrap();
+        dsl.set_program_output("num", x).unwrap();
+
+        let program = Compiler::compiler(dsl.clone()).unwrap();
+        assert_eq!(program.report.final_altstack_depth, 4);
+        simulate(&program).unwrap();
+
+        let options = CompilerOptions::new().with_output_left_in_altstack();
+        let program = Compiler::compile_checked(dsl.clone(), options).unwrap();
+        assert_eq!(program.report.final_altstack_depth, 4);
+
+        // a branch that is never taken does not count towards the depth
+        dsl.embed_script(script! { OP_0 OP_IF 5 OP_TOALTSTACK OP_ENDIF }, &[], vec![]).unwrap();
+        let program = Compiler::compiler(dsl).unwrap();
+        assert_eq!(program.report.final_altstack_depth, 4);
+        simulate(&program).unwrap();
+    }
+
+    fn build_program_with_dead_code() -> DSL {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(3)).unwrap();
+        let d = dsl.alloc_constant("num", Element::Num(4)).unwrap();
+        let e = dsl.execute("add", &[c, d]).unwrap()[0];
+
+        let mut terms = vec![];
+        for _ in 0..3 {
+            terms.push(dsl.execute("add", &[x, y]).unwrap()[0]);
+        }
+        let mut sum = e;
+        for &term in terms.iter() {
+            sum = dsl.execute("add", &[sum, term]).unwrap()[0];
+        }
+        dsl.execute("add", &[sum, x]).unwrap();
+        dsl.set_program_output("num", sum).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_opt_level() {
+        let compile = |opt_level| {
+            let options = CompilerOptions::new().with_opt_level(opt_level);
+            let program = Compiler::compile_with(build_program_with_dead_code(), options).unwrap();
+            simulate(&program).unwrap();
+            assert_eq!(program.output[0].data, Element::Num(16));
+            program
+        };
+
+        // the script of the compiler before the levels, which picks the inputs used again and
+        // computes the dead sum as well
+        let none = compile(OptLevel::None);
+        let expected = script! {
+            3 4 OP_SWAP OP_SWAP OP_ADD
+            2 OP_PICK 2 OP_PICK OP_ADD
+            3 OP_PICK 3 OP_PICK OP_ADD
+            4 OP_PICK 4 OP_ROLL OP_ADD
+            3 OP_ROLL 3 OP_ROLL OP_ADD
+            OP_ROT OP_ADD
+            OP_SWAP OP_ADD
+            OP_DUP OP_ROT OP_ADD
+            1 OP_ROLL OP_TOALTSTACK OP_DROP OP_FROMALTSTACK
+        };
+        assert_eq!(none.script.as_bytes(), expected.as_bytes());
+
+        let size = compile(OptLevel::Size);
+        assert!(size.script.len() < none.script.len());
+
+        let balanced = compile(OptLevel::Balanced);
+        assert!(balanced.script.len() < size.script.len());
+        assert_eq!(balanced.report.num_constants, 1);
+
+        // the folded constant is added to the first term as soon as it is computed
+        let aggressive = compile(OptLevel::Aggressive);
+        let expected = script! {
+            7
+            2 OP_PICK 2 OP_PICK OP_ADD
+            OP_SWAP OP_SWAP OP_ADD
+            2 OP_PICK 2 OP_PICK OP_ADD
+            3 OP_ROLL 3 OP_ROLL OP_ADD
+            OP_ROT OP_ROT OP_ADD
+            OP_SWAP OP_ADD
+            0 OP_ROLL OP_TOALTSTACK OP_FROMALTSTACK
+        };
+        assert_eq!(aggressive.script.as_bytes(), expected.as_bytes());
+        assert!(aggressive.report.max_stack_depth <= balanced.report.max_stack_depth);
+    }
+
+    #[test]
+    fn test_witness_len_check() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let h = dsl.alloc_hint("num", Element::Num(3)).unwrap();
+        let z = dsl.execute("add", &[x, y]).unwrap()[0];
+        let w = dsl.execute("add", &[z, h]).unwrap()[0];
+        dsl.set_program_output("num", w).unwrap();
+
+        let program =
+            Compiler::compile_with(dsl, CompilerOptions::new().with_witness_len_check()).unwrap();
+        assert!(program.script.as_bytes().starts_with(script! { OP_DEPTH 3 OP_EQUALVERIFY }.as_bytes()));
+        simulate(&program).unwrap();
+
+        // both a missing and an extra witness item abort at the check
+        assert!(simulate_with_hints(&program, &[]).is_err());
+        let mut hints = program.hint.clone();
+        hints.push(MemoryEntry::new("num", Element::Num(4)));
+        assert!(simulate_with_hints(&program, &hints).is_err());
+    }
+
+    #[test]
+    fn test_min_witness_len_check() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let h1 = dsl.alloc_hint("num", Element::Num(2)).unwrap();
+        let h2 = dsl.alloc_hint("num", Element::Num(3)).unwrap();
+        let y = dsl.execute("add", &[x, h1]).unwrap()[0];
+        let z = dsl.execute("add", &[y, h2]).unwrap()[0];
+        dsl.set_program_output("num", z).unwrap();
+
+        let program =
+            Compiler::compile_with(dsl, CompilerOptions::new().with_min_witness_len_check()).unwrap();
+        assert!(program.script.as_bytes().starts_with(script! { OP_DEPTH 3 OP_GREATERTHANOREQUAL OP_VERIFY }.as_bytes()));
+        simulate(&program).unwrap();
+
+        // a witness that lacks a hint fails on the check
+        assert!(simulate_with_hints(&program, &program.hint[..1]).is_err());
+
+        // an extra element is accepted by the check, but is left on the stack
+        let mut with_extra = vec![MemoryEntry::new("num", Element::Num(9))];
+        with_extra.extend(program.hint.iter().cloned());
+        assert!(simulate_with_hints(&program, &with_extra).is_err());
+    }
+
+    #[test]
+    fn test_concat_output() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("str", ElementType::Str).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(100)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(200)).unwrap();
+        let s = dsl.alloc_input("str", Element::Str(b"xyz".to_vec())).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        dsl.set_program_output("str", s).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+
+        assert!(Compiler::compile_with(dsl.clone(), CompilerOptions::new().with_concat_output()).is_err());
+
+        let program = Compiler::compile_with(
+            dsl.clone(),
+            CompilerOptions::new().with_concat_output().with_op_cat(),
+        )
+        .unwrap();
+        let separate = Compiler::compiler(dsl).unwrap();
+        let joined = separate
+            .output
+            .iter()
+            .flat_map(|entry| entry.data.to_witness())
+            .collect::<Vec<Vec<u8>>>()
+            .concat();
+        assert_eq!(joined, vec![0x2c, 0x01, b'x', b'y', b'z', 100]);
+        assert_eq!(program.output.len(), 1);
+        assert_eq!(program.output[0].data, Element::Str(joined));
+        simulate(&program).unwrap();
+    }
+
+    #[test]
+    fn test_compile_variants() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, b]).unwrap()[0];
+
+        let variants = vec![vec![c], vec![d], vec![d, a], vec![]];
+        let programs = Compiler::compile_variants(dsl.clone(), &variants, CompilerOptions::new()).unwrap();
+        assert_eq!(programs.len(), variants.len());
+
+        // the same outputs as compiling each variant on its own, in the order of the variants
+        for (program, variant) in programs.iter().zip(variants.iter()) {
+            let mut dsl = dsl.clone();
+            for &idx in variant.iter() {
+                dsl.set_program_output("num", idx).unwrap();
+            }
+            let expected = Compiler::compiler(dsl).unwrap();
+            assert_eq!(program.output.len(), variant.len());
+            assert_eq!(program.output_idxs, expected.output_idxs);
+            for (output, expected) in program.output.iter().zip(expected.output.iter()) {
+                assert_eq!(output.data, expected.data);
+            }
+            simulate(program).unwrap();
+        }
+
+        // the trace is compiled once, so the scripts only differ after it
+        let trace_len = programs[0].trace_byte_ranges.iter().map(|&(_, end)| end).max().unwrap();
+        for program in programs.iter() {
+            assert_eq!(program.trace_byte_ranges, programs[0].trace_byte_ranges);
+            assert_eq!(program.script.as_bytes()[..trace_len], programs[0].script.as_bytes()[..trace_len]);
+        }
+
+        assert!(Compiler::compile_variants(dsl, &[vec![100]], CompilerOptions::new()).is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_compile_variants_parallel() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, b]).unwrap()[0];
+
+        let variants = vec![vec![c], vec![d], vec![d, a], vec![], vec![a, b, c, d]];
+        let compiler_options = CompilerOptions::new().with_verify_level(VerifyLevel::Full);
+        let programs = Compiler::compile_variants(dsl.clone(), &variants, compiler_options.clone()).unwrap();
+
+        // the same as completing the variants one after another
+        let mut sequential_dsl = dsl;
+        let positions = crate::compiler::set_variant_outputs(&mut sequential_dsl, &variants).unwrap();
+        let compiler_options = compiler_options.with_checks_of_verify_level();
+        let prefix = Compiler::compile_prefix(sequential_dsl, &compiler_options).unwrap();
+        assert_eq!(programs.len(), positions.len());
+        for (program, positions) in programs.iter().zip(positions.iter()) {
+            let output = positions.iter().map(|&i| prefix.dsl.output[i]).collect();
+            let expected =
+                Compiler::compile_outputs(prefix.duplicate().unwrap(), output, compiler_options.clone()).unwrap();
+            assert_eq!(program.script, expected.script);
+            assert_eq!(program.output_idxs, expected.output_idxs);
+            assert_eq!(program.report.max_stack_depth, expected.report.max_stack_depth);
+        }
+    }
+
+    #[test]
+    fn test_decision_log() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, a]).unwrap()[0];
+        dsl.set_program_output("num", d).unwrap();
+
+        assert!(Compiler::compiler(dsl.clone()).unwrap().decisions.is_empty());
+
+        let program = Compiler::compile_with(dsl, CompilerOptions::new().with_decision_log()).unwrap();
+        let decision = |step, idx, kind, position, distance| CompileDecision {
+            step,
+            idx,
+            kind,
+            position,
+            distance,
+            len: 1,
+        };
+        // stack: a b, then a c, where c is rolled first, then d
+        assert_eq!(
+            program.decisions,
+            vec![
+                decision(0, a, DecisionKind::Pick, 1, 1),
+                decision(0, b, DecisionKind::Roll, 0, 1),
+                decision(1, c, DecisionKind::Roll, 0, 0),
+                decision(1, a, DecisionKind::Roll, 0, 1),
+                decision(2, d, DecisionKind::Roll, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lazy_constants() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(100)).unwrap();
+        dsl.alloc_constant("num", Element::Num(200)).unwrap();
+        let d = dsl.alloc_constant("num", Element::Num(300)).unwrap();
+        let y = dsl.execute("add", &[x, x]).unwrap()[0];
+        let z = dsl.execute("add", &[y, c]).unwrap()[0];
+        dsl.set_program_output("num", z).unwrap();
+        dsl.set_program_output("num", d).unwrap();
+
+        let eager = Compiler::compiler(dsl.clone()).unwrap();
+        let lazy = Compiler::compile_with(dsl, CompilerOptions::new().with_lazy_constants()).unwrap();
+        simulate(&lazy).unwrap();
+
+        // the unused constant 200 is not pushed, and 100 is pushed after x + x
+        let push_200 = script! { 200 }.to_bytes();
+        assert!(eager.script.as_bytes().windows(push_200.len()).any(|w| w == push_200.as_slice()));
+        assert!(!lazy.script.as_bytes().windows(push_200.len()).any(|w| w == push_200.as_slice()));
+        assert!(lazy.script.len() < eager.script.len());
+        assert!(lazy.script.as_bytes().starts_with(script! { OP_DUP OP_ADD 100 }.as_bytes()));
+    }
+
+    static NUM_GENERATED: AtomicI32 = AtomicI32::new(0);
+
+    fn counting_gadget(_: &[usize]) -> Result<Script> {
+        // the script depends on how many times the generator has run
+        let n = NUM_GENERATED.fetch_add(1, Ordering::Relaxed);
+        Ok(script! {
+            { n } OP_DROP
+            OP_ADD
+        })
+    }
+
+    #[test]
+    fn test_determinism_check() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "counting_add",
+            FunctionMetadata::new(wrapping_add, counting_gadget, vec!["num", "num"], vec!["num"]),
+        )
+        .unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+        dsl.set_program_output("num", b).unwrap();
+        let program =
+            Compiler::compile_with(dsl.clone(), CompilerOptions::new().with_determinism_check())
+                .unwrap();
+        simulate(&program).unwrap();
+
+        let c = dsl.execute("counting_add", &[b, a]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        assert!(Compiler::compiler(dsl.clone()).is_ok());
+        let err = Compiler::compile_with(dsl, CompilerOptions::new().with_determinism_check())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("counting_add is not deterministic"));
+    }
+
+    fn lookup(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let i = dsl.get_num(inputs[0])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(10 * (i + 1)))],
+            new_hints: vec![],
+        })
+    }
+
+    fn lookup_prologue() -> Script {
+        script! { 10 20 30 40 }
+    }
+
+    fn lookup_gadget(ref_positions: &[usize]) -> Result<Script> {
+        // the first entry of the table is the deepest
+        Ok(script! {
+            { ref_positions[0] } OP_SWAP OP_SUB OP_PICK
+        })
+    }
+
+    #[test]
+    fn test_prologue() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "lookup",
+            FunctionMetadata {
+                prologue_script: Some(lookup_prologue),
+                ..FunctionMetadata::new(lookup, lookup_gadget, vec!["num"], vec!["num"])
+            },
+        )
+        .unwrap();
+
+        let a = dsl.alloc_input("num", Element::Num(0)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        for idx in [a, b, c] {
+            let res = dsl.execute("lookup", &[idx]).unwrap()[0];
+            dsl.set_program_output("num", res).unwrap();
+        }
+
+        let program = Compiler::compiler(dsl).unwrap();
+        let table = lookup_prologue().to_bytes();
+        let script = program.script.as_bytes();
+        let num_prologues = script.windows(table.len()).filter(|w| *w == table.as_slice()).count();
+        assert_eq!(num_prologues, 1);
+
+        let output = program.output.iter().map(|entry| entry.data.clone()).collect::<Vec<Element>>();
+        assert_eq!(output, vec![Element::Num(10), Element::Num(30), Element::Num(40)]);
+        simulate(&program).unwrap();
+    }
+
+    fn leaky_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {
+            OP_ADD OP_DUP OP_TOALTSTACK
+        })
+    }
+
+    #[test]
+    fn test_compile_checked() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "leaky_add",
+            FunctionMetadata::new(wrapping_add, leaky_gadget, vec!["num", "num"], vec!["num"]),
+        )
+        .unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+        // a balanced use of the altstack is accepted
+        dsl.embed_script(script! { OP_TOALTSTACK OP_FROMALTSTACK }, &[], vec![]).unwrap();
+        dsl.set_program_output("num", b).unwrap();
+        let program = Compiler::compile_checked(dsl.clone(), CompilerOptions::new()).unwrap();
+        simulate(&program).unwrap();
+
+        // the altstack used in a branch is not counted, and is left to the simulation
+        let mut with_branch = dsl.clone();
+        with_branch.embed_script(script! { OP_0 OP_IF 5 OP_TOALTSTACK OP_ENDIF }, &[], vec![]).unwrap();
+        let program = Compiler::compile_checked(with_branch, CompilerOptions::new()).unwrap();
+        simulate(&program).unwrap();
+
+        let c = dsl.execute("leaky_add", &[b, a]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        let options = CompilerOptions::new().with_verify_level(VerifyLevel::None);
+        assert!(Compiler::compile_with(dsl.clone(), options.clone()).is_ok());
+        let err = Compiler::compile_checked(dsl, options).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("The function leaky_add at the trace step 2 changes the depth of the altstack by 1"));
+    }
+
+    #[test]
+    fn test_verify_level() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "leaky_add",
+            FunctionMetadata::new(wrapping_add, leaky_gadget, vec!["num", "num"], vec!["num"]),
+        )
+        .unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("leaky_add", &[a, a]).unwrap()[0];
+        dsl.set_program_output("num", b).unwrap();
+
+        // the leaked element is caught by the altstack balance at the default level
+        let options = CompilerOptions::new().with_verify_level(VerifyLevel::None);
+        assert!(Compiler::compile_with(dsl.clone(), options).is_ok());
+        for verify_level in [VerifyLevel::Fast, VerifyLevel::Full] {
+            let options = CompilerOptions::new().with_verify_level(verify_level);
+            assert!(Compiler::compile_with(dsl.clone(), options).is_err());
+        }
+
+        // the budget is only checked by `Full`
+        let mut dsl = new_dsl_with_add();
+        let inputs = (0..1001)
+            .map(|i| dsl.alloc_input("num", Element::Num(i)).unwrap())
+            .collect::<Vec<usize>>();
+        dsl.set_program_output("num", inputs[1000]).unwrap();
+        let options = CompilerOptions::new().with_verify_level(VerifyLevel::Fast);
+        assert!(Compiler::compile_with(dsl.clone(), options).is_ok());
+        let options = CompilerOptions::new().with_verify_level(VerifyLevel::Full);
+        let err = Compiler::compile_with(dsl, options).err().unwrap();
+        assert!(err.to_string().contains("over the limit of 1000 elements"));
+
+        // the outputs left in the altstack are recovered for the self check
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+        dsl.set_program_output("num", b).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+        let options = CompilerOptions::new()
+            .with_verify_level(VerifyLevel::Full)
+            .with_output_left_in_altstack();
+        Compiler::compile_with(dsl, options).unwrap();
+    }
+
+    #[test]
+    fn test_segment_markers() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let mut sum = a;
+        for _ in 0..4 {
+            sum = dsl.execute("add", &[sum, a]).unwrap()[0];
+        }
+        dsl.set_program_output("num", sum).unwrap();
+
+        let plain = Compiler::compiler(dsl.clone()).unwrap();
+        assert!(find_segment_boundaries(&plain.script).is_empty());
+        assert!(plain.report.segment_boundaries.is_empty());
+
+        // every add but the last one ends a segment, which changes nothing in the script itself
+        let options = CompilerOptions::new().with_segment_size(1);
+        let segmented = Compiler::compile_with(dsl.clone(), options.clone()).unwrap();
+        assert_eq!(segmented.script, plain.script);
+        assert_eq!(segmented.report.segment_boundaries.len(), 3);
+        assert!(find_segment_boundaries(&segmented.script).is_empty());
+
+        let program = Compiler::compile_with(dsl, options.with_segment_markers()).unwrap();
+        simulate(&program).unwrap();
+        let boundaries = find_segment_boundaries(&program.script);
+        assert_eq!(boundaries, program.report.segment_boundaries);
+        assert_eq!(boundaries.len(), 3);
+        for &offset in boundaries.iter() {
+            assert_eq!(&program.script.as_bytes()[offset..offset + 4], SEGMENT_MARKER.as_slice());
+        }
+        assert_eq!(program.script.len(), plain.script.len() + 3 * SEGMENT_MARKER.len());
+
+        // the same bytes inside a push are not a marker, and a longer run of OP_NOPs counts per four
+        let script = script! { { SEGMENT_MARKER.to_vec() } OP_DROP OP_NOP OP_NOP OP_NOP };
+        assert!(find_segment_boundaries(&script).is_empty());
+        let script = script! { OP_DUP OP_NOP OP_NOP OP_NOP OP_NOP OP_NOP OP_DROP };
+        assert_eq!(find_segment_boundaries(&script), vec![1]);
+    }
+
+    #[test]
+    fn test_setup_script() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let h = dsl.alloc_hint("num", Element::Num(5)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(2)).unwrap();
+        let a = dsl.execute("add", &[x, h]).unwrap()[0];
+        let b = dsl.execute("add", &[a, c]).unwrap()[0];
+        dsl.set_program_output("num", b).unwrap();
+
+        let setup = Compiler::setup_script(dsl.clone()).unwrap();
+        assert_eq!(setup, script! { OP_DEPTH OP_1SUB OP_ROLL 2 });
+
+        // the setup brings the hint up from below the input and pushes the constant
+        let script = script! {
+            5 1
+            { setup.clone() }
+            2 OP_EQUALVERIFY
+            5 OP_EQUALVERIFY
+            1 OP_EQUAL
+        };
+        assert!(execute_script(script).success);
+
+        let program = Compiler::compiler(dsl).unwrap();
+        assert!(program.script.as_bytes().starts_with(setup.as_bytes()));
+        simulate(&program).unwrap();
+    }
+
+    #[test]
+    fn test_coverage_map() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+        let c = dsl
+            .embed_script(script! { OP_DUP OP_1ADD OP_ADD }, &[b], vec![("num", Element::Num(5))])
+            .unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+
+        assert!(Compiler::compiler(dsl.clone()).unwrap().coverage.is_empty());
+
+        // the pick of the input for the addition is not part of the script of `add`
+        let program = Compiler::compile_with(dsl, CompilerOptions::new().with_coverage_map()).unwrap();
+        let kinds = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<BTreeSet<String>>();
+        assert_eq!(program.coverage.len(), 2);
+        assert_eq!(program.coverage["add"], kinds(&["OP_ADD"]));
+        assert_eq!(program.coverage["an embedded script"], kinds(&["OP_DUP", "OP_1ADD", "OP_ADD"]));
+        simulate(&program).unwrap();
+    }
+}
@@ -1,18 +1,187 @@
-use crate::dsl::ElementType;
-use std::collections::HashMap;
+use crate::dsl::{Element, ElementType};
+use crate::treepp::Script;
+use anyhow::{Error, Result};
+use bitcoin_scriptexec::convert_to_witness;
+use indexmap::IndexMap;
 
+#[derive(Clone)]
 pub struct DataTypeRegistry {
-    pub map: HashMap<String, DataTypeMetadata>,
+    pub map: IndexMap<String, DataTypeMetadata>,
 }
 
 impl DataTypeRegistry {
     pub fn new() -> Self {
         Self {
-            map: HashMap::new(),
+            map: IndexMap::new(),
         }
     }
+
+    pub fn validate(&self) -> Result<()> {
+        // Function signatures refer to types by name, with `&` marking an input passed by reference
+        // and `any` accepting every type, so a registered name must not collide with either. The
+        // compiler also assumes that every value occupies at least one stack element.
+        for (name, metadata) in self.map.iter() {
+            if name == "any" || name.starts_with('&') || decoded_data_type(name).is_some() {
+                return Err(Error::msg(format!(
+                    "The data type name {} conflicts with the reference, any or encoded syntax",
+                    name
+                )));
+            }
+            if metadata.element_type.len() == 0 {
+                return Err(Error::msg(format!(
+                    "The data type {} does not occupy any stack element",
+                    name
+                )));
+            }
+        }
+
+        // Two plain types with the same layout can only be told apart by their names, so one being
+        // ref-only and the other not would let a value escape the by-reference restriction by being
+        // relabelled. A validator, encoder or decoder makes a type distinct.
+        let plain = |metadata: &DataTypeMetadata| {
+            metadata.validator.is_none() && metadata.encoder.is_none() && metadata.decoder.is_none()
+        };
+        for (i, (name, metadata)) in self.map.iter().enumerate() {
+            for (other_name, other) in self.map.iter().skip(i + 1) {
+                if plain(metadata)
+                    && plain(other)
+                    && metadata.element_type == other.element_type
+                    && metadata.num_encoding == other.num_encoding
+                    && metadata.ref_only != other.ref_only
+                {
+                    return Err(Error::msg(format!(
+                        "The data types {} and {} are structurally identical, but only one of them is ref-only",
+                        name, other_name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
+#[derive(Clone)]
 pub struct DataTypeMetadata {
     pub element_type: ElementType,
+    pub num_encoding: NumEncoding,
+    /// checks the invariants of the type beyond its element type, for every allocated or computed value
+    pub validator: Option<fn(&Element) -> Result<()>>,
+    /// Replace the plain pushes of the values of the type, which gadgets then find in this layout
+    /// on the stack. The decoder reads a value back from its stack elements.
+    pub encoder: Option<fn(&Element) -> Script>,
+    pub decoder: Option<fn(&[Vec<u8>]) -> Result<Element>>,
+    /// values that functions may only take by reference, such as tables that stay in place
+    pub ref_only: bool,
+}
+
+impl DataTypeMetadata {
+    pub fn encode(&self, data: &Element) -> Result<Vec<Vec<u8>>> {
+        let elements = match self.encoder {
+            Some(encoder) => convert_to_witness(encoder(data))
+                .map_err(|x| Error::msg(format!("The encoding cannot be parsed: {:?}", x)))?,
+            None if self.num_encoding == NumEncoding::Minimal => return Ok(data.to_witness()),
+            None => return data.encode_nums(self.num_encoding),
+        };
+        if elements.len() != self.element_type.len() {
+            return Err(Error::msg(
+                "The encoding does not have the number of elements of the data type",
+            ));
+        }
+        Ok(elements)
+    }
+}
+
+/// The data type given to a value of a type with a custom encoding once it is encoded, as in the
+/// inputs, hints and outputs of a compiled program, whose data is then the stack elements.
+pub fn encoded_data_type(name: &str) -> String {
+    format!("{}{}", name, ENCODED_SUFFIX)
+}
+
+/// The data type of an encoded value before its encoding, if it is encoded.
+pub fn decoded_data_type(name: &str) -> Option<&str> {
+    name.strip_suffix(ENCODED_SUFFIX)
+}
+
+const ENCODED_SUFFIX: &str = "#encoded";
+
+/// How constants of a numeric type are pushed. `FixedWidth` pads the sign-magnitude encoding with
+/// zeros to the given number of bytes, for gadgets that expect numbers of a specific width.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NumEncoding {
+    #[default]
+    Minimal,
+    FixedWidth(usize),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data_type::{encoded_data_type, DataTypeMetadata, DataTypeRegistry, NumEncoding};
+    use crate::dsl::{Element, ElementType};
+    use anyhow::Result;
+
+    #[test]
+    fn test_element_type_eq() {
+        assert_eq!(ElementType::Num, ElementType::Num);
+        assert_eq!(ElementType::ManyNum(3), ElementType::ManyNum(3));
+        assert_ne!(ElementType::ManyNum(3), ElementType::ManyNum(4));
+        assert_ne!(ElementType::Num, ElementType::Str);
+        assert_ne!(ElementType::ManyNum(2), ElementType::ManyStr(2));
+        assert_eq!(ElementType::ManyStr(2).clone(), ElementType::ManyStr(2));
+    }
+
+    #[test]
+    fn test_validate() {
+        let base = DataTypeMetadata {
+            element_type: ElementType::Num,
+            num_encoding: NumEncoding::Minimal,
+            validator: None,
+            encoder: None,
+            decoder: None,
+            ref_only: false,
+        };
+        let mut registry = DataTypeRegistry::new();
+        registry.map.insert("num".to_string(), base.clone());
+        registry.validate().unwrap();
+
+        let mut with_ref_name = registry.clone();
+        with_ref_name.map.insert("&num".to_string(), base.clone());
+        assert!(with_ref_name.validate().is_err());
+
+        let mut with_encoded_name = registry.clone();
+        with_encoded_name.map.insert(encoded_data_type("num"), base.clone());
+        assert!(with_encoded_name.validate().is_err());
+
+        let mut with_empty_type = registry.clone();
+        with_empty_type.map.insert(
+            "empty".to_string(),
+            DataTypeMetadata {
+                element_type: ElementType::ManyNum(0),
+                ..base.clone()
+            },
+        );
+        assert!(with_empty_type.validate().is_err());
+
+        let plain = |ref_only: bool| DataTypeMetadata {
+            element_type: ElementType::ManyNum(3),
+            ref_only,
+            ..base.clone()
+        };
+
+        let mut with_same_ref_only = registry.clone();
+        with_same_ref_only.map.insert("a".to_string(), plain(true));
+        with_same_ref_only.map.insert("b".to_string(), plain(true));
+        with_same_ref_only.validate().unwrap();
+
+        let mut with_inconsistent_ref_only = registry.clone();
+        with_inconsistent_ref_only.map.insert("felt".to_string(), plain(false));
+        with_inconsistent_ref_only.map.insert("modulus".to_string(), plain(true));
+        assert!(with_inconsistent_ref_only.validate().is_err());
+
+        // a validator tells the ref-only type apart
+        fn accept(_: &Element) -> Result<()> {
+            Ok(())
+        }
+        with_inconsistent_ref_only.map.get_mut("modulus").unwrap().validator = Some(accept);
+        with_inconsistent_ref_only.validate().unwrap();
+    }
 }
@@ -1,10 +1,13 @@
-use crate::data_type::{DataTypeMetadata, DataTypeRegistry};
-use crate::functions::{AcceptableFunctionMetadata, FunctionRegistry};
+use crate::data_type::{DataTypeMetadata, DataTypeRegistry, NumEncoding};
+use crate::functions::{AcceptableFunctionMetadata, FunctionEffect, FunctionRegistry};
 use crate::treepp::pushable::{Builder, Pushable};
+use crate::treepp::Script;
 use anyhow::{Error, Result};
 use indexmap::IndexMap;
-use crate::options::Options;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::options::{Options, OptionsEntry};
 
+#[derive(Clone)]
 pub struct DSL {
     pub data_type_registry: DataTypeRegistry,
     pub function_registry: FunctionRegistry,
@@ -13,7 +16,39 @@ pub struct DSL {
     pub trace: Vec<TraceEntry>,
     pub num_inputs: Option<usize>,
     pub hint: Vec<MemoryEntry>,
+    pub hint_origins: Vec<usize>,
     pub output: Vec<usize>,
+    /// annotations of the trace entries by their index, which the compiler leaves alone
+    pub annotations: HashMap<usize, BTreeMap<String, String>>,
+    /// the trace entries that the optimization passes must leave as they are
+    pub pinned: BTreeSet<usize>,
+    /// the constants allocated by `intern_constant`, by their data type and data
+    pub interned: HashMap<(String, Element), usize>,
+    /// the maximum number of stack elements of a single value, if limited
+    pub max_element_width: Option<usize>,
+}
+
+/// A constant allocated once by `DSL::intern_constant`, which can be passed to any number of
+/// function calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstHandle(usize);
+
+impl ConstHandle {
+    pub fn idx(self) -> usize {
+        self.0
+    }
+}
+
+/// A function call in the trace that takes a value of another data type than the one declared for
+/// the input, which `structurally_equal` tells if the two types have the same element type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeConfusion {
+    pub step: usize,
+    pub function_name: String,
+    pub input: usize,
+    pub expected: String,
+    pub actual: String,
+    pub structurally_equal: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -29,7 +64,7 @@ impl Pushable for &MemoryEntry {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Element {
     Num(i32),
     ManyNum(Vec<i32>),
@@ -37,6 +72,7 @@ pub enum Element {
     ManyStr(Vec<Vec<u8>>),
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ElementType {
     Num,
     ManyNum(usize),
@@ -86,12 +122,30 @@ impl Pushable for &Element {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TraceEntry {
     FunctionCall(String, Vec<usize>),
     FunctionCallWithOptions(String, Vec<usize>, Options),
     AllocatedConstant(usize),
     AllocatedHint(usize),
+    EmbeddedScript(Script, Vec<usize>, Vec<String>),
+    /// the sequence value checked by OP_CSV, in the encoding of BIP 68
+    RelativeTimelock(u32),
+    AbsoluteTimelock(u32),
+}
+
+impl TraceEntry {
+    pub fn input_idxs(&self) -> &[usize] {
+        match self {
+            TraceEntry::FunctionCall(_, inputs)
+            | TraceEntry::FunctionCallWithOptions(_, inputs, _)
+            | TraceEntry::EmbeddedScript(_, inputs, _) => inputs,
+            TraceEntry::AllocatedConstant(_)
+            | TraceEntry::AllocatedHint(_)
+            | TraceEntry::RelativeTimelock(_)
+            | TraceEntry::AbsoluteTimelock(_) => &[],
+        }
+    }
 }
 
 impl Element {
@@ -103,6 +157,67 @@ impl Element {
             Element::ManyStr(v) => v.len(),
         }
     }
+
+    pub fn to_witness(&self) -> Vec<Vec<u8>> {
+        match self {
+            Element::Num(v) => vec![num_to_bytes(*v)],
+            Element::ManyNum(v) => v.iter().map(|&vv| num_to_bytes(vv)).collect(),
+            Element::Str(v) => vec![v.clone()],
+            Element::ManyStr(v) => v.clone(),
+        }
+    }
+}
+
+impl Element {
+    pub(crate) fn encode_nums(&self, num_encoding: NumEncoding) -> Result<Vec<Vec<u8>>> {
+        let nums = match self {
+            Element::Num(v) => vec![*v],
+            Element::ManyNum(v) => v.clone(),
+            _ => vec![],
+        };
+        nums.into_iter()
+            .map(|v| match num_encoding {
+                NumEncoding::Minimal => Ok(num_to_bytes(v)),
+                NumEncoding::FixedWidth(width) => num_to_fixed_width_bytes(v, width),
+            })
+            .collect()
+    }
+}
+
+fn num_to_fixed_width_bytes(v: i32, width: usize) -> Result<Vec<u8>> {
+    if num_to_bytes(v).len() > width {
+        return Err(Error::msg(format!(
+            "The number {} does not fit in {} bytes",
+            v, width
+        )));
+    }
+    let mut res = vec![0u8; width];
+    for (i, b) in v.unsigned_abs().to_le_bytes().iter().enumerate().take(width) {
+        res[i] = *b;
+    }
+    if v < 0 {
+        res[width - 1] |= 0x80;
+    }
+    Ok(res)
+}
+
+pub(crate) fn num_to_bytes(v: i32) -> Vec<u8> {
+    // the minimal script number encoding, as pushed by `OP_0`, `OP_1NEGATE`, `OP_1`-`OP_16`, or data pushes
+    // zero is the empty vector pushed by `OP_0`, never the negative zero `0x80`
+    let mut res = vec![];
+    let mut abs = (v as i64).unsigned_abs();
+    while abs > 0 {
+        res.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    if let Some(last) = res.last_mut() {
+        if *last & 0x80 != 0 {
+            res.push(if v < 0 { 0x80 } else { 0x00 });
+        } else if v < 0 {
+            *last |= 0x80;
+        }
+    }
+    res
 }
 
 impl MemoryEntry {
@@ -137,11 +252,145 @@ impl DSL {
             trace: vec![],
             num_inputs: None,
             hint: vec![],
+            hint_origins: vec![],
             output: vec![],
+            annotations: HashMap::new(),
+            pinned: BTreeSet::new(),
+            interned: HashMap::new(),
+            max_element_width: None,
         }
     }
 
     pub fn add_data_type(&mut self, name: impl ToString, element_type: ElementType) -> Result<()> {
+        self.add_data_type_with_num_encoding(name, element_type, NumEncoding::Minimal)
+    }
+
+    pub fn set_max_element_width(&mut self, n: usize) {
+        // a guard against composite values growing out of hand, such as a huge `ManyNum`
+        self.max_element_width = Some(n);
+    }
+
+    fn check_element_width(&self, data: &Element) -> Result<()> {
+        match self.max_element_width {
+            Some(max) if data.len() > max => Err(Error::msg(format!(
+                "The value has {} elements, which exceeds the maximum element width of {}",
+                data.len(),
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn data_type_len(&self, name: impl ToString) -> Result<usize> {
+        match self.data_type_registry.map.get(&name.to_string()) {
+            Some(metadata) => Ok(metadata.element_type.len()),
+            None => Err(Error::msg("The data type has not been registered")),
+        }
+    }
+
+    pub fn add_data_type_with_validator(
+        &mut self,
+        name: impl ToString,
+        element_type: ElementType,
+        validator: fn(&Element) -> Result<()>,
+    ) -> Result<()> {
+        self.add_data_type(name.to_string(), element_type)?;
+        self.data_type_registry.map.get_mut(&name.to_string()).unwrap().validator = Some(validator);
+        Ok(())
+    }
+
+    pub fn add_ref_only_data_type(&mut self, name: impl ToString, element_type: ElementType) -> Result<()> {
+        self.add_data_type(name.to_string(), element_type)?;
+        self.data_type_registry.map.get_mut(&name.to_string()).unwrap().ref_only = true;
+        Ok(())
+    }
+
+    pub fn add_data_type_family(
+        &mut self,
+        name: impl ToString,
+        base_type: ElementType,
+        counts: &[usize],
+    ) -> Result<Vec<String>> {
+        // `fq` with the counts 4 and 8 registers `fq4` and `fq8`, with 4 and 8 elements, and
+        // nothing is registered unless all of them can be
+        if !matches!(base_type, ElementType::Num | ElementType::Str) {
+            return Err(Error::msg("A family of types is based on a single number or string"));
+        }
+        for (i, &count) in counts.iter().enumerate() {
+            if count == 0 {
+                return Err(Error::msg("A type of a family must have at least one element"));
+            }
+            if counts[..i].contains(&count) {
+                return Err(Error::msg(format!("The count {} appears more than once in the family", count)));
+            }
+        }
+        let names = counts
+            .iter()
+            .map(|count| format!("{}{}", name.to_string(), count))
+            .collect::<Vec<String>>();
+        if let Some(name) = names
+            .iter()
+            .find(|name| self.data_type_registry.map.contains_key(name.as_str()))
+        {
+            return Err(Error::msg(format!("The type {} has already been registered", name)));
+        }
+
+        for (name, &count) in names.iter().zip(counts.iter()) {
+            let element_type = match base_type {
+                ElementType::Num => ElementType::ManyNum(count),
+                _ => ElementType::ManyStr(count),
+            };
+            self.add_data_type(name, element_type)?;
+        }
+        Ok(names)
+    }
+
+    pub fn list_data_types(&self) -> Vec<(String, usize, bool)> {
+        // in the order of registration
+        self.data_type_registry
+            .map
+            .iter()
+            .map(|(name, metadata)| (name.clone(), metadata.element_type.len(), metadata.ref_only))
+            .collect()
+    }
+
+    pub fn add_data_type_with_encoding(
+        &mut self,
+        name: impl ToString,
+        element_type: ElementType,
+        encoder: fn(&Element) -> Script,
+        decoder: fn(&[Vec<u8>]) -> Result<Element>,
+    ) -> Result<()> {
+        self.add_data_type(name.to_string(), element_type)?;
+        let metadata = self.data_type_registry.map.get_mut(&name.to_string()).unwrap();
+        metadata.encoder = Some(encoder);
+        metadata.decoder = Some(decoder);
+        Ok(())
+    }
+
+    pub fn decode(&self, data_type: impl ToString, elements: &[Vec<u8>]) -> Result<Element> {
+        let data_type_metadata = self
+            .data_type_registry
+            .map
+            .get(&data_type.to_string())
+            .ok_or(Error::msg("The data type has not been registered"))?;
+        match data_type_metadata.decoder {
+            Some(decoder) => decoder(elements),
+            None => Err(Error::msg("The data type does not have a custom encoding")),
+        }
+    }
+
+    pub fn add_data_type_with_num_encoding(
+        &mut self,
+        name: impl ToString,
+        element_type: ElementType,
+        num_encoding: NumEncoding,
+    ) -> Result<()> {
+        if num_encoding != NumEncoding::Minimal
+            && !matches!(element_type, ElementType::Num | ElementType::ManyNum(_))
+        {
+            return Err(Error::msg("Only numeric types can have a number encoding"));
+        }
         if name.to_string() == "any" {
             return Err(Error::msg("The any type cannot be registered"));
         }
@@ -150,7 +399,17 @@ impl DSL {
         }
         self.data_type_registry
             .map
-            .insert(name.to_string(), DataTypeMetadata { element_type });
+            .insert(
+                name.to_string(),
+                DataTypeMetadata {
+                    element_type,
+                    num_encoding,
+                    validator: None,
+                    encoder: None,
+                    decoder: None,
+                    ref_only: false,
+                },
+            );
         Ok(())
     }
 
@@ -162,9 +421,148 @@ impl DSL {
         Ok(())
     }
 
+    pub fn list_functions(&self) -> Vec<&str> {
+        // in the order of registration
+        self.function_registry.map.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn gadget_dependency_graph(&self) -> BTreeMap<String, Vec<String>> {
+        // every registered function, with the functions that it declares to call
+        self.function_registry
+            .map
+            .iter()
+            .map(|(name, function_metadata)| {
+                let calls = function_metadata.calls().iter().map(|call| call.to_string()).collect();
+                (name.clone(), calls)
+            })
+            .collect()
+    }
+
+    pub fn function_effects(&self) -> Vec<FunctionEffect> {
+        // the outputs of functions with options are those of the default options
+        let num_elements = |types: &mut dyn Iterator<Item = &str>| -> Option<usize> {
+            let mut res = 0;
+            for data_type in types {
+                res += self.data_type_registry.map.get(data_type)?.element_type.len();
+            }
+            Some(res)
+        };
+
+        let mut res = vec![];
+        for (name, function_metadata) in self.function_registry.map.iter() {
+            let (input, output, max_internal_stack) = match function_metadata {
+                AcceptableFunctionMetadata::FunctionWithoutOptions(v) => {
+                    (&v.input, v.output.clone(), v.max_internal_stack)
+                }
+                AcceptableFunctionMetadata::FunctionWithOptions(v) => {
+                    (&v.input, v.output_types(&Options::new()), v.max_internal_stack)
+                }
+            };
+
+            let consumed = num_elements(&mut input.iter().copied().filter(|t| !t.starts_with('&')));
+            let produced = num_elements(&mut output.iter().copied());
+            let net_delta = match (consumed, produced) {
+                (Some(consumed), Some(produced)) => Some(produced as isize - consumed as isize),
+                _ => None,
+            };
+
+            res.push(FunctionEffect {
+                name: name.clone(),
+                consumed,
+                produced,
+                net_delta,
+                max_internal_stack,
+            });
+        }
+        res
+    }
+
+    pub fn type_confusion_report(&self) -> Vec<TypeConfusion> {
+        // `execute` rejects such calls, but a trace that is edited, or checked against another
+        // registry than the one it is built with, may have them. Inputs of the `any` type are
+        // not reported, as they take any type on purpose.
+        let mut res = vec![];
+        for (step, trace_entry) in self.trace.iter().enumerate() {
+            let (function_name, input_idxs) = match trace_entry {
+                TraceEntry::FunctionCall(function_name, input_idxs)
+                | TraceEntry::FunctionCallWithOptions(function_name, input_idxs, _) => {
+                    (function_name, input_idxs)
+                }
+                _ => continue,
+            };
+            let input = match self.function_registry.map.get(function_name) {
+                Some(AcceptableFunctionMetadata::FunctionWithoutOptions(v)) => &v.input,
+                Some(AcceptableFunctionMetadata::FunctionWithOptions(v)) => &v.input,
+                None => continue,
+            };
+
+            for (i, (idx, input_type)) in input_idxs.iter().zip(input.iter()).enumerate() {
+                let expected = input_type.trim_start_matches('&');
+                let actual = match self.memory.get(idx) {
+                    Some(entry) => &entry.data_type,
+                    None => continue,
+                };
+                if expected == "any" || expected == actual {
+                    continue;
+                }
+                let element_type = |data_type: &str| {
+                    self.data_type_registry
+                        .map
+                        .get(data_type)
+                        .map(|metadata| metadata.element_type.clone())
+                };
+                let structurally_equal = element_type(expected).is_some()
+                    && element_type(expected) == element_type(actual);
+                res.push(TypeConfusion {
+                    step,
+                    function_name: function_name.clone(),
+                    input: i,
+                    expected: expected.to_string(),
+                    actual: actual.clone(),
+                    structurally_equal,
+                });
+            }
+        }
+        res
+    }
+
+    pub fn validate_registry(&self) -> Result<()> {
+        for (function_name, function_metadata) in self.function_registry.map.iter() {
+            let (input, output) = match function_metadata {
+                AcceptableFunctionMetadata::FunctionWithoutOptions(v) => (&v.input, &v.output),
+                AcceptableFunctionMetadata::FunctionWithOptions(v) => (&v.input, &v.output),
+            };
+
+            for data_type in input.iter() {
+                let by_value = self.data_type_registry.map.get(*data_type);
+                if by_value.is_some_and(|metadata| metadata.ref_only) {
+                    return Err(Error::msg(format!(
+                        "The function {} takes the data type {} by value, which is only passed by reference",
+                        function_name, data_type
+                    )));
+                }
+            }
+
+            let data_types = input
+                .iter()
+                .map(|t| t.trim_start_matches('&'))
+                .chain(output.iter().copied())
+                .filter(|&t| t != "any");
+            for data_type in data_types {
+                if !self.data_type_registry.map.contains_key(data_type) {
+                    return Err(Error::msg(format!(
+                        "The function {} uses the data type {}, which has not been registered",
+                        function_name, data_type
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn alloc(&mut self, data_type: impl ToString, data: Element) -> Result<usize> {
+        // the index is only taken once the data is accepted, so that rejected data leaves no gap
         let idx = self.memory_last_idx;
-        self.memory_last_idx += 1;
 
         let data_type_metadata = self.data_type_registry.map.get(&data_type.to_string());
 
@@ -176,9 +574,20 @@ impl DSL {
         if !data.match_type(&data_type_metadata.element_type) {
             return Err(Error::msg("The data does not match the type definitions"));
         }
+        self.check_element_width(&data)?;
+        let encoded = data_type_metadata.encode(&data)?;
+        if let Some(decoder) = data_type_metadata.decoder {
+            if decoder(&encoded)? != data {
+                return Err(Error::msg("The data is not decoded back from its encoding"));
+            }
+        }
+        if let Some(validator) = data_type_metadata.validator {
+            validator(&data)?;
+        }
         if self.memory.get(&idx).is_some() {
             return Err(Error::msg("Memory is corrupted"));
         }
+        self.memory_last_idx += 1;
         self.memory.insert(
             idx,
             MemoryEntry {
@@ -199,6 +608,18 @@ impl DSL {
         Ok(idx)
     }
 
+    pub fn intern_constant(&mut self, data_type: impl ToString, data: Element) -> Result<ConstHandle> {
+        // the same data type and data always give the same handle, so the constant is allocated,
+        // and pushed by the compiler, only once
+        let key = (data_type.to_string(), data);
+        if let Some(&idx) = self.interned.get(&key) {
+            return Ok(ConstHandle(idx));
+        }
+        let idx = self.alloc_constant(&key.0, key.1.clone())?;
+        self.interned.insert(key, idx);
+        Ok(ConstHandle(idx))
+    }
+
     pub fn alloc_input(&mut self, data_type: impl ToString, data: Element) -> Result<usize> {
         if self.num_inputs.is_some() {
             return Err(Error::msg(
@@ -214,19 +635,79 @@ impl DSL {
         }
         let idx = Self::alloc(self, data_type, data)?;
         self.hint.push(self.memory.get(&idx).unwrap().clone());
+        self.hint_origins.push(self.trace.len());
         self.trace.push(TraceEntry::AllocatedHint(idx));
         Ok(idx)
     }
 
+    pub fn alloc_named_hint(
+        &mut self,
+        data_type: impl ToString,
+        data: Element,
+        name: impl ToString,
+    ) -> Result<usize> {
+        let idx = self.alloc_hint(data_type, data)?;
+        self.set_name(idx, name)?;
+        Ok(idx)
+    }
+
+    pub fn embed_script(
+        &mut self,
+        script: Script,
+        consumes: &[usize],
+        produces: Vec<(&'static str, Element)>,
+    ) -> Result<Vec<usize>> {
+        // the script finds the consumed values at the top of the stack, the last one at the top,
+        // and replaces them with the produced values, just like a function
+        if self.num_inputs.is_none() {
+            self.num_inputs = Some(self.memory_last_idx);
+        }
+        self.check_allocated(consumes)?;
+
+        let output_types = produces.iter().map(|(data_type, _)| *data_type).collect::<Vec<&str>>();
+        let new_elements = produces
+            .into_iter()
+            .map(|(data_type, data)| MemoryEntry::new(data_type, data))
+            .collect();
+        let outputs = handle_output(self, &output_types, new_elements)?;
+
+        self.trace.push(TraceEntry::EmbeddedScript(
+            script,
+            consumes.to_vec(),
+            output_types.iter().map(|t| t.to_string()).collect(),
+        ));
+
+        Ok(outputs)
+    }
+
+    pub fn require_relative_timelock(&mut self, blocks: u16) {
+        self.require_relative_lock_time(bitcoin::relative::LockTime::from_height(blocks));
+    }
+
+    pub fn require_relative_lock_time(&mut self, lock_time: bitcoin::relative::LockTime) {
+        // a lock time in blocks or in units of 512 seconds
+        self.trace.push(TraceEntry::RelativeTimelock(lock_time.to_consensus_u32()));
+    }
+
+    pub fn require_absolute_timelock(&mut self, height_or_time: u32) {
+        self.trace.push(TraceEntry::AbsoluteTimelock(height_or_time));
+    }
+
     pub fn set_program_output(
         &mut self,
         expected_data_type: impl ToString,
         idx: usize,
     ) -> Result<()> {
+        // a value can be output more than once, each time checked against the type it is stored with
         match self.memory.get(&idx) {
             Some(MemoryEntry { data_type, .. }) => {
                 if *data_type != expected_data_type.to_string() {
-                    Err(Error::msg("The program output data type does not match"))
+                    Err(Error::msg(format!(
+                        "The program output data type does not match: the value {} has the type {}, not {}",
+                        idx,
+                        data_type,
+                        expected_data_type.to_string()
+                    )))
                 } else {
                     self.output.push(idx);
                     Ok(())
@@ -238,6 +719,39 @@ impl DSL {
         }
     }
 
+    pub fn set_output_groups(&mut self, groups: &[(&str, Vec<usize>)]) -> Result<()> {
+        // The outputs are replaced by the groups in order, each of values of the type it declares.
+        // A value may be in several groups, which then must declare the same type. Nothing is
+        // output unless all the groups are consistent.
+        let mut group_types: HashMap<usize, &str> = HashMap::new();
+        for (group_type, idxs) in groups.iter() {
+            for &idx in idxs.iter() {
+                if let Some(other_type) = group_types.insert(idx, *group_type) {
+                    if other_type != *group_type {
+                        return Err(Error::msg(format!(
+                            "The value {} is in output groups of the types {} and {}",
+                            idx, other_type, group_type
+                        )));
+                    }
+                }
+                let data_type = &self
+                    .memory
+                    .get(&idx)
+                    .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+                    .data_type;
+                if data_type.as_str() != *group_type {
+                    return Err(Error::msg(format!(
+                        "The output group of the type {} has the value {} of the type {}",
+                        group_type, idx, data_type
+                    )));
+                }
+            }
+        }
+
+        self.output = groups.iter().flat_map(|(_, idxs)| idxs.iter().copied()).collect();
+        Ok(())
+    }
+
     pub fn get_num(&mut self, idx: usize) -> Result<i32> {
         match self.memory.get(&idx) {
             Some(MemoryEntry {
@@ -286,6 +800,40 @@ impl DSL {
         }
     }
 
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+
+    pub fn trace_len(&self) -> usize {
+        self.trace.len()
+    }
+
+    pub fn constants(&self) -> impl Iterator<Item = (usize, &MemoryEntry)> {
+        self.trace.iter().filter_map(|trace_entry| match trace_entry {
+            TraceEntry::AllocatedConstant(idx) => Some((*idx, self.memory.get(idx).unwrap())),
+            _ => None,
+        })
+    }
+
+    pub fn debug_assert_value(&self, idx: usize, expected: Element) -> Result<()> {
+        // a build-time check only, nothing is emitted into the script
+        match self.memory.get(&idx) {
+            Some(MemoryEntry { data, .. }) => {
+                if *data == expected {
+                    Ok(())
+                } else {
+                    Err(Error::msg(format!(
+                        "The value at memory index {} is {:?}, but {:?} is expected",
+                        idx, data, expected
+                    )))
+                }
+            }
+            _ => Err(Error::msg(
+                "Could not find the memory entry with the given index",
+            )),
+        }
+    }
+
     pub fn set_name(&mut self, idx: usize, name: impl ToString) -> Result<()> {
         let entry = self.memory.get_mut(&idx);
 
@@ -295,6 +843,15 @@ impl DSL {
             ))
         } else {
             entry.unwrap().description = Some(name.to_string());
+
+            // keep the copy in the hint list in sync
+            for (hint, &origin) in self.hint.iter_mut().zip(self.hint_origins.iter()) {
+                if let TraceEntry::AllocatedHint(hint_idx) = self.trace[origin] {
+                    if hint_idx == idx {
+                        hint.description = Some(name.to_string());
+                    }
+                }
+            }
             Ok(())
         }
     }
@@ -333,7 +890,7 @@ impl DSL {
         }
 
         for (input_idx, &input_type) in input_idxs.iter().zip(input.iter()) {
-            if input_type != "any" {
+            if input_type != "any" && input_type != "&any" {
                 let stack_entry = self.memory.get_mut(input_idx).unwrap();
                 if stack_entry.data_type != input_type
                     && input_type != format!("&{}", stack_entry.data_type)
@@ -343,19 +900,15 @@ impl DSL {
             }
         }
 
-        let output = match function_metadata {
-            AcceptableFunctionMetadata::FunctionWithoutOptions(v) => &v.output,
-            AcceptableFunctionMetadata::FunctionWithOptions(v) => &v.output
-        };
-
-        let output_types = output.clone();
+        let output_types = function_metadata.output_types(&Options::new());
 
         let exec_result = match function_metadata {
             AcceptableFunctionMetadata::FunctionWithoutOptions(v) => {
                 (v.trace_generator)(self, &input_idxs)?
             }
             AcceptableFunctionMetadata::FunctionWithOptions(v) => {
-                (v.trace_generator)(self, &input_idxs, &Options::new())?
+                let options = v.default_options.clone();
+                (v.trace_generator)(self, &input_idxs, &options)?
             }
         };
 
@@ -363,6 +916,8 @@ impl DSL {
             return Err(Error::msg("The number of outputs does not match"));
         }
 
+        self.hint_origins
+            .extend(vec![self.trace.len(); exec_result.new_hints.len()]);
         self.hint.extend(exec_result.new_hints);
 
         let outputs = handle_output(self, &output_types, exec_result.new_elements)?;
@@ -375,6 +930,23 @@ impl DSL {
         Ok(outputs)
     }
 
+    pub fn execute_pinned(&mut self, function_name: impl ToString, input_idxs: &[usize]) -> Result<Vec<usize>> {
+        let outputs = self.execute(function_name, input_idxs)?;
+        self.pinned.insert(self.trace.len() - 1);
+        Ok(outputs)
+    }
+
+    pub fn execute_with_options_pinned(
+        &mut self,
+        function_name: impl ToString,
+        input_idxs: &[usize],
+        options: &Options,
+    ) -> Result<Vec<usize>> {
+        let outputs = self.execute_with_options(function_name, input_idxs, options)?;
+        self.pinned.insert(self.trace.len() - 1);
+        Ok(outputs)
+    }
+
     pub fn execute_with_options(
         &mut self,
         function_name: impl ToString,
@@ -410,7 +982,7 @@ impl DSL {
         }
 
         for (input_idx, &input_type) in input_idxs.iter().zip(function_metadata.input.iter()) {
-            if input_type != "any" {
+            if input_type != "any" && input_type != "&any" {
                 let stack_entry = self.memory.get_mut(input_idx).unwrap();
                 if stack_entry.data_type != input_type
                     && input_type != format!("&{}", stack_entry.data_type)
@@ -420,14 +992,17 @@ impl DSL {
             }
         }
 
-        let output_types = function_metadata.output.clone();
+        let options = &options.with_defaults(&function_metadata.default_options);
+        let output_types = function_metadata.output_types(options);
 
-        let exec_result = (function_metadata.trace_generator)(self, &input_idxs, &options)?;
+        let exec_result = (function_metadata.trace_generator)(self, &input_idxs, options)?;
 
         if exec_result.new_elements.len() != output_types.len() {
             return Err(Error::msg("The number of outputs does not match"));
         }
 
+        self.hint_origins
+            .extend(vec![self.trace.len(); exec_result.new_hints.len()]);
         self.hint.extend(exec_result.new_hints);
 
         let outputs = handle_output(self, &output_types, exec_result.new_elements)?;
@@ -440,25 +1015,1807 @@ impl DSL {
 
         Ok(outputs)
     }
-}
 
-fn handle_output(dsl: &mut DSL, output_types: &[&str], new_elements: Vec<MemoryEntry>) -> Result<Vec<usize>> {
-    let mut outputs = vec![];
-    for (&output_type, entry) in output_types.iter().zip(new_elements) {
-        if output_type != entry.data_type {
-            return Err(Error::msg("The output data type mismatches"));
+    /// Replays a recorded trace against the given inputs. Besides the trace and the inputs, it takes
+    /// the constants and hints as `allocations` and the program outputs, since the trace only records
+    /// their indices, and the two registries as the separate values the DSL keeps them in.
+    pub fn rebuild(
+        trace: Vec<TraceEntry>,
+        inputs: Vec<MemoryEntry>,
+        allocations: Vec<MemoryEntry>,
+        output: Vec<usize>,
+        data_type_registry: DataTypeRegistry,
+        function_registry: FunctionRegistry,
+    ) -> Result<DSL> {
+        // `allocations` are the constants and hints, in the order they appear in the trace
+        let mut dsl = DSL::new();
+        dsl.data_type_registry = data_type_registry;
+        dsl.function_registry = function_registry;
+
+        for entry in inputs.into_iter() {
+            let idx = dsl.alloc_input(&entry.data_type, entry.data)?;
+            if let Some(description) = entry.description {
+                dsl.set_name(idx, description)?;
+            }
         }
-        let data_type_metadata = dsl.data_type_registry.map.get(output_type).unwrap();
-        if !entry.data.match_type(&data_type_metadata.element_type) {
+
+        let mut allocations = allocations.into_iter();
+        for trace_entry in trace.into_iter() {
+            match trace_entry {
+                TraceEntry::FunctionCall(function_name, input_idxs) => {
+                    dsl.check_allocated(&input_idxs)?;
+                    dsl.execute(function_name, &input_idxs)?;
+                }
+                TraceEntry::FunctionCallWithOptions(function_name, input_idxs, options) => {
+                    dsl.check_allocated(&input_idxs)?;
+                    dsl.execute_with_options(function_name, &input_idxs, &options)?;
+                }
+                TraceEntry::RelativeTimelock(sequence) => {
+                    dsl.trace.push(TraceEntry::RelativeTimelock(sequence))
+                }
+                TraceEntry::AbsoluteTimelock(height_or_time) => {
+                    dsl.require_absolute_timelock(height_or_time)
+                }
+                TraceEntry::EmbeddedScript(..) => {
+                    return Err(Error::msg(
+                        "The values produced by an embedded script cannot be recomputed",
+                    ));
+                }
+                TraceEntry::AllocatedConstant(idx) | TraceEntry::AllocatedHint(idx) => {
+                    let entry = allocations.next().ok_or(Error::msg(
+                        "The trace has more allocations than the provided memory entries",
+                    ))?;
+                    let new_idx = if matches!(trace_entry, TraceEntry::AllocatedConstant(_)) {
+                        dsl.alloc_constant(&entry.data_type, entry.data)?
+                    } else {
+                        dsl.alloc_hint(&entry.data_type, entry.data)?
+                    };
+                    if new_idx != idx {
+                        return Err(Error::msg(
+                            "The trace is inconsistent with the number of provided inputs",
+                        ));
+                    }
+                    if let Some(description) = entry.description {
+                        dsl.set_name(new_idx, description)?;
+                    }
+                }
+            }
+        }
+
+        if allocations.next().is_some() {
             return Err(Error::msg(
-                "The output data does not match the type definitions",
+                "The trace has fewer allocations than the provided memory entries",
             ));
         }
 
-        let idx = dsl.memory_last_idx;
-        dsl.memory_last_idx += 1;
-        dsl.memory.insert(idx, entry);
-        outputs.push(idx);
+        dsl.check_allocated(&output)?;
+        dsl.output = output;
+
+        Ok(dsl)
+    }
+
+    pub fn replay_with_inputs(&self, inputs: Vec<MemoryEntry>) -> Result<DSL> {
+        DSL::rebuild(
+            self.trace.clone(),
+            inputs,
+            self.allocations(),
+            self.output.clone(),
+            self.data_type_registry.clone(),
+            self.function_registry.clone(),
+        )
+    }
+
+    pub(crate) fn allocations(&self) -> Vec<MemoryEntry> {
+        // the constants and hints allocated in the trace, in order, as `rebuild` takes them
+        let mut allocations = vec![];
+        for trace_entry in self.trace.iter() {
+            match trace_entry {
+                TraceEntry::AllocatedConstant(idx) | TraceEntry::AllocatedHint(idx) => {
+                    allocations.push(self.memory.get(idx).unwrap().clone());
+                }
+                _ => {}
+            }
+        }
+        allocations
+    }
+
+    pub fn annotate(&mut self, trace_idx: usize, key: impl ToString, value: impl ToString) -> Result<()> {
+        if trace_idx >= self.trace.len() {
+            return Err(Error::msg("The trace entry to be annotated does not exist"));
+        }
+        self.annotations
+            .entry(trace_idx)
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    pub fn get_annotations(&self, trace_idx: usize) -> Option<&BTreeMap<String, String>> {
+        self.annotations.get(&trace_idx)
+    }
+
+    pub fn hint_consumers(&self) -> Vec<(usize, usize)> {
+        // every hint is pulled by the trace step that produced it, which is either its allocation
+        // or the function call whose trace generator returned it
+        self.hint_origins.iter().copied().enumerate().collect()
+    }
+
+    pub fn assert_hints_determined(&self) -> Result<()> {
+        // The trace is replayed from the same inputs, which catches trace generators that read
+        // anything other than their inputs and prior values, such as a clock or an RNG. This does
+        // not show that the script rejects other hints, which is up to each gadget, and it cannot
+        // replay a trace with embedded scripts.
+        self.assert_replay_matches(false)
+    }
+
+    pub fn assert_trace_deterministic(&self) -> Result<()> {
+        // as `assert_hints_determined`, but every value is also compared with the one produced
+        // the first time, so that the error points to the first trace step that diverges
+        self.assert_replay_matches(true)
+    }
+
+    fn assert_replay_matches(&self, compare_values: bool) -> Result<()> {
+        let replay = self.replay_own_inputs()?;
+
+        let mut hints_by_step = vec![vec![]; self.trace.len()];
+        for (i, &origin) in self.hint_origins.iter().enumerate() {
+            hints_by_step[origin].push(i);
+        }
+
+        for (step, idxs) in self.trace_outputs().iter().enumerate() {
+            if compare_values {
+                for &idx in idxs.iter() {
+                    let entry = self.memory.get(&idx).unwrap();
+                    let replayed = replay.memory.get(&idx).unwrap();
+                    if entry.data_type != replayed.data_type || entry.data != replayed.data {
+                        return Err(Error::msg(format!(
+                            "The trace step {} produces {:?} for the memory entry {}, but {:?} when the trace is replayed",
+                            step, entry.data, idx, replayed.data
+                        )));
+                    }
+                }
+            }
+            for &i in hints_by_step[step].iter() {
+                let hint = &self.hint[i];
+                match replay.hint.get(i) {
+                    Some(replayed)
+                        if replay.hint_origins[i] == step
+                            && replayed.data_type == hint.data_type
+                            && replayed.data == hint.data => {}
+                    _ => {
+                        return Err(Error::msg(format!(
+                            "The hint {} produced by the trace step {} changes when the trace is replayed",
+                            i, step
+                        )))
+                    }
+                }
+            }
+        }
+        if replay.hint.len() != self.hint.len() {
+            return Err(Error::msg("The number of hints changes when the trace is replayed"));
+        }
+        Ok(())
+    }
+
+    fn replay_own_inputs(&self) -> Result<DSL> {
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let inputs = (0..num_inputs)
+            .map(|idx| {
+                self.memory.get(&idx).cloned().ok_or(Error::msg(
+                    "Could not find the memory entry with the given index",
+                ))
+            })
+            .collect::<Result<Vec<MemoryEntry>>>()?;
+        self.replay_with_inputs(inputs)
+    }
+
+    pub fn peak_live_values(&self) -> usize {
+        // a value is live from the trace step that produces it (inputs: step 0) to its last use,
+        // or to the end of the program if it is an output
+        let num_steps = self.trace.len();
+        let mut def = vec![0usize; self.memory_last_idx];
+        for (t, idxs) in self.trace_outputs().iter().enumerate() {
+            for &idx in idxs.iter() {
+                def[idx] = t;
+            }
+        }
+
+        let mut end = def.clone();
+        for (t, trace_entry) in self.trace.iter().enumerate() {
+            for &idx in trace_entry.input_idxs().iter() {
+                end[idx] = t;
+            }
+        }
+        for &idx in self.output.iter() {
+            end[idx] = num_steps;
+        }
+
+        let mut delta = vec![0isize; num_steps + 2];
+        for idx in 0..self.memory_last_idx {
+            if self.memory.contains_key(&idx) {
+                delta[def[idx]] += 1;
+                delta[end[idx] + 1] -= 1;
+            }
+        }
+
+        let mut peak = 0;
+        let mut cur = 0;
+        for d in delta.iter() {
+            cur += d;
+            peak = peak.max(cur);
+        }
+        peak as usize
+    }
+
+    pub(crate) fn trace_outputs(&self) -> Vec<Vec<usize>> {
+        // memory indices are allocated sequentially, first the inputs and then along the trace
+        let mut next = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let mut res = vec![];
+        for trace_entry in self.trace.iter() {
+            let num = match trace_entry {
+                TraceEntry::FunctionCall(function_name, _) => self
+                    .function_registry
+                    .map
+                    .get(function_name)
+                    .unwrap()
+                    .output_types(&Options::new())
+                    .len(),
+                TraceEntry::FunctionCallWithOptions(function_name, _, options) => self
+                    .function_registry
+                    .map
+                    .get(function_name)
+                    .unwrap()
+                    .output_types(options)
+                    .len(),
+                TraceEntry::AllocatedConstant(_) | TraceEntry::AllocatedHint(_) => 1,
+                TraceEntry::EmbeddedScript(_, _, output_types) => output_types.len(),
+                TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_) => 0,
+            };
+            res.push((next..next + num).collect());
+            next += num;
+        }
+        res
+    }
+
+    pub(crate) fn reorder(&mut self, inputs: &[usize], order: &[usize]) -> Result<()> {
+        // Rebuild the memory from the given inputs (old indices, in their new order) and trace steps
+        // (old trace indices, in their new order), so that indices are again allocated sequentially.
+        // Entries that are left out are dropped, together with the values and hints they produced.
+        let trace_outputs = self.trace_outputs();
+
+        let mut hints_by_step = vec![vec![]; self.trace.len()];
+        for (hint, &origin) in self.hint.iter().zip(self.hint_origins.iter()) {
+            hints_by_step[origin].push(hint.clone());
+        }
+
+        let mut map = HashMap::new();
+        let mut memory = IndexMap::new();
+        let mut next = 0;
+
+        for &idx in inputs.iter() {
+            let entry = self.memory.get(&idx).ok_or(Error::msg(
+                "Could not find the memory entry with the given index",
+            ))?;
+            map.insert(idx, next);
+            memory.insert(next, entry.clone());
+            next += 1;
+        }
+        let num_inputs = next;
+
+        let mut trace = vec![];
+        let mut hint = vec![];
+        let mut hint_origins = vec![];
+        let mut new_annotations = HashMap::new();
+        let mut pinned = BTreeSet::new();
+        for (new_step, &step) in order.iter().enumerate() {
+            let trace_entry = match &self.trace[step] {
+                TraceEntry::FunctionCall(function_name, input_idxs) => TraceEntry::FunctionCall(
+                    function_name.clone(),
+                    remap_idxs(&map, input_idxs)?,
+                ),
+                TraceEntry::FunctionCallWithOptions(function_name, input_idxs, options) => {
+                    TraceEntry::FunctionCallWithOptions(
+                        function_name.clone(),
+                        remap_idxs(&map, input_idxs)?,
+                        options.clone(),
+                    )
+                }
+                TraceEntry::EmbeddedScript(script, input_idxs, output_types) => {
+                    TraceEntry::EmbeddedScript(
+                        script.clone(),
+                        remap_idxs(&map, input_idxs)?,
+                        output_types.clone(),
+                    )
+                }
+                TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_) => {
+                    self.trace[step].clone()
+                }
+                TraceEntry::AllocatedConstant(_) => TraceEntry::AllocatedConstant(next),
+                TraceEntry::AllocatedHint(_) => TraceEntry::AllocatedHint(next),
+            };
+            trace.push(trace_entry);
+
+            for &idx in trace_outputs[step].iter() {
+                map.insert(idx, next);
+                memory.insert(next, self.memory.get(&idx).unwrap().clone());
+                next += 1;
+            }
+
+            for entry in hints_by_step[step].drain(..) {
+                hint.push(entry);
+                hint_origins.push(new_step);
+            }
+
+            if let Some(annotations) = self.annotations.get(&step) {
+                new_annotations.insert(new_step, annotations.clone());
+            }
+            if self.pinned.contains(&step) {
+                pinned.insert(new_step);
+            }
+        }
+
+        self.output = remap_idxs(&map, &self.output)?;
+        if self.num_inputs.is_some() || !trace.is_empty() {
+            self.num_inputs = Some(num_inputs);
+        }
+        self.memory = memory;
+        self.memory_last_idx = next;
+        self.trace = trace;
+        self.hint = hint;
+        self.hint_origins = hint_origins;
+        self.annotations = new_annotations;
+        self.pinned = pinned;
+        self.interned = self
+            .interned
+            .drain()
+            .filter_map(|(key, idx)| map.get(&idx).map(|&new_idx| (key, new_idx)))
+            .collect();
+
+        Ok(())
+    }
+
+    pub fn rebase_indices(&mut self, offset: usize) -> Result<()> {
+        // The steps of the trace keep their positions, so the hints and annotations stay as they
+        // are. The indices below the offset are left to the program that this one is merged into,
+        // so the result only compiles once they are filled. A copy is rebased and checked, so the
+        // program is left as it is on an error.
+        if self.memory_last_idx.checked_add(offset).is_none() {
+            return Err(Error::msg(format!(
+                "Rebasing the memory indices by {} overflows them",
+                offset
+            )));
+        }
+        let shift = |idxs: &[usize]| idxs.iter().map(|&idx| idx + offset).collect::<Vec<usize>>();
+
+        let mut rebased = self.clone();
+        rebased.memory = self
+            .memory
+            .iter()
+            .map(|(&idx, entry)| (idx + offset, entry.clone()))
+            .collect();
+        rebased.trace = self
+            .trace
+            .iter()
+            .map(|trace_entry| match trace_entry {
+                TraceEntry::FunctionCall(function_name, input_idxs) => {
+                    TraceEntry::FunctionCall(function_name.clone(), shift(input_idxs))
+                }
+                TraceEntry::FunctionCallWithOptions(function_name, input_idxs, options) => {
+                    TraceEntry::FunctionCallWithOptions(
+                        function_name.clone(),
+                        shift(input_idxs),
+                        options.clone(),
+                    )
+                }
+                TraceEntry::EmbeddedScript(script, input_idxs, output_types) => {
+                    TraceEntry::EmbeddedScript(script.clone(), shift(input_idxs), output_types.clone())
+                }
+                TraceEntry::AllocatedConstant(idx) => TraceEntry::AllocatedConstant(*idx + offset),
+                TraceEntry::AllocatedHint(idx) => TraceEntry::AllocatedHint(*idx + offset),
+                TraceEntry::RelativeTimelock(_) | TraceEntry::AbsoluteTimelock(_) => trace_entry.clone(),
+            })
+            .collect();
+        rebased.output = shift(&self.output);
+        rebased.memory_last_idx += offset;
+        rebased.num_inputs = self.num_inputs.map(|num_inputs| num_inputs + offset);
+        for idx in rebased.interned.values_mut() {
+            *idx += offset;
+        }
+
+        rebased.check_rebased(offset)?;
+        rebased.data_type_registry.validate()?;
+        rebased.validate_registry()?;
+        *self = rebased;
+        Ok(())
+    }
+
+    fn check_rebased(&self, offset: usize) -> Result<()> {
+        // The shifted program is checked against where the trace produces its values, starting
+        // from the inputs at the offset, so that a value used before it is defined, an allocation
+        // out of order or a stale interned constant shows up before the program is merged.
+        if self.memory.keys().any(|&idx| idx < offset || idx >= self.memory_last_idx) {
+            return Err(Error::msg("The memory has an entry outside of the rebased indices"));
+        }
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let mut defined = vec![false; self.memory_last_idx];
+        for idx in offset..num_inputs {
+            if !self.memory.contains_key(&idx) {
+                return Err(Error::msg(format!("The input {} is missing after rebasing", idx)));
+            }
+            defined[idx] = true;
+        }
+
+        for (step, (trace_entry, outputs)) in self.trace.iter().zip(self.trace_outputs()).enumerate() {
+            for &idx in trace_entry.input_idxs().iter() {
+                if !defined.get(idx).copied().unwrap_or(false) {
+                    return Err(Error::msg(format!(
+                        "The trace step {} uses the value {}, which is not defined before it",
+                        step, idx
+                    )));
+                }
+            }
+            if let TraceEntry::AllocatedConstant(idx) | TraceEntry::AllocatedHint(idx) = trace_entry {
+                if outputs != [*idx] {
+                    return Err(Error::msg(format!(
+                        "The trace step {} allocates the value {} out of order",
+                        step, idx
+                    )));
+                }
+            }
+            for &idx in outputs.iter() {
+                if idx >= self.memory_last_idx || !self.memory.contains_key(&idx) {
+                    return Err(Error::msg(format!(
+                        "The trace step {} produces the value {}, which is not in the memory",
+                        step, idx
+                    )));
+                }
+                defined[idx] = true;
+            }
+        }
+
+        for &idx in self.output.iter() {
+            if !defined.get(idx).copied().unwrap_or(false) {
+                return Err(Error::msg(format!("The program output {} is not defined", idx)));
+            }
+        }
+        for ((data_type, data), &idx) in self.interned.iter() {
+            let matches = self
+                .memory
+                .get(&idx)
+                .is_some_and(|entry| entry.data_type == *data_type && entry.data == *data);
+            if !matches || !self.trace.contains(&TraceEntry::AllocatedConstant(idx)) {
+                return Err(Error::msg(format!(
+                    "The interned constant {} is not a constant of its type and value",
+                    idx
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn canonical_key(&self) -> Vec<u8> {
+        // The values are renumbered in the order the trace first uses or produces them, so that
+        // the key does not depend on the indices or the names. The inputs, by their positions in
+        // the witness with their types, the trace with the data of its constants, the outputs, and
+        // the registered metadata of the types and functions used are serialized with length
+        // prefixes. The values of the inputs and the hints are not part of the key. Programs that
+        // only differ in the order of allocating the inputs have different witness layouts, and
+        // thus different keys, unless `canonicalize_inputs` is run on both first.
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let trace_outputs = self.trace_outputs();
+
+        let mut map = HashMap::new();
+        for (step, trace_entry) in self.trace.iter().enumerate() {
+            for &idx in trace_entry.input_idxs().iter().chain(trace_outputs[step].iter()) {
+                let next = map.len();
+                map.entry(idx).or_insert(next);
+            }
+        }
+        for idx in self.output.iter().copied().chain(0..num_inputs) {
+            let next = map.len();
+            map.entry(idx).or_insert(next);
+        }
+        let remap = |idxs: &[usize]| idxs.iter().map(|idx| map[idx] as u64).collect::<Vec<u64>>();
+
+        let mut key = vec![];
+        write_u64s(&mut key, &remap(&(0..num_inputs).collect::<Vec<usize>>()));
+        for idx in 0..num_inputs {
+            write_bytes(&mut key, self.memory.get(&idx).unwrap().data_type.as_bytes());
+        }
+
+        write_u64s(&mut key, &[self.trace.len() as u64]);
+        for trace_entry in self.trace.iter() {
+            match trace_entry {
+                TraceEntry::FunctionCall(function_name, input_idxs) => {
+                    key.push(0);
+                    write_bytes(&mut key, function_name.as_bytes());
+                    write_u64s(&mut key, &remap(input_idxs));
+                }
+                TraceEntry::FunctionCallWithOptions(function_name, input_idxs, options) => {
+                    key.push(1);
+                    write_bytes(&mut key, function_name.as_bytes());
+                    write_u64s(&mut key, &remap(input_idxs));
+                    write_options(&mut key, options);
+                }
+                TraceEntry::AllocatedConstant(idx) => {
+                    let entry = self.memory.get(idx).unwrap();
+                    key.push(2);
+                    write_bytes(&mut key, entry.data_type.as_bytes());
+                    write_element(&mut key, &entry.data);
+                }
+                TraceEntry::AllocatedHint(idx) => {
+                    key.push(3);
+                    write_bytes(&mut key, self.memory.get(idx).unwrap().data_type.as_bytes());
+                }
+                TraceEntry::EmbeddedScript(script, input_idxs, output_types) => {
+                    key.push(4);
+                    write_bytes(&mut key, script.as_bytes());
+                    write_u64s(&mut key, &remap(input_idxs));
+                    write_u64s(&mut key, &[output_types.len() as u64]);
+                    for output_type in output_types.iter() {
+                        write_bytes(&mut key, output_type.as_bytes());
+                    }
+                }
+                TraceEntry::RelativeTimelock(sequence) => {
+                    key.push(5);
+                    write_u64s(&mut key, &[*sequence as u64]);
+                }
+                TraceEntry::AbsoluteTimelock(height_or_time) => {
+                    key.push(6);
+                    write_u64s(&mut key, &[*height_or_time as u64]);
+                }
+            }
+        }
+
+        write_u64s(&mut key, &remap(&self.output));
+        self.write_registry_fingerprint(&mut key);
+        key
+    }
+
+    fn write_registry_fingerprint(&self, key: &mut Vec<u8>) {
+        // the generators are only known by their names, so the rest of the metadata is what a
+        // change of the registries shows up in
+        let data_types = self
+            .memory
+            .values()
+            .map(|entry| entry.data_type.as_str())
+            .collect::<BTreeSet<&str>>();
+        write_u64s(key, &[data_types.len() as u64]);
+        for name in data_types {
+            write_bytes(key, name.as_bytes());
+            match self.data_type_registry.map.get(name) {
+                Some(metadata) => {
+                    key.push(1);
+                    let (element_tag, element_len) = match metadata.element_type {
+                        ElementType::Num => (0, 1),
+                        ElementType::ManyNum(len) => (1, len),
+                        ElementType::Str => (2, 1),
+                        ElementType::ManyStr(len) => (3, len),
+                    };
+                    let (encoding_tag, width) = match metadata.num_encoding {
+                        NumEncoding::Minimal => (0, 0),
+                        NumEncoding::FixedWidth(width) => (1, width),
+                    };
+                    key.extend_from_slice(&[
+                        element_tag,
+                        encoding_tag,
+                        metadata.validator.is_some() as u8,
+                        metadata.encoder.is_some() as u8,
+                        metadata.decoder.is_some() as u8,
+                        metadata.ref_only as u8,
+                    ]);
+                    write_u64s(key, &[element_len as u64, width as u64]);
+                }
+                None => key.push(0),
+            }
+        }
+
+        let function_names = self
+            .trace
+            .iter()
+            .filter_map(|trace_entry| match trace_entry {
+                TraceEntry::FunctionCall(function_name, _)
+                | TraceEntry::FunctionCallWithOptions(function_name, _, _) => Some(function_name.as_str()),
+                _ => None,
+            })
+            .collect::<BTreeSet<&str>>();
+        write_u64s(key, &[function_names.len() as u64]);
+        for name in function_names {
+            write_bytes(key, name.as_bytes());
+            let (input, output, range_checked_outputs, max_internal_stack, prologue_script) =
+                match self.function_registry.map.get(name) {
+                    Some(AcceptableFunctionMetadata::FunctionWithoutOptions(v)) => {
+                        key.push(1);
+                        (&v.input, &v.output, &v.range_checked_outputs, v.max_internal_stack, v.prologue_script)
+                    }
+                    Some(AcceptableFunctionMetadata::FunctionWithOptions(v)) => {
+                        key.push(2);
+                        key.push(v.output_fn.is_some() as u8);
+                        write_options(key, &v.default_options);
+                        (&v.input, &v.output, &v.range_checked_outputs, v.max_internal_stack, v.prologue_script)
+                    }
+                    None => {
+                        key.push(0);
+                        continue;
+                    }
+                };
+            for types in [input, output] {
+                write_u64s(key, &[types.len() as u64]);
+                for data_type in types.iter() {
+                    write_bytes(key, data_type.as_bytes());
+                }
+            }
+            write_u64s(key, &range_checked_outputs.iter().map(|&i| i as u64).collect::<Vec<u64>>());
+            match max_internal_stack {
+                Some(max_internal_stack) => write_u64s(key, &[max_internal_stack as u64]),
+                None => write_u64s(key, &[]),
+            }
+            match prologue_script {
+                Some(prologue_script) => {
+                    key.push(1);
+                    write_bytes(key, prologue_script().as_bytes());
+                }
+                None => key.push(0),
+            }
+        }
+    }
+
+    pub fn check_outputs_produced(&self) -> Result<()> {
+        // an entry put in the memory by hand, as a reserved slot, is not produced by the trace
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let produced = self.trace_outputs().concat().into_iter().collect::<HashSet<usize>>();
+        for &idx in self.output.iter() {
+            if idx >= num_inputs && !produced.contains(&idx) {
+                return Err(Error::msg(format!(
+                    "The program output {} is not an input or produced by a trace entry",
+                    idx
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_allocated(&self, idxs: &[usize]) -> Result<()> {
+        for idx in idxs.iter() {
+            if !self.memory.contains_key(idx) {
+                return Err(Error::msg(
+                    "The trace references a memory entry that has not been allocated",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn remap_idxs(map: &HashMap<usize, usize>, idxs: &[usize]) -> Result<Vec<usize>> {
+    idxs.iter()
+        .map(|idx| {
+            map.get(idx).copied().ok_or(Error::msg(
+                "The value is not available at this point of the reordered program",
+            ))
+        })
+        .collect()
+}
+
+fn write_bytes(key: &mut Vec<u8>, bytes: &[u8]) {
+    key.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    key.extend_from_slice(bytes);
+}
+
+fn write_u64s(key: &mut Vec<u8>, values: &[u64]) {
+    key.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    for value in values.iter() {
+        key.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn write_element(key: &mut Vec<u8>, element: &Element) {
+    match element {
+        Element::Num(v) => {
+            key.push(0);
+            key.extend_from_slice(&v.to_le_bytes());
+        }
+        Element::ManyNum(v) => {
+            key.push(1);
+            write_u64s(key, &v.iter().map(|&vv| vv as u32 as u64).collect::<Vec<u64>>());
+        }
+        Element::Str(v) => {
+            key.push(2);
+            write_bytes(key, v);
+        }
+        Element::ManyStr(v) => {
+            key.push(3);
+            write_u64s(key, &[v.len() as u64]);
+            for vv in v.iter() {
+                write_bytes(key, vv);
+            }
+        }
+    }
+}
+
+fn write_options(key: &mut Vec<u8>, options: &Options) {
+    // the entries of the options are in a hash map, so they are sorted by their names first
+    let mut entries = options.map.iter().collect::<Vec<(&String, &OptionsEntry)>>();
+    entries.sort_by_key(|(name, _)| *name);
+    write_u64s(key, &[entries.len() as u64]);
+    for (name, entry) in entries {
+        write_bytes(key, name.as_bytes());
+        match entry {
+            OptionsEntry::String(v) => {
+                key.push(0);
+                write_bytes(key, v.as_bytes());
+            }
+            OptionsEntry::Binary(v) => {
+                key.push(1);
+                write_bytes(key, v);
+            }
+            OptionsEntry::MultiBinary(v) => {
+                key.push(2);
+                write_u64s(key, &[v.len() as u64]);
+                for vv in v.iter() {
+                    write_bytes(key, vv);
+                }
+            }
+            OptionsEntry::U32(v) => {
+                key.push(3);
+                write_u64s(key, &[*v as u64]);
+            }
+            OptionsEntry::MultiU32(v) => {
+                key.push(4);
+                write_u64s(key, &v.iter().map(|&vv| vv as u64).collect::<Vec<u64>>());
+            }
+            OptionsEntry::U64(v) => {
+                key.push(5);
+                write_u64s(key, &[*v]);
+            }
+            OptionsEntry::MultiU64(v) => {
+                key.push(6);
+                write_u64s(key, v);
+            }
+        }
+    }
+}
+
+pub fn trace_diff(a: &DSL, b: &DSL) -> Option<usize> {
+    // the first trace step that differs, where a trace that ends early differs at its end
+    match a.trace.iter().zip(b.trace.iter()).position(|(x, y)| x != y) {
+        Some(step) => Some(step),
+        None if a.trace.len() != b.trace.len() => Some(a.trace.len().min(b.trace.len())),
+        None => None,
+    }
+}
+
+fn handle_output(dsl: &mut DSL, output_types: &[&str], new_elements: Vec<MemoryEntry>) -> Result<Vec<usize>> {
+    let mut outputs = vec![];
+    for (&output_type, entry) in output_types.iter().zip(new_elements) {
+        // an output declared as `any` takes the type of the element that the trace generator returns
+        if output_type != "any" && output_type != entry.data_type {
+            return Err(Error::msg("The output data type mismatches"));
+        }
+        let data_type_metadata = dsl
+            .data_type_registry
+            .map
+            .get(&entry.data_type)
+            .ok_or(Error::msg("The output data type has not been registered"))?;
+        if !entry.data.match_type(&data_type_metadata.element_type) {
+            return Err(Error::msg(
+                "The output data does not match the type definitions",
+            ));
+        }
+        if let Some(validator) = data_type_metadata.validator {
+            validator(&entry.data)?;
+        }
+        dsl.check_element_width(&entry.data)?;
+
+        let idx = dsl.memory_last_idx;
+        dsl.memory_last_idx += 1;
+        dsl.memory.insert(idx, entry);
+        outputs.push(idx);
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::{
+        trace_diff, Element, ElementType, MemoryEntry, TraceEntry, TypeConfusion, DSL,
+    };
+    use crate::functions::{
+        AcceptableFunctionMetadata, FunctionEffect, FunctionMetadata, FunctionOutput,
+        FunctionWithOptionsMetadata,
+    };
+    use crate::options::Options;
+    use crate::{simulate, test_program};
+    use crate::treepp::*;
+    use anyhow::{Error, Result};
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    fn add(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        let b = dsl.get_num(inputs[1])?;
+
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a + b))],
+            new_hints: vec![],
+        })
+    }
+
+    fn add_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {
+            OP_ADD
+        })
+    }
+
+    pub(crate) fn new_dsl_with_add() -> DSL {
+        let mut dsl = DSL::new();
+        dsl.add_data_type("num", ElementType::Num).unwrap();
+        dsl.add_function(
+            "add",
+            FunctionMetadata::new(add, add_gadget, vec!["num", "num"], vec!["num"]),
+        )
+        .unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(100)).unwrap();
+        let d = dsl.execute("add", &[a, b]).unwrap()[0];
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+        assert_eq!(dsl.get_num(e).unwrap(), 103);
+
+        let mut rebuilt = dsl
+            .replay_with_inputs(vec![
+                MemoryEntry::new("num", Element::Num(10)),
+                MemoryEntry::new("num", Element::Num(20)),
+            ])
+            .unwrap();
+        assert_eq!(rebuilt.get_num(d).unwrap(), 30);
+        assert_eq!(rebuilt.get_num(e).unwrap(), 130);
+        assert_eq!(rebuilt.output, vec![e]);
+
+        test_program(rebuilt, script! { 130 }).unwrap();
+
+        // a different number of inputs shifts every index and is rejected
+        assert!(dsl
+            .replay_with_inputs(vec![MemoryEntry::new("num", Element::Num(10))])
+            .is_err());
+    }
+
+    static NOISE: AtomicI32 = AtomicI32::new(0);
+
+    fn noisy(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        // the output and the hint depend on how many times the generator has run before
+        let a = dsl.get_num(inputs[0])?;
+        let noise = NOISE.fetch_add(1, Ordering::Relaxed);
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a + noise))],
+            new_hints: vec![MemoryEntry::new("num", Element::Num(noise))],
+        })
+    }
+
+    fn noisy_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {
+            OP_DEPTH OP_1SUB OP_ROLL OP_DROP
+        })
+    }
+
+    #[test]
+    fn test_assert_hints_determined() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let h = dsl.alloc_hint("num", Element::Num(3)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, h]).unwrap()[0];
+        dsl.set_program_output("num", d).unwrap();
+        dsl.assert_hints_determined().unwrap();
+
+        dsl.add_function(
+            "noisy",
+            FunctionMetadata::new(noisy, noisy_gadget, vec!["num"], vec!["num"]),
+        )
+        .unwrap();
+        let e = dsl.execute("noisy", &[d]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+        assert!(dsl.assert_hints_determined().is_err());
+    }
+
+    fn checked_copy(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a))],
+            new_hints: vec![MemoryEntry::new("num", Element::Num(a))],
+        })
+    }
+
+    fn checked_copy_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {
+            OP_DEPTH OP_1SUB OP_ROLL OP_OVER OP_EQUALVERIFY
+        })
+    }
+
+    #[test]
+    fn test_hint_consumers() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "checked_copy",
+            FunctionMetadata::new(checked_copy, checked_copy_gadget, vec!["num"], vec!["num"]),
+        )
+        .unwrap();
+
+        let x = dsl.alloc_input("num", Element::Num(4)).unwrap();
+        let h = dsl.alloc_hint("num", Element::Num(5)).unwrap();
+        let y = dsl.execute("checked_copy", &[x]).unwrap()[0];
+        let z = dsl.execute("add", &[y, h]).unwrap()[0];
+        let w = dsl.execute("checked_copy", &[z]).unwrap()[0];
+        dsl.set_program_output("num", w).unwrap();
+
+        assert_eq!(dsl.hint_consumers(), vec![(0, 0), (1, 1), (2, 3)]);
+        test_program(dsl, script! { 9 }).unwrap();
+    }
+
+    fn validate_pubkey(data: &Element) -> Result<()> {
+        match data {
+            Element::Str(v) if v.len() == 33 && (v[0] == 0x02 || v[0] == 0x03) => Ok(()),
+            _ => Err(Error::msg("The value is not a compressed public key")),
+        }
+    }
+
+    fn copy_str(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let mut v = dsl.get_str(inputs[0])?.to_vec();
+        v[0] = 0x04;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("pubkey", Element::Str(v))],
+            new_hints: vec![],
+        })
+    }
+
+    fn copy_str_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {})
+    }
+
+    #[test]
+    fn test_data_type_validator() {
+        let mut dsl = DSL::new();
+        dsl.add_data_type_with_validator("pubkey", ElementType::Str, validate_pubkey)
+            .unwrap();
+        dsl.add_function(
+            "to_uncompressed",
+            FunctionMetadata::new(copy_str, copy_str_gadget, vec!["pubkey"], vec!["pubkey"]),
+        )
+        .unwrap();
+
+        let mut pubkey = vec![0x02];
+        pubkey.extend_from_slice(&[0x11; 32]);
+        let a = dsl.alloc_input("pubkey", Element::Str(pubkey.clone())).unwrap();
+
+        pubkey[0] = 0x04;
+        assert!(dsl.alloc_input("pubkey", Element::Str(pubkey)).is_err());
+        assert!(dsl.alloc_input("pubkey", Element::Str(vec![0x02; 32])).is_err());
+
+        // values computed by functions are checked as well
+        assert!(dsl.execute("to_uncompressed", &[a]).is_err());
+    }
+
+    #[test]
+    fn test_list_functions() {
+        let names = ["sub", "mul", "add2", "neg", "div"];
+        for _ in 0..3 {
+            let mut dsl = new_dsl_with_add();
+            for name in names.iter() {
+                dsl.add_function(
+                    *name,
+                    FunctionMetadata::new(add, add_gadget, vec!["num", "num"], vec!["num"]),
+                )
+                .unwrap();
+            }
+            assert_eq!(dsl.list_functions(), vec!["add", "sub", "mul", "add2", "neg", "div"]);
+        }
+    }
+
+    #[test]
+    fn test_intern_constant() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let h = dsl.intern_constant("num", Element::Num(1000)).unwrap();
+        assert_eq!(dsl.intern_constant("num", Element::Num(1000)).unwrap(), h);
+        assert_ne!(dsl.intern_constant("num", Element::Num(2000)).unwrap(), h);
+        assert!(dsl.intern_constant("str", Element::Num(1000)).is_err());
+
+        let b = dsl.execute("add", &[a, h.idx()]).unwrap()[0];
+        let c = dsl.execute("add", &[b, h.idx()]).unwrap()[0];
+        let d = dsl.execute("add", &[c, h.idx()]).unwrap()[0];
+        assert_eq!(dsl.get_num(d).unwrap(), 3001);
+        dsl.set_program_output("num", d).unwrap();
+
+        // the constant is pushed once, and picked by the first two calls
+        let program = Compiler::compiler(dsl).unwrap();
+        let push = script! { 1000 }.to_bytes();
+        let script = program.script.as_bytes();
+        let num_pushes = script.windows(push.len()).filter(|w| *w == push.as_slice()).count();
+        assert_eq!(num_pushes, 1);
+        simulate(&program).unwrap();
+    }
+
+    #[test]
+    fn test_canonical_key() {
+        fn build(swapped: bool, name: &str) -> DSL {
+            let mut dsl = new_dsl_with_add();
+            let (a, b) = if swapped {
+                let b = dsl.alloc_input("num", Element::Num(20)).unwrap();
+                let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+                (a, b)
+            } else {
+                let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+                let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+                (a, b)
+            };
+            dsl.set_name(a, name).unwrap();
+            let c = dsl.alloc_constant("num", Element::Num(100)).unwrap();
+            let d = dsl.execute("add", &[b, a]).unwrap()[0];
+            let e = dsl.execute("add", &[d, c]).unwrap()[0];
+            dsl.set_program_output("num", e).unwrap();
+            dsl
+        }
+
+        // the values of the inputs and their names do not matter
+        let key = build(false, "a").canonical_key();
+        assert_eq!(build(false, "x").canonical_key(), key);
+
+        // the order of the inputs changes the witness layout, unless they are put in order first
+        assert_ne!(build(true, "a").canonical_key(), key);
+        let mut dsl = build(false, "a");
+        dsl.canonicalize_inputs().unwrap();
+        let mut swapped = build(true, "a");
+        swapped.canonicalize_inputs().unwrap();
+        assert_eq!(swapped.canonical_key(), dsl.canonical_key());
+
+        // but the constants do
+        let mut dsl = build(false, "a");
+        dsl.alloc_constant("num", Element::Num(7)).unwrap();
+        assert_ne!(dsl.canonical_key(), key);
+
+        // and so do the registered types and functions that the program uses
+        let mut dsl = build(false, "a");
+        dsl.data_type_registry.map.get_mut("num").unwrap().ref_only = true;
+        assert_ne!(dsl.canonical_key(), key);
+        let mut dsl = build(false, "a");
+        match dsl.function_registry.map.get_mut("add").unwrap() {
+            AcceptableFunctionMetadata::FunctionWithoutOptions(v) => v.max_internal_stack = Some(1),
+            AcceptableFunctionMetadata::FunctionWithOptions(_) => unreachable!(),
+        }
+        assert_ne!(dsl.canonical_key(), key);
+        let mut dsl = build(false, "a");
+        dsl.add_data_type("unused", ElementType::Str).unwrap();
+        assert_eq!(dsl.canonical_key(), key);
+    }
+
+    #[test]
+    fn test_type_confusion_report() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("scalar", ElementType::Num).unwrap();
+        dsl.add_data_type("str", ElementType::Str).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let s = dsl.alloc_input("scalar", Element::Num(2)).unwrap();
+        let t = dsl.alloc_input("str", Element::Str(vec![3])).unwrap();
+        dsl.execute("add", &[a, a]).unwrap();
+        assert!(dsl.execute("add", &[a, s]).is_err());
+        assert!(dsl.type_confusion_report().is_empty());
+
+        // a trace that is edited is not checked by `execute`
+        dsl.trace.push(TraceEntry::FunctionCall("add".to_string(), vec![s, t]));
+        assert_eq!(
+            dsl.type_confusion_report(),
+            vec![
+                TypeConfusion {
+                    step: 1,
+                    function_name: "add".to_string(),
+                    input: 0,
+                    expected: "num".to_string(),
+                    actual: "scalar".to_string(),
+                    structurally_equal: true,
+                },
+                TypeConfusion {
+                    step: 1,
+                    function_name: "add".to_string(),
+                    input: 1,
+                    expected: "num".to_string(),
+                    actual: "str".to_string(),
+                    structurally_equal: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_annotations() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        dsl.execute("add", &[a, a]).unwrap();
+        let d = dsl.execute("add", &[c, c]).unwrap()[0];
+        dsl.set_program_output("num", d).unwrap();
+
+        dsl.annotate(2, "source", "main.rs:12").unwrap();
+        dsl.annotate(2, "pass", "unrolled").unwrap();
+        assert!(dsl.annotate(3, "source", "main.rs:13").is_err());
+
+        // the annotations follow their entry when the dead entry before it is removed
+        dsl.eliminate_dead_code().unwrap();
+        assert!(dsl.get_annotations(2).is_none());
+        let annotations = dsl.get_annotations(1).unwrap();
+        assert_eq!(annotations.get("source").unwrap(), "main.rs:12");
+        assert_eq!(annotations.len(), 2);
+
+        assert!(dsl.to_listing().contains("add(%2, %2)  # pass = unrolled, source = main.rs:12"));
+        let program = Compiler::compiler(dsl).unwrap();
+        assert_eq!(
+            program.to_artifact_json()["annotations"]["1"]["source"],
+            "main.rs:12"
+        );
+    }
+
+    #[test]
+    fn test_function_effects() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("pair", ElementType::ManyNum(2)).unwrap();
+        dsl.add_function(
+            "split",
+            FunctionMetadata {
+                max_internal_stack: Some(4),
+                ..FunctionMetadata::new(
+                    add,
+                    add_gadget,
+                    vec!["&num", "pair"],
+                    vec!["num", "num", "num"],
+                )
+            },
+        )
+        .unwrap();
+        dsl.add_function(
+            "anything",
+            FunctionMetadata::new(add, add_gadget, vec!["any"], vec!["num"]),
+        )
+        .unwrap();
+
+        let effects = dsl.function_effects();
+        assert_eq!(effects.len(), 3);
+        assert_eq!(effects[0].name, "add");
+        assert_eq!(effects[0].consumed, Some(2));
+        assert_eq!(effects[0].net_delta, Some(-1));
+        assert_eq!(
+            effects[1],
+            FunctionEffect {
+                name: "split".to_string(),
+                consumed: Some(2),
+                produced: Some(3),
+                net_delta: Some(1),
+                max_internal_stack: Some(4),
+            }
+        );
+        assert_eq!(effects[2].consumed, None);
+        assert_eq!(effects[2].produced, Some(1));
+        assert_eq!(effects[2].net_delta, None);
+    }
+
+    fn double_add(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        // the script reuses that of the add function
+        let a = dsl.get_num(inputs[0])?;
+        let b = dsl.get_num(inputs[1])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(2 * (a + b)))],
+            new_hints: vec![],
+        })
+    }
+
+    fn double_add_gadget(_: &[usize]) -> Result<Script> {
+        let add = add_gadget(&[])?;
+        Ok(script! {
+            { add.clone() } OP_DUP { add }
+        })
+    }
+
+    #[test]
+    fn test_gadget_dependency_graph() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "double_add",
+            FunctionMetadata {
+                calls: vec!["add"],
+                ..FunctionMetadata::new(
+                    double_add,
+                    double_add_gadget,
+                    vec!["num", "num"],
+                    vec!["num"],
+                )
+            },
+        )
+        .unwrap();
+
+        let graph = dsl.gadget_dependency_graph();
+        assert_eq!(graph.len(), 2);
+        assert_eq!(graph["add"], Vec::<String>::new());
+        assert_eq!(graph["double_add"], vec!["add".to_string()]);
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("double_add", &[a, b]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        test_program(dsl, script! { 6 }).unwrap();
+    }
+
+    #[test]
+    fn test_list_data_types() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_ref_only_data_type("table", ElementType::ManyNum(16)).unwrap();
+        assert_eq!(
+            dsl.list_data_types(),
+            vec![("num".to_string(), 1, false), ("table".to_string(), 16, true)]
+        );
+        dsl.validate_registry().unwrap();
+
+        dsl.add_function(
+            "add_table",
+            FunctionMetadata::new(add, add_gadget, vec!["num", "table"], vec!["num"]),
+        )
+        .unwrap();
+        assert!(dsl.validate_registry().is_err());
+    }
+
+    #[test]
+    fn test_data_type_len() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("point", ElementType::ManyNum(3)).unwrap();
+        assert_eq!(dsl.data_type_len("num").unwrap(), 1);
+        assert_eq!(dsl.data_type_len("point").unwrap(), 3);
+        assert!(dsl.data_type_len("str").is_err());
+    }
+
+    #[test]
+    fn test_trace_diff() {
+        let build = |b_val: i32, c_val: i32| {
+            let mut dsl = new_dsl_with_add();
+            let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+            let b = dsl.alloc_constant("num", Element::Num(b_val)).unwrap();
+            let c = dsl.execute("add", &[a, b]).unwrap()[0];
+            let d = dsl.alloc_constant("num", Element::Num(c_val)).unwrap();
+            dsl.execute("add", &[c, d]).unwrap();
+            dsl
+        };
+
+        // the values are not part of the trace, only the operations are
+        assert_eq!(trace_diff(&build(2, 3), &build(5, 6)), None);
+
+        let mut longer = build(2, 3);
+        longer.execute("add", &[0, 1]).unwrap();
+        assert_eq!(trace_diff(&build(2, 3), &longer), Some(4));
+
+        let mut swapped = new_dsl_with_add();
+        let a = swapped.alloc_input("num", Element::Num(1)).unwrap();
+        let b = swapped.alloc_constant("num", Element::Num(2)).unwrap();
+        swapped.execute("add", &[b, a]).unwrap();
+        assert_eq!(trace_diff(&build(2, 3), &swapped), Some(1));
+    }
+
+    #[test]
+    fn test_peak_live_values() {
+        let mut dsl = new_dsl_with_add();
+
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y1 = dsl.execute("add", &[x, x]).unwrap()[0];
+        let y2 = dsl.execute("add", &[x, x]).unwrap()[0];
+        let y3 = dsl.execute("add", &[y1, y2]).unwrap()[0];
+        dsl.set_program_output("num", y3).unwrap();
+
+        // x, y1, and y2 are all live when y2 is computed
+        assert_eq!(dsl.peak_live_values(), 3);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_constant("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        dsl.execute("add", &[c, c]).unwrap();
+
+        // inputs are not part of the trace, but constants are
+        assert_eq!(dsl.trace().len(), 3);
+        assert_eq!(dsl.trace_len(), 3);
+        assert!(matches!(dsl.trace()[0], TraceEntry::AllocatedConstant(idx) if idx == b));
+    }
+
+    #[test]
+    fn test_embed_script() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(3)).unwrap();
+
+        // a fragment compiled elsewhere: (a, b) => (a - b, 2 * b)
+        let fragment = script! {
+            OP_DUP OP_ROT OP_SWAP OP_SUB OP_SWAP OP_DUP OP_ADD
+        };
+        let res = dsl
+            .embed_script(
+                fragment,
+                &[a, b],
+                vec![("num", Element::Num(7)), ("num", Element::Num(6))],
+            )
+            .unwrap();
+        let c = dsl.execute("add", &[res[0], a]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        dsl.set_program_output("num", res[1]).unwrap();
+
+        assert!(dsl
+            .embed_script(script! { OP_DUP }, &[a], vec![("field", Element::Num(10))])
+            .is_err());
+
+        test_program(dsl, script! { 17 6 }).unwrap();
+    }
+
+    #[test]
+    fn test_timelocks() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+        dsl.set_program_output("num", b).unwrap();
+
+        let without_timelocks = Compiler::compiler(dsl.clone()).unwrap();
+
+        dsl.require_relative_timelock(144);
+        dsl.require_absolute_timelock(800000);
+        assert_eq!(dsl.trace_len(), 3);
+
+        // the checks are at the start, and leave the rest of the script unchanged
+        let program = Compiler::compiler(dsl).unwrap();
+        let expected = [
+            0x02, 0x90, 0x00, 0xb2, 0x69, // 144 OP_CSV OP_VERIFY
+            0x03, 0x00, 0x35, 0x0c, 0xb1, 0x69, // 800000 OP_CLTV OP_VERIFY
+        ];
+        assert_eq!(&program.script.as_bytes()[..expected.len()], expected);
+        assert_eq!(&program.script.as_bytes()[expected.len()..], without_timelocks.script.as_bytes());
+        assert_eq!(
+            program.report.max_stack_depth,
+            without_timelocks.report.max_stack_depth
+        );
+
+        // a time-based relative lock time sets the type flag of BIP 68
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+        dsl.require_relative_lock_time(bitcoin::relative::LockTime::from_512_second_intervals(10));
+        assert_eq!(dsl.trace[0], TraceEntry::RelativeTimelock((1 << 22) | 10));
+        let program = Compiler::compiler(dsl).unwrap();
+        let expected = [0x03, 0x0a, 0x00, 0x40, 0xb2, 0x69]; // 0x40000a OP_CSV OP_VERIFY
+        assert_eq!(&program.script.as_bytes()[..expected.len()], expected);
+    }
+
+    fn split(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+        let s = dsl.get_str(inputs[0])?.to_vec();
+        let parts = options.get_u32("parts")? as usize;
+        let part_len = s.len().div_ceil(parts);
+
+        let new_elements = (0..parts)
+            .map(|i| {
+                let part = s.iter().skip(i * part_len).take(part_len).copied().collect();
+                MemoryEntry::new("str", Element::Str(part))
+            })
+            .collect::<Vec<MemoryEntry>>();
+        Ok(FunctionOutput {
+            new_hints: new_elements.clone(),
+            new_elements,
+        })
+    }
+
+    fn split_gadget(_: &[usize], options: &Options) -> Result<Script> {
+        // take the parts from the hints, and check that they concatenate to the input
+        let parts = options.get_u32("parts")? as usize;
+        Ok(script! {
+            for _ in 0..parts {
+                OP_DEPTH OP_1SUB OP_ROLL
+            }
+            for _ in 0..parts {
+                { parts - 1 } OP_PICK
+            }
+            for _ in 1..parts {
+                OP_CAT
+            }
+            { parts + 1 } OP_ROLL OP_EQUALVERIFY
+        })
+    }
+
+    fn split_output(options: &Options) -> Vec<&'static str> {
+        vec!["str"; options.get_u32("parts").unwrap() as usize]
+    }
+
+    #[test]
+    fn test_output_fn() {
+        for parts in 2..4 {
+            let mut dsl = DSL::new();
+            dsl.add_data_type("str", ElementType::Str).unwrap();
+            dsl.add_function(
+                "split",
+                FunctionWithOptionsMetadata {
+                    output_fn: Some(split_output),
+                    ..FunctionWithOptionsMetadata::new(split, split_gadget, vec!["str"], vec![])
+                },
+            )
+            .unwrap();
+
+            let s = dsl.alloc_input("str", Element::Str(b"abcdef".to_vec())).unwrap();
+            let res = dsl
+                .execute_with_options("split", &[s], &Options::new().with_u32("parts", parts))
+                .unwrap();
+            assert_eq!(res.len(), parts as usize);
+            for &idx in res.iter() {
+                dsl.set_program_output("str", idx).unwrap();
+            }
+
+            let expected = if parts == 2 {
+                script! { { b"abc".to_vec() } { b"def".to_vec() } }
+            } else {
+                script! { { b"ab".to_vec() } { b"cd".to_vec() } { b"ef".to_vec() } }
+            };
+            test_program(dsl, expected).unwrap();
+        }
+    }
+
+    fn affine(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        let factor = options.get_u32("factor")? as i32;
+        let offset = options.get_u32("offset")? as i32;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a * factor + offset))],
+            new_hints: vec![],
+        })
+    }
+
+    fn affine_gadget(_: &[usize], options: &Options) -> Result<Script> {
+        let factor = options.get_u32("factor")?;
+        let offset = options.get_u32("offset")? as i32;
+        Ok(script! {
+            OP_0
+            for _ in 0..factor {
+                OP_OVER OP_ADD
+            }
+            OP_NIP { offset } OP_ADD
+        })
+    }
+
+    #[test]
+    fn test_default_options() {
+        let options = Options::new().with_u32("offset", 5).with_defaults(
+            &Options::new().with_u32("factor", 2).with_u32("offset", 1),
+        );
+        assert_eq!(options.get_u32("factor").unwrap(), 2);
+        assert_eq!(options.get_u32("offset").unwrap(), 5);
+
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "affine",
+            FunctionWithOptionsMetadata {
+                default_options: Options::new().with_u32("factor", 2).with_u32("offset", 1),
+                ..FunctionWithOptionsMetadata::new(
+                    affine,
+                    affine_gadget,
+                    vec!["num"],
+                    vec!["num"],
+                )
+            },
+        )
+        .unwrap();
+
+        // the first call takes both defaults, and the second one overrides the offset
+        let x = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let y = dsl.execute("affine", &[x]).unwrap()[0];
+        let z = dsl
+            .execute_with_options("affine", &[y], &Options::new().with_u32("offset", 5))
+            .unwrap()[0];
+        assert_eq!(dsl.get_num(y).unwrap(), 21);
+        assert_eq!(dsl.get_num(z).unwrap(), 47);
+
+        dsl.set_program_output("num", z).unwrap();
+        test_program(dsl, script! { 47 }).unwrap();
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_constant("num", Element::Num(10)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.alloc_constant("num", Element::Num(20)).unwrap();
+        dsl.execute("add", &[c, d]).unwrap();
+
+        let constants = dsl
+            .constants()
+            .map(|(idx, entry)| (idx, entry.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(constants, vec![(b, Element::Num(10)), (d, Element::Num(20))]);
+    }
+
+    #[test]
+    fn test_validate_registry() {
+        let mut dsl = new_dsl_with_add();
+        dsl.validate_registry().unwrap();
+
+        dsl.add_function(
+            "add_ref",
+            FunctionMetadata::new(add, add_gadget, vec!["&num", "any"], vec!["num"]),
+        )
+        .unwrap();
+        dsl.validate_registry().unwrap();
+
+        dsl.add_function(
+            "to_field",
+            FunctionMetadata::new(add, add_gadget, vec!["num", "num"], vec!["field"]),
+        )
+        .unwrap();
+        let err = dsl.validate_registry().unwrap_err();
+        assert!(err.to_string().contains("to_field"));
+        assert!(err.to_string().contains("field"));
+    }
+
+    #[test]
+    fn test_debug_assert_value() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+
+        assert!(dsl.debug_assert_value(b, Element::Num(2)).is_ok());
+
+        let err = dsl.debug_assert_value(b, Element::Num(3)).unwrap_err();
+        assert!(err.to_string().contains("Num(2)"));
+        assert!(err.to_string().contains("Num(3)"));
+    }
+
+    fn spread(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("wide", Element::ManyNum(vec![a; 16]))],
+            new_hints: vec![],
+        })
+    }
+
+    fn spread_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {
+            for _ in 0..15 {
+                OP_DUP
+            }
+        })
+    }
+
+    #[test]
+    fn test_max_element_width() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("narrow", ElementType::ManyNum(4)).unwrap();
+        dsl.add_data_type("wide", ElementType::ManyNum(16)).unwrap();
+        dsl.add_function(
+            "spread",
+            FunctionMetadata::new(spread, spread_gadget, vec!["num"], vec!["wide"]),
+        )
+        .unwrap();
+        dsl.set_max_element_width(8);
+
+        let err = dsl
+            .alloc_input("wide", Element::ManyNum(vec![0; 16]))
+            .unwrap_err();
+        assert!(err.to_string().contains("exceeds the maximum element width of 8"));
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.alloc_input("narrow", Element::ManyNum(vec![0; 4])).unwrap();
+        assert!(dsl.alloc_constant("wide", Element::ManyNum(vec![0; 16])).is_err());
+        assert!(dsl.execute("spread", &[a]).is_err());
+
+        // a rejected value takes no index
+        let b = dsl.execute("add", &[a, a]).unwrap()[0];
+        assert_eq!(b, 2);
+    }
+
+    #[test]
+    fn test_add_data_type_family() {
+        let mut dsl = new_dsl_with_add();
+        let names = dsl.add_data_type_family("fq", ElementType::Num, &[2, 4]).unwrap();
+        assert_eq!(names, vec!["fq2", "fq4"]);
+        assert_eq!(dsl.data_type_len("fq4").unwrap(), 4);
+
+        assert!(dsl.add_data_type_family("fq", ElementType::Num, &[8, 4]).is_err());
+        assert!(dsl.data_type_len("fq8").is_err());
+        assert!(dsl
+            .add_data_type_family("pair", ElementType::ManyNum(2), &[2])
+            .is_err());
+
+        // the counts are checked before registering any of the types
+        assert!(dsl.add_data_type_family("fr", ElementType::Num, &[3, 0]).is_err());
+        assert!(dsl.add_data_type_family("fr", ElementType::Num, &[3, 5, 3]).is_err());
+        assert!(dsl.data_type_len("fr3").is_err());
+        assert!(dsl.data_type_len("fr5").is_err());
+
+        let a = dsl.alloc_input("fq2", Element::ManyNum(vec![1, 2])).unwrap();
+        let b = dsl.alloc_input("fq4", Element::ManyNum(vec![3, 4, 5, 6])).unwrap();
+        assert!(dsl.alloc_input("fq4", Element::ManyNum(vec![3, 4])).is_err());
+        dsl.set_program_output("fq4", b).unwrap();
+        dsl.set_program_output("fq2", a).unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+    }
+
+    #[test]
+    fn test_check_outputs_produced() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(2)).unwrap();
+        let b = dsl.execute("add", &[a, c]).unwrap()[0];
+        dsl.set_program_output("num", a).unwrap();
+        dsl.set_program_output("num", c).unwrap();
+        dsl.set_program_output("num", b).unwrap();
+        dsl.check_outputs_produced().unwrap();
+
+        // a slot reserved in the memory, but never filled by the trace
+        let reserved = dsl.memory_last_idx;
+        dsl.memory.insert(reserved, MemoryEntry::new("num", Element::Num(0)));
+        dsl.memory_last_idx += 1;
+        dsl.set_program_output("num", reserved).unwrap();
+
+        let err = dsl.check_outputs_produced().unwrap_err();
+        assert!(err.to_string().contains("The program output 3 is not"));
+        let err = Compiler::compiler(dsl).err().unwrap();
+        assert!(err.to_string().contains("The program output 3 is not"));
+    }
+
+    #[test]
+    fn test_output_aliasing() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("other", ElementType::Num).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+
+        // the same value output twice under its own type is fine, but not under another one
+        dsl.set_program_output("num", a).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+        let err = dsl.set_program_output("other", a).unwrap_err();
+        assert!(err.to_string().contains("the value 0 has the type num, not other"));
+        assert_eq!(dsl.output, vec![a, a]);
+
+        test_program(dsl, script! { 1 1 }).unwrap();
+    }
+
+    #[test]
+    fn test_output_groups() {
+        let mut dsl = new_dsl_with_add();
+        dsl.add_data_type("other", ElementType::Num).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.alloc_input("other", Element::Num(3)).unwrap();
+
+        // a group that disagrees with the type of its value
+        let err = dsl.set_output_groups(&[("num", vec![a]), ("other", vec![b])]).unwrap_err();
+        assert!(err.to_string().contains("The output group of the type other has the value 1 of the type num"));
+
+        // the same value in groups of different types
+        let err = dsl.set_output_groups(&[("num", vec![a, b]), ("other", vec![c, a])]).unwrap_err();
+        assert!(err.to_string().contains("The value 0 is in output groups of the types num and other"));
+        assert!(dsl.output.is_empty());
+
+        dsl.set_output_groups(&[("num", vec![b, a]), ("other", vec![c]), ("num", vec![a])]).unwrap();
+        assert_eq!(dsl.output, vec![b, a, c, a]);
+        test_program(dsl, script! { 2 1 3 1 }).unwrap();
+    }
+
+    #[test]
+    fn test_rebase_indices() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let h = dsl.alloc_hint("num", Element::Num(3)).unwrap();
+        let c = dsl.intern_constant("num", Element::Num(4)).unwrap().idx();
+        let d = dsl.execute("add", &[a, h]).unwrap()[0];
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+        dsl.set_program_output("num", b).unwrap();
+
+        let original = dsl.clone();
+        dsl.rebase_indices(10).unwrap();
+        assert_eq!(dsl.memory_last_idx, original.memory_last_idx + 10);
+        assert_eq!(dsl.num_inputs, Some(12));
+        assert_eq!(dsl.output, vec![e + 10, b + 10]);
+        assert_eq!(dsl.trace[0], TraceEntry::AllocatedHint(h + 10));
+        assert_eq!(dsl.trace[3], TraceEntry::FunctionCall("add".to_string(), vec![d + 10, c + 10]));
+        for (&idx, entry) in original.memory.iter() {
+            let rebased = dsl.memory.get(&(idx + 10)).unwrap();
+            assert_eq!(rebased.data_type, entry.data_type);
+            assert_eq!(rebased.data, entry.data);
+        }
+        assert_eq!(dsl.intern_constant("num", Element::Num(4)).unwrap().idx(), c + 10);
+        assert_eq!(dsl.hint_origins, original.hint_origins);
+
+        // rebasing back gives the original program, which compiles as before
+        let mut rebased = original.clone();
+        rebased.rebase_indices(0).unwrap();
+        assert_eq!(
+            Compiler::compiler(rebased).unwrap().script,
+            Compiler::compiler(original.clone()).unwrap().script
+        );
+
+        let mut overflowing = original.clone();
+        assert!(overflowing.rebase_indices(usize::MAX).is_err());
+        assert_eq!(overflowing.memory_last_idx, original.memory_last_idx);
+        assert_eq!(overflowing.trace, original.trace);
+
+        // merged after a program that fills the indices below the offset, it computes the same
+        let mut low = new_dsl_with_add();
+        low.alloc_input("num", Element::Num(5)).unwrap();
+        low.alloc_input("num", Element::Num(6)).unwrap();
+        low.alloc_input("num", Element::Num(7)).unwrap();
+        let mut rebased = original.clone();
+        rebased.rebase_indices(low.memory_last_idx).unwrap();
+        let inputs = (0..low.memory_last_idx)
+            .map(|idx| low.memory.get(&idx).unwrap().clone())
+            .chain((3..5).map(|idx| rebased.memory.get(&idx).unwrap().clone()))
+            .collect();
+        let merged = DSL::rebuild(
+            rebased.trace.clone(),
+            inputs,
+            rebased.allocations(),
+            rebased.output.clone(),
+            rebased.data_type_registry.clone(),
+            rebased.function_registry.clone(),
+        )
+        .unwrap();
+        assert_eq!(merged.output, vec![e + 3, b + 3]);
+        for (&idx, &expected) in merged.output.iter().zip(original.output.iter()) {
+            assert_eq!(merged.memory.get(&idx).unwrap().data, original.memory.get(&expected).unwrap().data);
+        }
+        test_program(merged, script! { 8 2 }).unwrap();
+
+        // a use before the definition, which the shift alone would keep, is found
+        let mut broken = original.clone();
+        broken.trace.insert(0, TraceEntry::FunctionCall("add".to_string(), vec![d, a]));
+        let err = broken.rebase_indices(3).unwrap_err();
+        assert!(err.to_string().contains("uses the value"));
+        assert_eq!(broken.trace[1], original.trace[0]);
+        assert_eq!(broken.memory_last_idx, original.memory_last_idx);
+
+        // as is an interned constant that is not one
+        let mut broken = original.clone();
+        broken.interned.insert(("num".to_string(), Element::Num(1)), a);
+        let err = broken.rebase_indices(3).unwrap_err();
+        assert!(err.to_string().contains("The interned constant 3"));
+    }
+
+    #[test]
+    fn test_assert_trace_deterministic() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let h = dsl.alloc_hint("num", Element::Num(3)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(4)).unwrap();
+        let d = dsl.execute("add", &[a, h]).unwrap()[0];
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+        dsl.assert_trace_deterministic().unwrap();
+
+        dsl.add_function(
+            "noisy",
+            FunctionMetadata::new(noisy, noisy_gadget, vec!["num"], vec!["num"]),
+        )
+        .unwrap();
+        // the step after the noisy one diverges as well, but the error points to the noisy one
+        let f = dsl.execute("noisy", &[e]).unwrap()[0];
+        dsl.execute("add", &[f, f]).unwrap();
+
+        let err = dsl.assert_trace_deterministic().unwrap_err();
+        assert!(err.to_string().contains("The trace step 4 produces"));
     }
-    Ok(outputs)
 }
@@ -1,10 +1,11 @@
 #[cfg(test)]
 mod test {
+    use crate::compiler::Compiler;
     use crate::dsl::{Element, ElementType, MemoryEntry, DSL};
     use crate::functions::{FunctionMetadata, FunctionOutput};
     use crate::test_program;
     use crate::treepp::*;
-    use anyhow::Result;
+    use anyhow::{Error, Result};
     use bitcoin::ScriptBuf;
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
@@ -37,12 +38,7 @@ mod test {
         dsl.add_data_type("m31", ElementType::Num).unwrap();
         dsl.add_function(
             "m31_mult",
-            FunctionMetadata {
-                trace_generator: m31_mult,
-                script_generator: m31_mult_gadget,
-                input: vec!["m31", "m31"],
-                output: vec!["m31"],
-            },
+            FunctionMetadata::new(m31_mult, m31_mult_gadget, vec!["m31", "m31"], vec!["m31"]),
         ).unwrap();
 
         let mut a = dsl.alloc_input("m31", Element::Num(a_val)).unwrap();
@@ -74,4 +70,157 @@ mod test {
         )
         .unwrap();
     }
+
+    #[test]
+    fn test_m31_schedule_for_stack_depth() {
+        // all the products of the input with the constants are computed before they are multiplied
+        // together, so they are all on the stack at once unless the products are interleaved
+        let mut dsl = DSL::new();
+        dsl.add_data_type("m31", ElementType::Num).unwrap();
+        dsl.add_function(
+            "m31_mult",
+            FunctionMetadata::new(m31_mult, m31_mult_gadget, vec!["m31", "m31"], vec!["m31"]),
+        )
+        .unwrap();
+
+        let x = dsl.alloc_input("m31", Element::Num(12345)).unwrap();
+        let constants = (2..10)
+            .map(|v| dsl.alloc_constant("m31", Element::Num(v)).unwrap())
+            .collect::<Vec<usize>>();
+        let products = constants
+            .iter()
+            .map(|&c| dsl.execute("m31_mult", &[x, c]).unwrap()[0])
+            .collect::<Vec<usize>>();
+        let mut res = products[0];
+        for &product in products[1..].iter() {
+            res = dsl.execute("m31_mult", &[res, product]).unwrap()[0];
+        }
+        dsl.set_program_output("m31", res).unwrap();
+        let expected = dsl.get_num(res).unwrap();
+        assert_eq!(expected as i64, (2..10).fold(12345i64, |acc, v| acc * v % M31));
+
+        let before = Compiler::compiler(dsl.clone()).unwrap();
+        assert_eq!(dsl.peak_live_values(), 10);
+        assert_eq!(before.report.max_stack_depth, 10);
+
+        // each constant is now multiplied as soon as it is pushed, and each product right after,
+        // which also brings the values to the top with shorter moves
+        let mut scheduled = dsl.clone();
+        scheduled.schedule_for_stack_depth().unwrap();
+        let after = Compiler::compiler(scheduled.clone()).unwrap();
+        assert_eq!(scheduled.peak_live_values(), 4);
+        assert_eq!(after.report.max_stack_depth, 4);
+        assert_eq!(before.script.len() - after.script.len(), 10);
+
+        // both report their script size and stack depth in the simulation
+        test_program(dsl, script! { { expected } }).unwrap();
+        test_program(scheduled, script! { { expected } }).unwrap();
+    }
+
+    // A fallible trace generator: the inverses are computed in Rust, which fails if an element is
+    // zero, and are given to the script as hints, so that the script only needs to check them.
+    fn m31_vec_inverse(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_many_num(inputs[0])?.to_vec();
+
+        let mut res = vec![];
+        for &v in a.iter() {
+            if v == 0 {
+                return Err(Error::msg("Zero does not have an inverse in M31"));
+            }
+            res.push(m31_pow(v, M31 - 2));
+        }
+
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("m31x4", Element::ManyNum(res.clone()))],
+            new_hints: res
+                .into_iter()
+                .map(|v| MemoryEntry::new("m31", Element::Num(v)))
+                .collect(),
+        })
+    }
+
+    fn m31_vec_inverse_gadget(_: &[usize]) -> Result<ScriptBuf> {
+        // stack: a_1 ... a_4, after pulling the hints: a_1 ... a_4 b_1 ... b_4
+        Ok(script! {
+            for _ in 0..4 {
+                OP_DEPTH OP_1SUB OP_ROLL
+                OP_DUP 0 { M31 as i32 } OP_WITHIN OP_VERIFY
+            }
+            for k in (1..=4).rev() {
+                OP_DUP { k + 1 } OP_ROLL
+                { rust_bitcoin_m31::m31_mul() }
+                1 OP_EQUALVERIFY
+                OP_TOALTSTACK
+            }
+            for _ in 0..4 {
+                OP_FROMALTSTACK
+            }
+        })
+    }
+
+    const M31: i64 = (1i64 << 31) - 1;
+
+    fn m31_pow(a: i32, mut e: i64) -> i32 {
+        let mut base = a as i64;
+        let mut res = 1i64;
+        while e > 0 {
+            if e & 1 == 1 {
+                res = res * base % M31;
+            }
+            base = base * base % M31;
+            e >>= 1;
+        }
+        res as i32
+    }
+
+    fn new_dsl_with_m31_vec_inverse() -> DSL {
+        let mut dsl = DSL::new();
+        dsl.add_data_type("m31", ElementType::Num).unwrap();
+        dsl.add_data_type("m31x4", ElementType::ManyNum(4)).unwrap();
+        dsl.add_function(
+            "m31_vec_inverse",
+            FunctionMetadata::new(
+                m31_vec_inverse,
+                m31_vec_inverse_gadget,
+                vec!["m31x4"],
+                vec!["m31x4"],
+            ),
+        )
+        .unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_m31_vec_inverse() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut dsl = new_dsl_with_m31_vec_inverse();
+
+        let a_val = (0..4)
+            .map(|_| prng.gen_range(1..M31) as i32)
+            .collect::<Vec<i32>>();
+        let a = dsl.alloc_input("m31x4", Element::ManyNum(a_val.clone())).unwrap();
+
+        let res = dsl.execute("m31_vec_inverse", &[a]).unwrap()[0];
+        let res_val = dsl.get_many_num(res).unwrap().to_vec();
+        for (&x, &y) in a_val.iter().zip(res_val.iter()) {
+            assert_eq!((x as i64) * (y as i64) % M31, 1);
+        }
+        dsl.set_program_output("m31x4", res).unwrap();
+
+        test_program(
+            dsl,
+            script! {
+                for v in res_val {
+                    { v }
+                }
+            },
+        )
+        .unwrap();
+
+        // the trace generator reports that zero is not invertible
+        let mut dsl = new_dsl_with_m31_vec_inverse();
+        let a = dsl.alloc_input("m31x4", Element::ManyNum(vec![1, 2, 0, 3])).unwrap();
+        assert!(dsl.execute("m31_vec_inverse", &[a]).is_err());
+    }
 }
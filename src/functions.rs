@@ -1,26 +1,73 @@
 use crate::dsl::{MemoryEntry, DSL};
 use crate::treepp::Script;
 use anyhow::Result;
-use std::collections::HashMap;
+use indexmap::IndexMap;
 use crate::options::Options;
 
+#[derive(Clone)]
 pub struct FunctionRegistry {
-    pub map: HashMap<String, AcceptableFunctionMetadata>,
+    pub map: IndexMap<String, AcceptableFunctionMetadata>,
 }
 
 impl FunctionRegistry {
     pub fn new() -> Self {
         Self {
-            map: HashMap::new(),
+            map: IndexMap::new(),
         }
     }
 }
 
+#[derive(Clone)]
 pub struct FunctionMetadata {
     pub trace_generator: fn(&mut DSL, &[usize]) -> Result<FunctionOutput>,
+    /// Receives the positions of the ref inputs (`&` types), in their declared order. Ref inputs
+    /// may be declared anywhere among the inputs: the positions are taken after all other inputs
+    /// have been copied to the top, and are relative to the stack below these copies.
     pub script_generator: fn(&[usize]) -> Result<Script>,
     pub input: Vec<&'static str>,
     pub output: Vec<&'static str>,
+    /// the indices of the numeric outputs that are checked when compiling with range checks
+    pub range_checked_outputs: Vec<usize>,
+    /// the peak number of stack elements that the script uses beyond its inputs, if declared
+    pub max_internal_stack: Option<usize>,
+    /// Pushes constants, such as a table, once before the first call of the function in a program.
+    /// They stay on the stack, and every call receives their position after those of the ref inputs.
+    pub prologue_script: Option<fn() -> Script>,
+    /// the functions that the trace generator executes, if it composes other functions
+    pub calls: Vec<&'static str>,
+}
+
+impl FunctionMetadata {
+    /// A function with no range-checked outputs, no declared stack use, no prologue and no calls,
+    /// which the struct update syntax can then override.
+    pub fn new(
+        trace_generator: fn(&mut DSL, &[usize]) -> Result<FunctionOutput>,
+        script_generator: fn(&[usize]) -> Result<Script>,
+        input: Vec<&'static str>,
+        output: Vec<&'static str>,
+    ) -> Self {
+        Self {
+            trace_generator,
+            script_generator,
+            input,
+            output,
+            range_checked_outputs: vec![],
+            max_internal_stack: None,
+            prologue_script: None,
+            calls: vec![],
+        }
+    }
+}
+
+/// The stack effect of a function, where the counts of elements are `None` when they depend on
+/// values of the `any` type or of unregistered types. Ref inputs are not consumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionEffect {
+    pub name: String,
+    pub consumed: Option<usize>,
+    pub produced: Option<usize>,
+    pub net_delta: Option<isize>,
+    pub max_internal_stack: Option<usize>,
 }
 
 pub struct FunctionOutput {
@@ -28,18 +75,82 @@ pub struct FunctionOutput {
     pub new_hints: Vec<MemoryEntry>,
 }
 
+#[derive(Clone)]
 pub struct FunctionWithOptionsMetadata {
     pub trace_generator: fn(&mut DSL, &[usize], &Options) -> Result<FunctionOutput>,
+    /// receives the positions of the ref inputs, as for `FunctionMetadata`
     pub script_generator: fn(&[usize], &Options) -> Result<Script>,
     pub input: Vec<&'static str>,
     pub output: Vec<&'static str>,
+    /// if present, computes the output types from the options, and `output` is ignored
+    pub output_fn: Option<fn(&Options) -> Vec<&'static str>>,
+    pub range_checked_outputs: Vec<usize>,
+    pub max_internal_stack: Option<usize>,
+    pub prologue_script: Option<fn() -> Script>,
+    pub calls: Vec<&'static str>,
+    /// the options that apply when the caller does not set them
+    pub default_options: Options,
 }
 
+impl FunctionWithOptionsMetadata {
+    /// as `FunctionMetadata::new`, with the output types not computed and no default options
+    pub fn new(
+        trace_generator: fn(&mut DSL, &[usize], &Options) -> Result<FunctionOutput>,
+        script_generator: fn(&[usize], &Options) -> Result<Script>,
+        input: Vec<&'static str>,
+        output: Vec<&'static str>,
+    ) -> Self {
+        Self {
+            trace_generator,
+            script_generator,
+            input,
+            output,
+            output_fn: None,
+            range_checked_outputs: vec![],
+            max_internal_stack: None,
+            prologue_script: None,
+            calls: vec![],
+            default_options: Options::new(),
+        }
+    }
+
+    pub fn output_types(&self, options: &Options) -> Vec<&'static str> {
+        match self.output_fn {
+            Some(output_fn) => output_fn(&options.with_defaults(&self.default_options)),
+            None => self.output.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum AcceptableFunctionMetadata {
     FunctionWithoutOptions(FunctionMetadata),
     FunctionWithOptions(FunctionWithOptionsMetadata),
 }
 
+impl AcceptableFunctionMetadata {
+    pub fn output_types(&self, options: &Options) -> Vec<&'static str> {
+        match self {
+            AcceptableFunctionMetadata::FunctionWithoutOptions(v) => v.output.clone(),
+            AcceptableFunctionMetadata::FunctionWithOptions(v) => v.output_types(options),
+        }
+    }
+
+    pub fn calls(&self) -> &[&'static str] {
+        match self {
+            AcceptableFunctionMetadata::FunctionWithoutOptions(v) => &v.calls,
+            AcceptableFunctionMetadata::FunctionWithOptions(v) => &v.calls,
+        }
+    }
+
+    pub fn prologue_script(&self) -> Option<fn() -> Script> {
+        match self {
+            AcceptableFunctionMetadata::FunctionWithoutOptions(v) => v.prologue_script,
+            AcceptableFunctionMetadata::FunctionWithOptions(v) => v.prologue_script,
+        }
+    }
+}
+
 impl Into<AcceptableFunctionMetadata> for FunctionMetadata {
     fn into(self) -> AcceptableFunctionMetadata {
         AcceptableFunctionMetadata::FunctionWithoutOptions(self)
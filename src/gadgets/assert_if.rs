@@ -0,0 +1,141 @@
+use crate::dsl::{Element, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// The flag is checked in the script to be 0 or 1, so that a spender cannot skip the assertion with
+// another value that OP_IF would take as false, or as true outside of tapscript.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "assert_if",
+        FunctionWithOptionsMetadata::new(
+            assert_if_trace,
+            assert_if_gadget,
+            vec!["num", "any", "any"],
+            vec![],
+        ),
+    )
+}
+
+pub fn assert_if(dsl: &mut DSL, flag: usize, a: usize, b: usize) -> Result<()> {
+    let data_type = &dsl
+        .memory
+        .get(&a)
+        .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+        .data_type;
+    let len = dsl.data_type_len(data_type)?;
+
+    dsl.execute_with_options(
+        "assert_if",
+        &[flag, a, b],
+        &Options::new().with_u32("len", len as u32),
+    )?;
+    Ok(())
+}
+
+fn assert_if_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let flag = dsl.get_num(inputs[0])?;
+    let a = dsl.memory.get(&inputs[1]).unwrap();
+    let b = dsl.memory.get(&inputs[2]).unwrap();
+    if flag != 0 && flag != 1 {
+        return Err(Error::msg("The flag must be 0 or 1"));
+    }
+    if a.data_type != b.data_type {
+        return Err(Error::msg("The two values to be compared must have the same type"));
+    }
+    if options.get_u32("len")? as usize != a.data.len() || a.data.len() != b.data.len() {
+        return Err(Error::msg("The length option does not match the length of the values"));
+    }
+    if flag == 1 && a.data != b.data {
+        return Err(Error::msg("The two values differ, while the flag is set"));
+    }
+
+    Ok(FunctionOutput {
+        new_elements: vec![],
+        new_hints: vec![],
+    })
+}
+
+fn assert_if_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let len = options.get_u32("len")? as usize;
+
+    // stack: flag a b, where a and b each have `len` elements, compared from the last element
+    Ok(script! {
+        { 2 * len } OP_ROLL
+        OP_DUP 0 2 OP_WITHIN OP_VERIFY
+        OP_IF
+            { len } OP_ROLL OP_EQUALVERIFY
+            for k in (1..len).rev() {
+                { k } OP_ROLL OP_EQUALVERIFY
+            }
+        OP_ELSE
+            for _ in 0..len {
+                OP_2DROP
+            }
+        OP_ENDIF
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::{Element, ElementType, DSL};
+    use crate::gadgets::assert_if::{assert_if, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::simulate;
+
+    fn new_dsl() -> DSL {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl.add_data_type("strs", ElementType::ManyStr(3)).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_assert_if() {
+        for (flag_val, b_val) in [(1, vec![2, 3]), (0, vec![2, 3]), (0, vec![2, 4])] {
+            let mut dsl = new_dsl();
+            let flag = dsl.alloc_input("num", Element::Num(flag_val)).unwrap();
+            let a = dsl
+                .alloc_input("strs", Element::ManyStr(vec![vec![1], vec![2, 3], vec![]]))
+                .unwrap();
+            let b = dsl
+                .alloc_input("strs", Element::ManyStr(vec![vec![1], b_val, vec![]]))
+                .unwrap();
+            assert_if(&mut dsl, flag, a, b).unwrap();
+
+            let program = Compiler::compiler(dsl).unwrap();
+            simulate(&program).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_assert_if_fails() {
+        let mut dsl = new_dsl();
+        let one = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let two = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(20)).unwrap();
+        let s = dsl.alloc_input("str", Element::Str(vec![10])).unwrap();
+        assert!(assert_if(&mut dsl, one, a, b).is_err());
+        assert!(assert_if(&mut dsl, two, a, a).is_err());
+        assert!(assert_if(&mut dsl, one, a, s).is_err());
+
+        // setting the flag, or setting it to another value, in the witness fails the script
+        let mut dsl = new_dsl();
+        let flag = dsl.alloc_input("num", Element::Num(0)).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(20)).unwrap();
+        assert_if(&mut dsl, flag, a, b).unwrap();
+
+        let mut program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+        for tampered in [1, 2, -1] {
+            program.input[0].data = Element::Num(tampered);
+            assert!(simulate(&program).is_err());
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// The set is an option of the function, so the script compares the value against constants, and
+// the option "keep" leaves a copy of the value on the stack as the output.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "assert_in_set",
+        FunctionWithOptionsMetadata {
+            output_fn: Some(assert_in_set_output),
+            default_options: Options::new().with_u32("keep", 0),
+            ..FunctionWithOptionsMetadata::new(
+                assert_in_set_trace,
+                assert_in_set_gadget,
+                vec!["num"],
+                vec![],
+            )
+        },
+    )
+}
+
+pub fn assert_in_set(dsl: &mut DSL, x: usize, set: &[i32]) -> Result<()> {
+    dsl.execute_with_options("assert_in_set", &[x], &set_options(set))?;
+    Ok(())
+}
+
+pub fn assert_in_set_and_keep(dsl: &mut DSL, x: usize, set: &[i32]) -> Result<usize> {
+    let res = dsl.execute_with_options(
+        "assert_in_set",
+        &[x],
+        &set_options(set).with_u32("keep", 1),
+    )?;
+    Ok(res[0])
+}
+
+fn set_options(set: &[i32]) -> Options {
+    Options::new().with_multi_u32("set", set.iter().map(|&v| v as u32).collect())
+}
+
+fn get_set(options: &Options) -> Result<Vec<i32>> {
+    let set = options
+        .get_multi_u32("set")?
+        .iter()
+        .map(|&v| v as i32)
+        .collect::<Vec<i32>>();
+    if set.is_empty() {
+        return Err(Error::msg("The set must not be empty"));
+    }
+    for (i, v) in set.iter().enumerate() {
+        if set[..i].contains(v) {
+            return Err(Error::msg(format!("The set has the value {} more than once", v)));
+        }
+    }
+    Ok(set)
+}
+
+fn assert_in_set_output(options: &Options) -> Vec<&'static str> {
+    // the output types cannot fail, so a missing "keep" is the default of keeping nothing
+    if matches!(options.get_u32("keep"), Ok(1)) {
+        vec!["num"]
+    } else {
+        vec![]
+    }
+}
+
+fn assert_in_set_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let x = dsl.get_num(inputs[0])?;
+    let set = get_set(options)?;
+    if !set.contains(&x) {
+        return Err(Error::msg(format!("The value {} is not in the set", x)));
+    }
+
+    let new_elements = if options.get_u32("keep")? == 1 {
+        vec![MemoryEntry::new("num", Element::Num(x))]
+    } else {
+        vec![]
+    };
+    Ok(FunctionOutput {
+        new_elements,
+        new_hints: vec![],
+    })
+}
+
+fn assert_in_set_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let set = get_set(options)?;
+    let keep = options.get_u32("keep")? == 1;
+    let n = set.len();
+
+    // stack: x, with the result of the comparisons so far kept below a copy of x
+    Ok(script! {
+        if keep {
+            OP_DUP
+        }
+        for i in 0..n - 1 {
+            OP_DUP { set[i] } OP_EQUAL
+            if i > 0 {
+                OP_ROT OP_BOOLOR
+            }
+            OP_SWAP
+        }
+        { set[n - 1] } OP_EQUAL
+        if n > 1 {
+            OP_BOOLOR
+        }
+        OP_VERIFY
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::{Element, DSL};
+    use crate::gadgets::assert_in_set::{assert_in_set, assert_in_set_and_keep, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::{simulate, test_program};
+    use crate::treepp::*;
+
+    fn new_dsl() -> DSL {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_assert_in_set() {
+        for set in [vec![7], vec![3, -1], vec![0, 5, 1000, -20]] {
+            for &v in set.iter() {
+                let mut dsl = new_dsl();
+                let x = dsl.alloc_input("num", Element::Num(v)).unwrap();
+                assert_in_set(&mut dsl, x, &set).unwrap();
+                simulate(&Compiler::compiler(dsl).unwrap()).unwrap();
+
+                let mut dsl = new_dsl();
+                let x = dsl.alloc_input("num", Element::Num(v)).unwrap();
+                let res = assert_in_set_and_keep(&mut dsl, x, &set).unwrap();
+                dsl.set_program_output("num", res).unwrap();
+                test_program(dsl, script! { { v } }).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_assert_in_set_fails() {
+        let mut dsl = new_dsl();
+        let x = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        assert!(assert_in_set(&mut dsl, x, &[1, 3]).is_err());
+        assert!(assert_in_set(&mut dsl, x, &[]).is_err());
+        assert!(assert_in_set(&mut dsl, x, &[2, 3, 2]).is_err());
+
+        // a value outside of the set in the witness fails the script
+        let mut dsl = new_dsl();
+        let x = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        assert_in_set(&mut dsl, x, &[1, 3, 5]).unwrap();
+
+        let mut program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+        program.input[0].data = Element::Num(4);
+        assert!(simulate(&program).is_err());
+    }
+}
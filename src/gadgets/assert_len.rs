@@ -0,0 +1,105 @@
+use crate::dsl::{Element, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// A `ManyStr(n)` value always occupies exactly n stack elements, because the compiler lays out the
+// stack by the types, so within a program the length can only be checked when the program is
+// built, and this gadget emits nothing. The length is only flexible at the boundary, where the
+// spender provides the witness, which `CompilerOptions::with_witness_len_check` verifies on-chain.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "assert_len",
+        FunctionWithOptionsMetadata::new(
+            assert_len_trace,
+            assert_len_gadget,
+            vec!["&any"],
+            vec![],
+        ),
+    )
+}
+
+pub fn assert_len(dsl: &mut DSL, arr: usize, expected: usize) -> Result<()> {
+    dsl.execute_with_options(
+        "assert_len",
+        &[arr],
+        &Options::new().with_u32("len", expected as u32),
+    )?;
+    Ok(())
+}
+
+fn assert_len_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let expected = options.get_u32("len")? as usize;
+    match &dsl.memory.get(&inputs[0]).unwrap().data {
+        Element::ManyStr(v) if v.len() == expected => Ok(FunctionOutput {
+            new_elements: vec![],
+            new_hints: vec![],
+        }),
+        Element::ManyStr(v) => Err(Error::msg(format!(
+            "The array has {} elements, but {} are expected",
+            v.len(),
+            expected
+        ))),
+        _ => Err(Error::msg("Only arrays of strings have their length checked")),
+    }
+}
+
+fn assert_len_gadget(_: &[usize], _: &Options) -> Result<Script> {
+    Ok(script! {})
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{Compiler, CompilerOptions};
+    use crate::dsl::{Element, ElementType, MemoryEntry, DSL};
+    use crate::gadgets::assert_len::{assert_len, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::{simulate, simulate_with_hints};
+
+    fn new_dsl() -> DSL {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl.add_data_type("strs", ElementType::ManyStr(3)).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_assert_len() {
+        let mut dsl = new_dsl();
+        let arr = dsl
+            .alloc_input("strs", Element::ManyStr(vec![vec![1], vec![2, 3], vec![]]))
+            .unwrap();
+        let s = dsl.alloc_input("str", Element::Str(vec![4])).unwrap();
+
+        assert_len(&mut dsl, arr, 3).unwrap();
+        assert!(assert_len(&mut dsl, arr, 2).is_err());
+        assert!(assert_len(&mut dsl, s, 1).is_err());
+
+        dsl.set_program_output("strs", arr).unwrap();
+        let program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+    }
+
+    #[test]
+    fn test_witness_len_check() {
+        let mut dsl = new_dsl();
+        let arr = dsl
+            .alloc_input("strs", Element::ManyStr(vec![vec![1], vec![2], vec![3]]))
+            .unwrap();
+        let h = dsl.alloc_hint("str", Element::Str(vec![4])).unwrap();
+        dsl.set_program_output("strs", arr).unwrap();
+        dsl.set_program_output("str", h).unwrap();
+
+        let program =
+            Compiler::compile_with(dsl, CompilerOptions::new().with_witness_len_check()).unwrap();
+        simulate(&program).unwrap();
+
+        // an additional witness item between the hints and the inputs is rejected
+        let mut hints = program.hint.clone();
+        hints.push(MemoryEntry::new("str", Element::Str(vec![5])));
+        assert!(simulate_with_hints(&program, &hints).is_err());
+    }
+}
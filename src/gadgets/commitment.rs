@@ -0,0 +1,26 @@
+use crate::treepp::*;
+use bitcoin::hashes::{sha256, Hash};
+
+// The elements are hashed as a chain from the top of the stack: the last element is hashed alone,
+// and then each element is hashed together with the hash so far, `h_i = SHA256(e_i || h_{i+1})`.
+// Since the hash has a fixed length, the chain is unambiguous even for variable-length strings.
+
+pub fn commitment_script(num_elements: usize) -> Script {
+    script! {
+        OP_SHA256
+        for _ in 1..num_elements {
+            OP_CAT OP_SHA256
+        }
+    }
+}
+
+pub fn commitment(elements: &[Vec<u8>]) -> Vec<u8> {
+    let (last, rest) = elements.split_last().unwrap();
+    let mut hash = sha256::Hash::hash(last).to_byte_array().to_vec();
+    for element in rest.iter().rev() {
+        let mut data = element.clone();
+        data.extend_from_slice(&hash);
+        hash = sha256::Hash::hash(&data).to_byte_array().to_vec();
+    }
+    hash
+}
@@ -0,0 +1,89 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionMetadata, FunctionOutput};
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// OP_CAT is only available in some contexts, so programs that use this gadget should be compiled
+// with `CompilerOptions::with_op_cat`, under which the opcode check accepts it.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "concat",
+        FunctionMetadata::new(concat_trace, concat_gadget, vec!["str", "str"], vec!["str"]),
+    )
+}
+
+pub fn concat(dsl: &mut DSL, a: usize, b: usize) -> Result<usize> {
+    let res = dsl.execute("concat", &[a, b])?;
+    Ok(res[0])
+}
+
+fn concat_trace(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+    let a = dsl.get_str(inputs[0])?.to_vec();
+    let b = dsl.get_str(inputs[1])?.to_vec();
+
+    if a.len() + b.len() > 520 {
+        return Err(Error::msg(
+            "The concatenation exceeds the 520-byte limit of a stack element",
+        ));
+    }
+
+    let res = [a, b].concat();
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new("str", Element::Str(res))],
+        new_hints: vec![],
+    })
+}
+
+fn concat_gadget(_: &[usize]) -> Result<Script> {
+    Ok(script! {
+        OP_CAT
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{Compiler, CompilerOptions};
+    use crate::dsl::{Element, DSL};
+    use crate::gadgets::concat::{concat, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::simulate;
+
+    #[test]
+    fn test_concat() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("str", Element::Str(b"hello, ".to_vec())).unwrap();
+        let b = dsl.alloc_input("str", Element::Str(b"world".to_vec())).unwrap();
+        let res = concat(&mut dsl, a, b).unwrap();
+        assert_eq!(dsl.get_str(res).unwrap(), b"hello, world".as_slice());
+        dsl.set_program_output("str", res).unwrap();
+
+        let program = Compiler::compile_with(
+            dsl.clone(),
+            CompilerOptions::new().with_opcode_check().with_op_cat(),
+        )
+        .unwrap();
+        simulate(&program).unwrap();
+
+        // without OP_CAT in the profile, the concatenation is rejected
+        let err = Compiler::compile_with(dsl, CompilerOptions::new().with_opcode_check())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("(concat) uses OP_CAT"));
+    }
+
+    #[test]
+    fn test_concat_too_long() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("str", Element::Str(vec![1; 300])).unwrap();
+        let b = dsl.alloc_input("str", Element::Str(vec![2; 300])).unwrap();
+        assert!(concat(&mut dsl, a, b).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::dsl::{MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "cswap",
+        FunctionWithOptionsMetadata::new(
+            cswap_trace,
+            cswap_gadget,
+            vec!["num", "any", "any"],
+            vec!["any", "any"],
+        ),
+    )
+}
+
+pub fn cswap(dsl: &mut DSL, bit: usize, a: usize, b: usize) -> Result<(usize, usize)> {
+    let data_type = &dsl
+        .memory
+        .get(&a)
+        .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+        .data_type;
+    let len = dsl.data_type_len(data_type)?;
+
+    let res = dsl.execute_with_options(
+        "cswap",
+        &[bit, a, b],
+        &Options::new().with_u32("len", len as u32),
+    )?;
+    Ok((res[0], res[1]))
+}
+
+fn cswap_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let bit = dsl.get_num(inputs[0])?;
+    if bit != 0 && bit != 1 {
+        return Err(Error::msg("The swap condition must be either 0 or 1"));
+    }
+
+    let a = dsl.memory.get(&inputs[1]).unwrap();
+    let b = dsl.memory.get(&inputs[2]).unwrap();
+    if a.data_type != b.data_type {
+        return Err(Error::msg("The two values to be swapped must have the same type"));
+    }
+    if options.get_u32("len")? as usize != a.data.len() {
+        return Err(Error::msg("The length option does not match the length of the values"));
+    }
+
+    let a = MemoryEntry::new(&a.data_type, a.data.clone());
+    let b = MemoryEntry::new(&b.data_type, b.data.clone());
+
+    Ok(FunctionOutput {
+        new_elements: if bit == 0 { vec![a, b] } else { vec![b, a] },
+        new_hints: vec![],
+    })
+}
+
+fn cswap_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let len = options.get_u32("len")? as usize;
+
+    // moves b below a, with the two-element case done in a single opcode
+    let swap = match len {
+        1 => script! { OP_SWAP },
+        2 => script! { OP_2SWAP },
+        _ => script! {
+            for _ in 0..len {
+                { 2 * len - 1 } OP_ROLL
+            }
+        },
+    };
+
+    // stack: bit a b, where a and b each have `len` elements
+    Ok(script! {
+        { 2 * len } OP_ROLL
+        OP_DUP 0 2 OP_WITHIN OP_VERIFY
+        OP_IF
+            { swap }
+        OP_ENDIF
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::{Element, ElementType, DSL};
+    use crate::gadgets::cswap::{cswap, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::test_program;
+    use crate::treepp::*;
+
+    #[test]
+    fn test_cswap() {
+        for flag in 0..2 {
+            let mut dsl = DSL::new();
+            load_data_types(&mut dsl).unwrap();
+            load_functions(&mut dsl).unwrap();
+
+            let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+            let b = dsl.alloc_input("num", Element::Num(20)).unwrap();
+            let bit = dsl.alloc_hint("num", Element::Num(flag)).unwrap();
+
+            let (a2, b2) = cswap(&mut dsl, bit, a, b).unwrap();
+            dsl.set_program_output("num", a2).unwrap();
+            dsl.set_program_output("num", b2).unwrap();
+
+            let expected = if flag == 0 { [10, 20] } else { [20, 10] };
+            assert_eq!(dsl.get_num(a2).unwrap(), expected[0]);
+            test_program(dsl, script! { { expected[0] } { expected[1] } }).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cswap_multi_element() {
+        for len in 2..4 {
+            let mut dsl = DSL::new();
+            load_data_types(&mut dsl).unwrap();
+            load_functions(&mut dsl).unwrap();
+            dsl.add_data_type("point", ElementType::ManyNum(len)).unwrap();
+
+            let a_val = (0..len as i32).collect::<Vec<i32>>();
+            let b_val = (100..100 + len as i32).collect::<Vec<i32>>();
+
+            let a = dsl.alloc_input("point", Element::ManyNum(a_val.clone())).unwrap();
+            let b = dsl.alloc_input("point", Element::ManyNum(b_val.clone())).unwrap();
+            let bit = dsl.alloc_constant("num", Element::Num(1)).unwrap();
+
+            let (a2, b2) = cswap(&mut dsl, bit, a, b).unwrap();
+            dsl.set_program_output("point", a2).unwrap();
+            dsl.set_program_output("point", b2).unwrap();
+
+            test_program(
+                dsl,
+                script! {
+                    for v in b_val.iter() {
+                        { *v }
+                    }
+                    for v in a_val.iter() {
+                        { *v }
+                    }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cswap_invalid() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(20)).unwrap();
+        let s = dsl.alloc_input("str", Element::Str(vec![1, 2])).unwrap();
+        let bit = dsl.alloc_constant("num", Element::Num(2)).unwrap();
+        assert!(cswap(&mut dsl, bit, a, b).is_err());
+
+        let bit = dsl.alloc_constant("num", Element::Num(0)).unwrap();
+        assert!(cswap(&mut dsl, bit, a, s).is_err());
+    }
+}
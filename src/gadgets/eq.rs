@@ -0,0 +1,125 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "eq",
+        FunctionWithOptionsMetadata::new(eq_trace, eq_gadget, vec!["any", "any"], vec!["num"]),
+    )
+}
+
+pub fn eq(dsl: &mut DSL, a: usize, b: usize) -> Result<usize> {
+    let data_type = &dsl
+        .memory
+        .get(&a)
+        .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+        .data_type;
+    let len = dsl.data_type_len(data_type)?;
+
+    let res = dsl.execute_with_options("eq", &[a, b], &Options::new().with_u32("len", len as u32))?;
+    Ok(res[0])
+}
+
+fn eq_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let a = dsl.memory.get(&inputs[0]).unwrap();
+    let b = dsl.memory.get(&inputs[1]).unwrap();
+    if a.data_type != b.data_type {
+        return Err(Error::msg("The two values to be compared must have the same type"));
+    }
+    if options.get_u32("len")? as usize != a.data.len() || a.data.len() != b.data.len() {
+        return Err(Error::msg("The length option does not match the length of the values"));
+    }
+
+    let res = if a.data == b.data { 1 } else { 0 };
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new("num", Element::Num(res))],
+        new_hints: vec![],
+    })
+}
+
+fn eq_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let len = options.get_u32("len")? as usize;
+
+    // stack: a b, where a and b each have `len` elements, compared from the last element
+    Ok(match len {
+        1 => script! { OP_EQUAL },
+        _ => script! {
+            { len } OP_ROLL OP_EQUAL
+            for k in (1..len).rev() {
+                OP_SWAP { k + 1 } OP_ROLL OP_EQUAL OP_BOOLAND
+            }
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::{Element, ElementType, DSL};
+    use crate::gadgets::eq::{eq, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::test_program;
+    use crate::treepp::*;
+
+    #[test]
+    fn test_eq() {
+        for (a_val, b_val) in [(10, 10), (10, 20)] {
+            let mut dsl = DSL::new();
+            load_data_types(&mut dsl).unwrap();
+            load_functions(&mut dsl).unwrap();
+
+            let a = dsl.alloc_input("num", Element::Num(a_val)).unwrap();
+            let b = dsl.alloc_input("num", Element::Num(b_val)).unwrap();
+
+            let res = eq(&mut dsl, a, b).unwrap();
+            dsl.set_program_output("num", res).unwrap();
+
+            let expected = if a_val == b_val { 1 } else { 0 };
+            assert_eq!(dsl.get_num(res).unwrap(), expected);
+            test_program(dsl, script! { { expected } }).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_eq_multi_element() {
+        for len in 2..4 {
+            // differ in no element, in the first, and in the last
+            for diff in [None, Some(0), Some(len - 1)] {
+                let mut dsl = DSL::new();
+                load_data_types(&mut dsl).unwrap();
+                load_functions(&mut dsl).unwrap();
+                dsl.add_data_type("strs", ElementType::ManyStr(len)).unwrap();
+
+                let a_val = (0..len).map(|i| vec![i as u8; 3]).collect::<Vec<Vec<u8>>>();
+                let mut b_val = a_val.clone();
+                if let Some(i) = diff {
+                    b_val[i][1] ^= 1;
+                }
+
+                let a = dsl.alloc_input("strs", Element::ManyStr(a_val)).unwrap();
+                let b = dsl.alloc_input("strs", Element::ManyStr(b_val)).unwrap();
+
+                let res = eq(&mut dsl, a, b).unwrap();
+                dsl.set_program_output("num", res).unwrap();
+
+                let expected = if diff.is_none() { 1 } else { 0 };
+                assert_eq!(dsl.get_num(res).unwrap(), expected);
+                test_program(dsl, script! { { expected } }).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_eq_type_mismatch() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("num", Element::Num(10)).unwrap();
+        let s = dsl.alloc_input("str", Element::Str(vec![10])).unwrap();
+        assert!(eq(&mut dsl, a, s).is_err());
+    }
+}
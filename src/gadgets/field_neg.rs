@@ -0,0 +1,197 @@
+use crate::biguint::{from_limbs, to_limbs};
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+use num_bigint::BigUint;
+
+// The values are big numbers as in `biguint`, with the least significant limb first. The script
+// subtracts x from the modulus limb by limb with a borrow, checks that nothing is borrowed out of
+// the last limb, so that x is at most the modulus, and replaces the result by zero if x is zero.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "field_neg",
+        FunctionWithOptionsMetadata {
+            max_internal_stack: Some(3),
+            ..FunctionWithOptionsMetadata::new(
+                field_neg_trace,
+                field_neg_gadget,
+                vec!["any", "&any"],
+                vec!["any"],
+            )
+        },
+    )
+}
+
+pub fn field_neg(dsl: &mut DSL, x: usize, modulus: usize, base_bits: u32) -> Result<usize> {
+    let data_type = &dsl
+        .memory
+        .get(&x)
+        .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+        .data_type;
+    let len = dsl.data_type_len(data_type)?;
+
+    let res = dsl.execute_with_options(
+        "field_neg",
+        &[x, modulus],
+        &Options::new()
+            .with_u32("len", len as u32)
+            .with_u32("base_bits", base_bits),
+    )?;
+    Ok(res[0])
+}
+
+fn field_neg_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let len = options.get_u32("len")? as usize;
+    let base_bits = options.get_u32("base_bits")?;
+    if base_bits > 30 {
+        return Err(Error::msg("The number of bits of a limb must be at most 30"));
+    }
+
+    let x = dsl.memory.get(&inputs[0]).unwrap();
+    let modulus = dsl.memory.get(&inputs[1]).unwrap();
+    let (x_limbs, modulus_limbs) = match (&x.data, &modulus.data) {
+        (Element::ManyNum(a), Element::ManyNum(b)) => (a, b),
+        _ => return Err(Error::msg("The value and the modulus must be arrays of numbers")),
+    };
+    if x_limbs.len() != len || modulus_limbs.len() != len {
+        return Err(Error::msg("The value and the modulus must have the same number of limbs"));
+    }
+
+    let x_value = from_limbs(x_limbs, base_bits)?;
+    let modulus_value = from_limbs(modulus_limbs, base_bits)?;
+    if x_value >= modulus_value {
+        return Err(Error::msg("The value must be reduced modulo the modulus"));
+    }
+    let res = if x_value == BigUint::from(0u32) {
+        x_value
+    } else {
+        modulus_value - x_value
+    };
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new(
+            x.data_type.clone(),
+            Element::ManyNum(to_limbs(&res, len, base_bits)?),
+        )],
+        new_hints: vec![],
+    })
+}
+
+fn field_neg_gadget(ref_positions: &[usize], options: &Options) -> Result<Script> {
+    let len = options.get_u32("len")? as usize;
+    let base_bits = options.get_u32("base_bits")?;
+    if base_bits == 0 || base_bits > 30 {
+        return Err(Error::msg("The number of bits of a limb must be between 1 and 30"));
+    }
+    let base = 1i64 << base_bits;
+    let pos = ref_positions[0];
+
+    // stack: x_0 ... x_{len-1} z b, where z is whether a limb so far is nonzero and b is the
+    // borrow, and the limbs of the result replace those of x as they are computed
+    Ok(script! {
+        0 0
+        for i in 0..len {
+            { len + 1 } OP_ROLL
+            OP_DUP 0 { base } OP_WITHIN OP_VERIFY
+            OP_DUP OP_0NOTEQUAL 3 OP_ROLL OP_BOOLOR OP_ROT OP_ROT
+            OP_ADD
+
+            // the limb of the modulus is below the other limbs of x and the result, and z
+            { pos + len + 1 - i } OP_PICK
+            OP_SWAP OP_SUB
+            OP_DUP 0 OP_LESSTHAN OP_DUP OP_TOALTSTACK
+            OP_IF { base } OP_ADD OP_ENDIF
+            OP_FROMALTSTACK
+            OP_ROT OP_SWAP
+        }
+        OP_NOT OP_VERIFY
+        OP_NOTIF
+            for _ in 0..len / 2 {
+                OP_2DROP
+            }
+            if len % 2 == 1 {
+                OP_DROP
+            }
+            for _ in 0..len {
+                0
+            }
+        OP_ENDIF
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::biguint::to_limbs;
+    use crate::compiler::Compiler;
+    use crate::dsl::{Element, ElementType, DSL};
+    use crate::gadgets::field_neg::{field_neg, load_functions};
+    use crate::simulate;
+    use num_bigint::BigUint;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn new_dsl() -> DSL {
+        let mut dsl = DSL::new();
+        dsl.add_data_type("felt", ElementType::ManyNum(3)).unwrap();
+        dsl.add_ref_only_data_type("modulus", ElementType::ManyNum(3)).unwrap();
+        dsl.add_data_type("short", ElementType::ManyNum(2)).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_field_neg() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let p = (BigUint::from(1u32) << 61u32) - BigUint::from(1u32);
+
+        let mut values = vec![BigUint::from(0u32), BigUint::from(1u32), &p - BigUint::from(1u32)];
+        for _ in 0..5 {
+            values.push(BigUint::from(prng.gen::<u64>()) % &p);
+        }
+
+        for value in values {
+            let mut dsl = new_dsl();
+            let x = dsl.alloc_input_biguint("felt", &value, 3, 30).unwrap();
+            let modulus = dsl.alloc_input_biguint("modulus", &p, 3, 30).unwrap();
+            let res = field_neg(&mut dsl, x, modulus, 30).unwrap();
+
+            let expected = (&p - &value) % &p;
+            assert_eq!(dsl.get_biguint(res, 30).unwrap(), expected);
+            dsl.set_program_output("felt", res).unwrap();
+
+            let program = Compiler::compiler(dsl).unwrap();
+            simulate(&program).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_field_neg_rejected() {
+        let p = (BigUint::from(1u32) << 61u32) - BigUint::from(1u32);
+
+        let mut dsl = new_dsl();
+        let x = dsl.alloc_input_biguint("felt", &p, 3, 30).unwrap();
+        let y = dsl.alloc_input_biguint("short", &BigUint::from(5u32), 2, 30).unwrap();
+        let z = dsl.alloc_input_biguint("felt", &BigUint::from(5u32), 3, 30).unwrap();
+        let modulus = dsl.alloc_input_biguint("modulus", &p, 3, 30).unwrap();
+        assert!(field_neg(&mut dsl, x, modulus, 30).is_err());
+        assert!(field_neg(&mut dsl, y, modulus, 30).is_err());
+        assert!(field_neg(&mut dsl, z, modulus, 31).is_err());
+        field_neg(&mut dsl, z, modulus, 30).unwrap();
+
+        // a value above the modulus in the witness borrows out of the last limb
+        let mut dsl = new_dsl();
+        let x = dsl.alloc_input_biguint("felt", &BigUint::from(5u32), 3, 30).unwrap();
+        let modulus = dsl.alloc_input_biguint("modulus", &p, 3, 30).unwrap();
+        let res = field_neg(&mut dsl, x, modulus, 30).unwrap();
+        dsl.set_program_output("felt", res).unwrap();
+
+        let mut program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+        let above = to_limbs(&(&p + BigUint::from(5u32)), 3, 30).unwrap();
+        program.input[0].data = Element::ManyNum(above);
+        assert!(simulate(&program).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+use bitcoin::hashes::{sha256, Hash};
+
+// The accumulator absorbs the items in order, `acc' = SHA256(acc || item)`, starting from `init`,
+// as in the transcript of a Fiat-Shamir transformation. OP_CAT is only available in some contexts,
+// so programs that use this gadget should be compiled with `CompilerOptions::with_op_cat`.
+
+const MAX_ELEMENT_SIZE: usize = 520;
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "hash_fold",
+        FunctionWithOptionsMetadata::new(
+            hash_fold_trace,
+            hash_fold_gadget,
+            vec!["str", "any"],
+            vec!["str"],
+        ),
+    )
+}
+
+// the script of the function concatenates with OP_CAT, so it only runs where it is enabled
+pub fn hash_fold(dsl: &mut DSL, init: usize, items: usize) -> Result<usize> {
+    let len = dsl.get_many_str(items)?.len();
+    let res = dsl.execute_with_options(
+        "hash_fold",
+        &[init, items],
+        &Options::new().with_u32("len", len as u32),
+    )?;
+    Ok(res[0])
+}
+
+fn hash_fold_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let init = dsl.get_str(inputs[0])?.to_vec();
+    let items = dsl.get_many_str(inputs[1])?.to_vec();
+
+    if options.get_u32("len")? as usize != items.len() {
+        return Err(Error::msg("The length option does not match the number of items"));
+    }
+
+    // every concatenation must fit in a stack element
+    let mut acc = init;
+    for item in items.iter() {
+        if acc.len() + item.len() > MAX_ELEMENT_SIZE {
+            return Err(Error::msg("The item is too long to be concatenated with the accumulator"));
+        }
+        let mut data = acc;
+        data.extend_from_slice(item);
+        acc = sha256::Hash::hash(&data).to_byte_array().to_vec();
+    }
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new("str", Element::Str(acc))],
+        new_hints: vec![],
+    })
+}
+
+fn hash_fold_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let len = options.get_u32("len")? as usize;
+
+    // This relies on OP_CAT to absorb each item into the accumulator.
+    // stack: init item_1 ... item_len
+    Ok(script! {
+        { len } OP_ROLL
+        for k in (1..=len).rev() {
+            { k } OP_ROLL OP_CAT OP_SHA256
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::{Element, ElementType, DSL};
+    use crate::gadgets::hash_fold::{hash_fold, load_functions};
+    use crate::gadgets::load_data_types;
+    use crate::test_program;
+    use crate::treepp::*;
+    use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+    fn reference_transcript(init: &[u8], items: &[Vec<u8>]) -> Vec<u8> {
+        let mut state = init.to_vec();
+        for item in items.iter() {
+            let mut engine = sha256::Hash::engine();
+            engine.input(&state);
+            engine.input(item);
+            state = sha256::Hash::from_engine(engine).to_byte_array().to_vec();
+        }
+        state
+    }
+
+    #[test]
+    fn test_hash_fold() {
+        for len in 1..5 {
+            let mut dsl = DSL::new();
+            load_data_types(&mut dsl).unwrap();
+            load_functions(&mut dsl).unwrap();
+            dsl.add_data_type("items", ElementType::ManyStr(len)).unwrap();
+
+            let init_val = b"transcript".to_vec();
+            let items_val = (0..len)
+                .map(|i| vec![0x20 + i as u8; 10 * i + 20])
+                .collect::<Vec<Vec<u8>>>();
+            let expected = reference_transcript(&init_val, &items_val);
+
+            let init = dsl.alloc_input("str", Element::Str(init_val)).unwrap();
+            let items = dsl.alloc_input("items", Element::ManyStr(items_val)).unwrap();
+
+            let res = hash_fold(&mut dsl, init, items).unwrap();
+            assert_eq!(dsl.get_str(res).unwrap(), expected.as_slice());
+
+            dsl.set_program_output("str", res).unwrap();
+            test_program(dsl, script! { { expected } }).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_hash_fold_item_too_long() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl.add_data_type("items", ElementType::ManyStr(2)).unwrap();
+
+        let init = dsl.alloc_input("str", Element::Str(vec![0; 32])).unwrap();
+        let items = dsl
+            .alloc_input("items", Element::ManyStr(vec![vec![1; 100], vec![2; 500]]))
+            .unwrap();
+        assert!(hash_fold(&mut dsl, init, items).is_err());
+    }
+}
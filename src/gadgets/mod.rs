@@ -0,0 +1,64 @@
+use crate::dsl::{ElementType, DSL};
+use anyhow::{Error, Result};
+
+pub mod assert_if;
+
+pub mod assert_in_set;
+
+pub mod assert_len;
+
+pub mod commitment;
+
+pub mod concat;
+
+pub mod cswap;
+
+pub mod eq;
+
+pub mod field_neg;
+
+pub mod hash_fold;
+
+pub mod mux_n;
+
+pub mod table;
+
+pub mod to_bits;
+
+pub mod xor;
+
+pub fn load_data_types(dsl: &mut DSL) -> Result<()> {
+    // the built-in gadgets share these data types, which may already have been registered by the user
+    for (name, element_type) in [("num", ElementType::Num), ("str", ElementType::Str)] {
+        match dsl.data_type_registry.map.get(name) {
+            Some(metadata) if metadata.element_type != element_type => {
+                return Err(Error::msg(format!(
+                    "The data type `{}` has been registered with another element type",
+                    name
+                )));
+            }
+            Some(_) => {}
+            None => dsl.add_data_type(name, element_type)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::{ElementType, DSL};
+    use crate::gadgets::load_data_types;
+
+    #[test]
+    fn test_load_data_types() {
+        // loading twice, or after the user registered the same types, is fine
+        let mut dsl = DSL::new();
+        dsl.add_data_type("num", ElementType::Num).unwrap();
+        load_data_types(&mut dsl).unwrap();
+        load_data_types(&mut dsl).unwrap();
+
+        let mut dsl = DSL::new();
+        dsl.add_data_type("str", ElementType::ManyStr(2)).unwrap();
+        assert!(load_data_types(&mut dsl).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// The options are a `ManyStr` value of equal-length strings, with the first option the deepest on
+// the stack, and the script rolls the selected one to the top after checking the index.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "mux_n",
+        FunctionWithOptionsMetadata::new(
+            mux_n_trace,
+            mux_n_gadget,
+            vec!["num", "any"],
+            vec!["str"],
+        ),
+    )
+}
+
+pub fn mux_n(dsl: &mut DSL, index: usize, options: usize) -> Result<usize> {
+    let n = match dsl.memory.get(&options).map(|entry| &entry.data) {
+        Some(Element::ManyStr(v)) => v.len(),
+        Some(_) => return Err(Error::msg("The options must be a list of strings")),
+        None => return Err(Error::msg("Could not find the memory entry with the given index")),
+    };
+
+    let res = dsl.execute_with_options(
+        "mux_n",
+        &[index, options],
+        &Options::new().with_u32("n", n as u32),
+    )?;
+    Ok(res[0])
+}
+
+fn mux_n_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let index = dsl.get_num(inputs[0])?;
+    let n = options.get_u32("n")? as usize;
+
+    let choices = match &dsl.memory.get(&inputs[1]).unwrap().data {
+        Element::ManyStr(v) if v.len() == n => v,
+        _ => return Err(Error::msg("The options do not match the number of options")),
+    };
+    if n == 0 {
+        return Err(Error::msg("There must be at least one option"));
+    }
+    if choices.iter().any(|choice| choice.len() != choices[0].len()) {
+        return Err(Error::msg("The options must have the same length"));
+    }
+    if index < 0 || index as usize >= n {
+        return Err(Error::msg("The index is out of the range of the options"));
+    }
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new("str", Element::Str(choices[index as usize].clone()))],
+        new_hints: vec![],
+    })
+}
+
+fn mux_n_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let n = options.get_u32("n")? as usize;
+    if n == 0 {
+        return Err(Error::msg("There must be at least one option"));
+    }
+
+    // stack: index option_0 ... option_{n-1}
+    Ok(script! {
+        { n } OP_ROLL
+        OP_DUP 0 { n } OP_WITHIN OP_VERIFY
+
+        // option_i is at depth n - 1 - i
+        { n - 1 } OP_SWAP OP_SUB OP_ROLL
+        OP_TOALTSTACK
+
+        for _ in 0..(n - 1) / 2 {
+            OP_2DROP
+        }
+        if (n - 1) % 2 == 1 {
+            OP_DROP
+        }
+        OP_FROMALTSTACK
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::{Element, ElementType, DSL};
+    use crate::gadgets::load_data_types;
+    use crate::gadgets::mux_n::{load_functions, mux_n};
+    use crate::{simulate, test_program};
+    use crate::treepp::*;
+
+    fn options() -> Vec<Vec<u8>> {
+        vec![b"zero".to_vec(), b"one!".to_vec(), b"two!".to_vec(), b"3333".to_vec()]
+    }
+
+    fn new_dsl() -> DSL {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        dsl.add_data_type("str[4]", ElementType::ManyStr(4)).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_mux_n() {
+        for i in 0..4 {
+            let mut dsl = new_dsl();
+            let index = dsl.alloc_input("num", Element::Num(i as i32)).unwrap();
+            let choices = dsl.alloc_input("str[4]", Element::ManyStr(options())).unwrap();
+
+            let res = mux_n(&mut dsl, index, choices).unwrap();
+            assert_eq!(dsl.get_str(res).unwrap(), options()[i].as_slice());
+            dsl.set_program_output("str", res).unwrap();
+
+            test_program(
+                dsl,
+                script! {
+                    { options()[i].clone() }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mux_n_out_of_range() {
+        for i in [-1, 4] {
+            let mut dsl = new_dsl();
+            let index = dsl.alloc_input("num", Element::Num(i)).unwrap();
+            let choices = dsl.alloc_input("str[4]", Element::ManyStr(options())).unwrap();
+            assert!(mux_n(&mut dsl, index, choices).is_err());
+        }
+
+        // an index changed in the witness fails the check of the script
+        let mut dsl = new_dsl();
+        let index = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let choices = dsl.alloc_input("str[4]", Element::ManyStr(options())).unwrap();
+        let res = mux_n(&mut dsl, index, choices).unwrap();
+        dsl.set_program_output("str", res).unwrap();
+
+        let mut program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+        program.input[0].data = Element::Num(4);
+        assert!(simulate(&program).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::dsl::{Element, ElementType, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// A table of `n` rows of type `t` is a single constant of type `t[n]`, whose elements are the rows,
+// with the first row the deepest. Rows are read by reference, so the table is pushed only once.
+
+impl DSL {
+    pub fn alloc_constant_table(&mut self, data_type: impl ToString, rows: Vec<Element>) -> Result<usize> {
+        let data_type = data_type.to_string();
+        let element_type = match self.data_type_registry.map.get(&data_type) {
+            Some(metadata) => metadata.element_type.clone(),
+            None => return Err(Error::msg("The data type has not been registered")),
+        };
+        if rows.is_empty() {
+            return Err(Error::msg("A table must have at least one row"));
+        }
+        for row in rows.iter() {
+            if !row.match_type(&element_type) {
+                return Err(Error::msg("The row does not match the type definitions"));
+            }
+        }
+
+        let num_rows = rows.len();
+        let (table_element_type, table) = match element_type {
+            ElementType::Num => {
+                let mut v = vec![];
+                for row in rows.into_iter() {
+                    if let Element::Num(vv) = row {
+                        v.push(vv);
+                    }
+                }
+                (ElementType::ManyNum(num_rows), Element::ManyNum(v))
+            }
+            ElementType::Str => {
+                let mut v = vec![];
+                for row in rows.into_iter() {
+                    if let Element::Str(vv) = row {
+                        v.push(vv);
+                    }
+                }
+                (ElementType::ManyStr(num_rows), Element::ManyStr(v))
+            }
+            _ => return Err(Error::msg("Only single-element types can be the rows of a table")),
+        };
+
+        let table_type = format!("{}[{}]", data_type, num_rows);
+        match self.data_type_registry.map.get(&table_type) {
+            Some(metadata) if metadata.element_type != table_element_type => {
+                return Err(Error::msg("The table type has been registered differently"));
+            }
+            Some(_) => {}
+            None => self.add_data_type(&table_type, table_element_type)?,
+        }
+        if !self.function_registry.map.contains_key("table_get") {
+            self.add_function(
+                "table_get",
+                FunctionWithOptionsMetadata::new(
+                    table_get_trace,
+                    table_get_gadget,
+                    vec!["&any"],
+                    vec!["any"],
+                ),
+            )?;
+        }
+
+        self.alloc_constant(table_type, table)
+    }
+
+    pub fn table_get(&mut self, table_idx: usize, i: usize) -> Result<usize> {
+        let table_type = &self
+            .memory
+            .get(&table_idx)
+            .ok_or(Error::msg("Could not find the memory entry with the given index"))?
+            .data_type;
+        let row_type = match table_type.rsplit_once('[') {
+            Some((row_type, _)) => row_type.to_string(),
+            None => return Err(Error::msg("The memory entry is not a table")),
+        };
+
+        let res = self.execute_with_options(
+            "table_get",
+            &[table_idx],
+            &Options::new()
+                .with_u32("row", i as u32)
+                .with_string("data_type", row_type),
+        )?;
+        Ok(res[0])
+    }
+}
+
+fn table_get_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let row = options.get_u32("row")? as usize;
+    let data_type = options.get_string("data_type")?;
+
+    let data = match &dsl.memory.get(&inputs[0]).unwrap().data {
+        Element::ManyNum(v) if row < v.len() => Element::Num(v[row]),
+        Element::ManyStr(v) if row < v.len() => Element::Str(v[row].clone()),
+        Element::ManyNum(_) | Element::ManyStr(_) => {
+            return Err(Error::msg("The row is out of the range of the table"))
+        }
+        _ => return Err(Error::msg("The memory entry is not a table")),
+    };
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new(data_type, data)],
+        new_hints: vec![],
+    })
+}
+
+fn table_get_gadget(ref_positions: &[usize], options: &Options) -> Result<Script> {
+    let row = options.get_u32("row")? as usize;
+    Ok(script! {
+        { ref_positions[0] - row } OP_PICK
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::dsl::Element;
+    use crate::test_program;
+    use crate::treepp::*;
+
+    #[test]
+    fn test_constant_table() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let rows = [10, 20, 30, 40].map(Element::Num).to_vec();
+        let table = dsl.alloc_constant_table("num", rows).unwrap();
+        let x = dsl.table_get(table, 1).unwrap();
+        let y = dsl.table_get(table, 3).unwrap();
+        let z = dsl.execute("add", &[x, y]).unwrap()[0];
+        let w = dsl.execute("add", &[z, a]).unwrap()[0];
+        let v = dsl.table_get(table, 0).unwrap();
+        dsl.set_program_output("num", w).unwrap();
+        dsl.set_program_output("num", v).unwrap();
+
+        assert_eq!(dsl.constants().count(), 1);
+        assert_eq!(dsl.get_num(w).unwrap(), 61);
+        assert!(dsl.table_get(table, 4).is_err());
+        assert!(dsl.table_get(a, 0).is_err());
+
+        test_program(dsl, script! { 61 10 }).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use crate::dsl::{Element, ElementType, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+// The bits of a number are a value of type `num[n]`, with the least significant bit first, so that
+// it is the deepest on the stack. The bits are provided as hints and recomposed by the script.
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "to_bits",
+        FunctionWithOptionsMetadata::new(to_bits_trace, to_bits_gadget, vec!["num"], vec!["any"]),
+    )
+}
+
+pub fn to_bits(dsl: &mut DSL, x: usize, n: usize) -> Result<usize> {
+    if n == 0 || n > 31 {
+        return Err(Error::msg("The number of bits must be between 1 and 31"));
+    }
+
+    let bits_type = format!("num[{}]", n);
+    match dsl.data_type_registry.map.get(&bits_type) {
+        Some(metadata) if metadata.element_type != ElementType::ManyNum(n) => {
+            return Err(Error::msg("The type of the bits has been registered differently"));
+        }
+        Some(_) => {}
+        None => dsl.add_data_type(&bits_type, ElementType::ManyNum(n))?,
+    }
+
+    let res = dsl.execute_with_options("to_bits", &[x], &Options::new().with_u32("n", n as u32))?;
+    Ok(res[0])
+}
+
+fn to_bits_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let x = dsl.get_num(inputs[0])?;
+    let n = options.get_u32("n")? as usize;
+
+    if x < 0 || (x as i64) >= (1i64 << n) {
+        return Err(Error::msg("The number does not fit in the given number of bits"));
+    }
+
+    let bits = (0..n).map(|i| (x >> i) & 1).collect::<Vec<i32>>();
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new(format!("num[{}]", n), Element::ManyNum(bits.clone()))],
+        new_hints: bits
+            .into_iter()
+            .map(|bit| MemoryEntry::new("num", Element::Num(bit)))
+            .collect(),
+    })
+}
+
+fn to_bits_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let n = options.get_u32("n")? as usize;
+
+    // stack: x b_0 ... b_{i-1} acc, where acc is the sum of the bits so far
+    Ok(script! {
+        OP_0
+        for i in 0..n {
+            OP_DEPTH OP_1SUB OP_ROLL
+            OP_DUP 0 2 OP_WITHIN OP_VERIFY
+            OP_SWAP OP_OVER
+            OP_IF { 1 << i } OP_ADD OP_ENDIF
+        }
+        { n + 1 } OP_ROLL OP_EQUALVERIFY
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::{Element, DSL};
+    use crate::gadgets::load_data_types;
+    use crate::gadgets::to_bits::{load_functions, to_bits};
+    use crate::{simulate, simulate_with_hints, test_program};
+    use crate::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn new_dsl() -> DSL {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_to_bits() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in [1, 8, 20, 31] {
+            let x_val = prng.gen_range(0..(1i64 << n)) as i32;
+
+            let mut dsl = new_dsl();
+            let x = dsl.alloc_input("num", Element::Num(x_val)).unwrap();
+            let bits = to_bits(&mut dsl, x, n).unwrap();
+
+            let bits_val = dsl.get_many_num(bits).unwrap().to_vec();
+            assert_eq!(bits_val.len(), n);
+            let recomposed = bits_val
+                .iter()
+                .enumerate()
+                .map(|(i, &bit)| bit << i)
+                .sum::<i32>();
+            assert_eq!(recomposed, x_val);
+
+            dsl.set_program_output(format!("num[{}]", n), bits).unwrap();
+            test_program(
+                dsl,
+                script! {
+                    for bit in bits_val {
+                        { bit }
+                    }
+                },
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_to_bits_tampered_hints() {
+        let mut dsl = new_dsl();
+        let x = dsl.alloc_input("num", Element::Num(5)).unwrap();
+        let bits = to_bits(&mut dsl, x, 4).unwrap();
+        dsl.set_program_output("num[4]", bits).unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+
+        // a non-boolean bit, which is as truthy as 1 for the recomposition
+        let mut bad_hints = program.hint.clone();
+        bad_hints[0].data = Element::Num(3);
+        assert!(simulate_with_hints(&program, &bad_hints).is_err());
+    }
+
+    #[test]
+    fn test_to_bits_out_of_range() {
+        let mut dsl = new_dsl();
+        let x = dsl.alloc_input("num", Element::Num(16)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(-1)).unwrap();
+        assert!(to_bits(&mut dsl, x, 4).is_err());
+        assert!(to_bits(&mut dsl, y, 4).is_err());
+        assert!(to_bits(&mut dsl, x, 32).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::functions::{FunctionOutput, FunctionWithOptionsMetadata};
+use crate::options::Options;
+use crate::treepp::*;
+use anyhow::{Error, Result};
+
+pub fn load_functions(dsl: &mut DSL) -> Result<()> {
+    dsl.add_function(
+        "xor",
+        FunctionWithOptionsMetadata::new(xor_trace, xor_gadget, vec!["str", "str"], vec!["str"]),
+    )
+}
+
+pub fn xor(dsl: &mut DSL, a: usize, b: usize) -> Result<usize> {
+    let len = dsl.get_str(a)?.len();
+    let res = dsl.execute_with_options("xor", &[a, b], &Options::new().with_u32("len", len as u32))?;
+    Ok(res[0])
+}
+
+fn xor_trace(dsl: &mut DSL, inputs: &[usize], options: &Options) -> Result<FunctionOutput> {
+    let a = dsl.get_str(inputs[0])?.to_vec();
+    let b = dsl.get_str(inputs[1])?.to_vec();
+
+    if a.len() != b.len() {
+        return Err(Error::msg("The two strings to be xor-ed must have the same length"));
+    }
+    if options.get_u32("len")? as usize != a.len() {
+        return Err(Error::msg("The length option does not match the length of the strings"));
+    }
+
+    // the script recomposes both inputs from their bytes, which are provided as hints
+    let mut new_hints = vec![];
+    for (&x, &y) in a.iter().zip(b.iter()) {
+        new_hints.push(MemoryEntry::new("num", Element::Num(x as i32)));
+        new_hints.push(MemoryEntry::new("num", Element::Num(y as i32)));
+    }
+
+    let c = a.iter().zip(b.iter()).map(|(&x, &y)| x ^ y).collect();
+
+    Ok(FunctionOutput {
+        new_elements: vec![MemoryEntry::new("str", Element::Str(c))],
+        new_hints,
+    })
+}
+
+fn xor_gadget(_: &[usize], options: &Options) -> Result<Script> {
+    let len = options.get_u32("len")? as usize;
+
+    // This relies on OP_CAT to reassemble the strings from the hinted bytes.
+    Ok(script! {
+        // the lookup table, where the single-byte string `[v]` is at depth `v`
+        for v in (0..=255u8).rev() {
+            { push_byte(v) }
+        }
+
+        // the accumulators for a, b, and the result
+        OP_0 OP_0 OP_0
+
+        for _ in 0..len {
+            // stack: a b table acc_a acc_b acc_c
+            OP_DEPTH OP_1SUB OP_ROLL
+            OP_DEPTH OP_1SUB OP_ROLL
+
+            OP_OVER 0 256 OP_WITHIN OP_VERIFY
+            OP_DUP 0 256 OP_WITHIN OP_VERIFY
+
+            OP_2DUP
+            { xor_byte() }
+
+            // stack: a b table acc_a acc_b acc_c x y x^y
+            5 OP_ADD OP_PICK
+            3 OP_ROLL OP_SWAP OP_CAT OP_TOALTSTACK
+
+            3 OP_ADD OP_PICK
+            OP_ROT OP_SWAP OP_CAT OP_TOALTSTACK
+
+            OP_1ADD OP_PICK
+            OP_CAT
+
+            OP_FROMALTSTACK OP_FROMALTSTACK
+        }
+
+        // check that the recomposed strings match the inputs
+        OP_TOALTSTACK
+        258 OP_ROLL OP_EQUALVERIFY
+        257 OP_ROLL OP_EQUALVERIFY
+
+        for _ in 0..128 {
+            OP_2DROP
+        }
+        OP_FROMALTSTACK
+    })
+}
+
+fn push_byte(v: u8) -> Script {
+    // use the minimal push for the single-byte strings that have a dedicated opcode
+    match v {
+        1..=16 => script! { { v as i32 } },
+        0x81 => script! { OP_1NEGATE },
+        _ => script! { { vec![v] } },
+    }
+}
+
+fn xor_byte() -> Script {
+    // stack: x y, both in [0, 256), output: x ^ y
+    script! {
+        OP_0 OP_ROT OP_ROT
+        for k in (0..8).rev() {
+            // stack: r x y
+            OP_SWAP
+            OP_DUP { 1 << k } OP_GREATERTHANOREQUAL
+            OP_DUP OP_TOALTSTACK
+            OP_IF { 1 << k } OP_SUB OP_ENDIF
+            OP_SWAP
+            OP_DUP { 1 << k } OP_GREATERTHANOREQUAL
+            OP_DUP OP_FROMALTSTACK OP_NUMNOTEQUAL OP_TOALTSTACK
+            OP_IF { 1 << k } OP_SUB OP_ENDIF
+            OP_FROMALTSTACK
+            OP_IF OP_ROT { 1 << k } OP_ADD OP_ROT OP_ROT OP_ENDIF
+        }
+        OP_2DROP
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::{Element, DSL};
+    use crate::gadgets::load_data_types;
+    use crate::gadgets::xor::{load_functions, xor};
+    use crate::{simulate, simulate_with_hints, test_program};
+    use crate::treepp::*;
+
+    #[test]
+    fn test_xor() {
+        let a_val = b"\x1c\x01\x11\x00\x1f\x01\x01\x00\x06\x1a\x02\x4b\x53\x53\x50\x09\x18\x1c".to_vec();
+        let b_val = b"hit the bull's eye".to_vec();
+        let expected = b"the kid don't play".to_vec();
+
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("str", Element::Str(a_val)).unwrap();
+        let b = dsl.alloc_input("str", Element::Str(b_val)).unwrap();
+
+        let res = xor(&mut dsl, a, b).unwrap();
+        assert_eq!(dsl.get_str(res).unwrap(), expected.as_slice());
+
+        dsl.set_program_output("str", res).unwrap();
+
+        test_program(
+            dsl,
+            script! {
+                { expected }
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_xor_tampered_hints() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("str", Element::Str(b"abcd".to_vec())).unwrap();
+        let b = dsl.alloc_input("str", Element::Str(b"wxyz".to_vec())).unwrap();
+        let res = xor(&mut dsl, a, b).unwrap();
+        dsl.set_program_output("str", res).unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        simulate(&program).unwrap();
+
+        // flipping a bit in one of the hinted bytes breaks the recomposition check
+        let mut bad_hints = program.hint.clone();
+        bad_hints[0].data = Element::Num(b'a' as i32 ^ 1);
+        assert!(simulate_with_hints(&program, &bad_hints).is_err());
+    }
+
+    #[test]
+    fn test_xor_length_mismatch() {
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        load_functions(&mut dsl).unwrap();
+
+        let a = dsl.alloc_input("str", Element::Str(vec![1, 2, 3])).unwrap();
+        let b = dsl.alloc_input("str", Element::Str(vec![1, 2])).unwrap();
+
+        assert!(xor(&mut dsl, a, b).is_err());
+    }
+}
@@ -0,0 +1,621 @@
+use crate::data_type::DataTypeRegistry;
+use crate::dsl::{Element, ElementType, MemoryEntry, TraceEntry, DSL};
+use crate::functions::FunctionRegistry;
+use crate::options::{Options, OptionsEntry};
+use crate::script::{memory_entry_to_json, to_hex};
+use crate::treepp::Script;
+use anyhow::{Error, Result};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+// Two serializations of a program: a line-based text IR and a JSON snapshot. Both keep the inputs,
+// the trace with its constants and hints, the names, annotations, pinned steps and the outputs, and
+// load back through `DSL::rebuild`, so a program with embedded scripts can be written but not loaded.
+//
+// Each line of the IR is a keyword followed by JSON values:
+//
+//   input "num" 1 "a"
+//   constant 2 "num" 100
+//   hint 3 "num" 7
+//   call "add" [0,1]
+//   call "to_bits" [0] {"n": {"u32": 4}}
+//   embed "8b" [4] ["num"]
+//   relative_timelock 10
+//   absolute_timelock 500000
+//   name 5 "sum"
+//   annotate 4 "note" "the sum"
+//   pin 4
+//   output [5]
+
+const IR_KEYWORDS: [&str; 11] = [
+    "input",
+    "constant",
+    "hint",
+    "call",
+    "embed",
+    "relative_timelock",
+    "absolute_timelock",
+    "name",
+    "annotate",
+    "pin",
+    "output",
+];
+
+impl DSL {
+    pub fn to_ir(&self) -> String {
+        let mut lines = vec![];
+        for entry in self.inputs() {
+            let mut line = format!("input {} {}", json!(entry.data_type), element_to_json(&entry.data));
+            if let Some(description) = &entry.description {
+                line.push_str(&format!(" {}", json!(description)));
+            }
+            lines.push(line);
+        }
+        for trace_entry in self.trace.iter() {
+            let line = match trace_entry {
+                TraceEntry::FunctionCall(name, inputs) => format!("call {} {}", json!(name), json!(inputs)),
+                TraceEntry::FunctionCallWithOptions(name, inputs, options) => format!(
+                    "call {} {} {}",
+                    json!(name),
+                    json!(inputs),
+                    options_to_json(options)
+                ),
+                TraceEntry::AllocatedConstant(idx) | TraceEntry::AllocatedHint(idx) => {
+                    let keyword = if matches!(trace_entry, TraceEntry::AllocatedConstant(_)) {
+                        "constant"
+                    } else {
+                        "hint"
+                    };
+                    let entry = self.memory.get(idx).unwrap();
+                    let mut line = format!(
+                        "{} {} {} {}",
+                        keyword,
+                        idx,
+                        json!(entry.data_type),
+                        element_to_json(&entry.data)
+                    );
+                    if let Some(description) = &entry.description {
+                        line.push_str(&format!(" {}", json!(description)));
+                    }
+                    line
+                }
+                TraceEntry::EmbeddedScript(script, inputs, output_types) => format!(
+                    "embed {} {} {}",
+                    json!(to_hex(script.as_bytes())),
+                    json!(inputs),
+                    json!(output_types)
+                ),
+                TraceEntry::RelativeTimelock(sequence) => format!("relative_timelock {}", sequence),
+                TraceEntry::AbsoluteTimelock(height_or_time) => {
+                    format!("absolute_timelock {}", height_or_time)
+                }
+            };
+            lines.push(line);
+        }
+        for (idx, name) in self.computed_names() {
+            lines.push(format!("name {} {}", idx, json!(name)));
+        }
+        for (step, annotations) in self.sorted_annotations() {
+            for (key, value) in annotations.iter() {
+                lines.push(format!("annotate {} {} {}", step, json!(key), json!(value)));
+            }
+        }
+        for step in self.pinned.iter() {
+            lines.push(format!("pin {}", step));
+        }
+        lines.push(format!("output {}", json!(self.output)));
+
+        let mut res = lines.join("\n");
+        res.push('\n');
+        res
+    }
+
+    pub fn from_ir(
+        ir: &str,
+        data_type_registry: DataTypeRegistry,
+        function_registry: FunctionRegistry,
+    ) -> Result<DSL> {
+        let mut parts = ProgramParts::default();
+
+        for (line_no, line) in ir.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let args = serde_json::Deserializer::from_str(rest)
+                .into_iter::<Value>()
+                .collect::<std::result::Result<Vec<Value>, _>>()
+                .map_err(|e| Error::msg(format!("The line {} of the IR is malformed: {}", line_no + 1, e)))?;
+
+            parts
+                .push_ir_line(keyword, &args, &data_type_registry)
+                .map_err(|e| e.context(format!("The line {} of the IR is invalid", line_no + 1)))?;
+        }
+
+        parts.build(data_type_registry, function_registry)
+    }
+
+    pub fn to_snapshot(&self) -> Value {
+        let trace = trace_to_json(&self.trace, &self.allocations());
+
+        json!({
+            "inputs": self.inputs().iter().map(memory_entry_to_json).collect::<Vec<Value>>(),
+            "trace": trace,
+            "names": self.computed_names(),
+            "annotations": self.sorted_annotations(),
+            "pinned": self.pinned,
+            "output": self.output,
+        })
+    }
+
+    pub fn from_snapshot(
+        snapshot: &Value,
+        data_type_registry: DataTypeRegistry,
+        function_registry: FunctionRegistry,
+    ) -> Result<DSL> {
+        let mut parts = ProgramParts::default();
+
+        for entry in json_array(&snapshot["inputs"])?.iter() {
+            parts.inputs.push(memory_entry_from_json(entry, &data_type_registry)?);
+        }
+
+        (parts.trace, parts.allocations) = trace_from_json(&snapshot["trace"], &data_type_registry)?;
+
+        for pair in json_array(&snapshot["names"])?.iter() {
+            parts.names.push((json_idx(&pair[0])?, json_str(&pair[1])?.to_string()));
+        }
+        for pair in json_array(&snapshot["annotations"])?.iter() {
+            let step = json_idx(&pair[0])?;
+            let map = pair[1]
+                .as_object()
+                .ok_or(Error::msg("The annotations of a trace entry must be an object"))?;
+            for (key, value) in map.iter() {
+                parts.annotations.push((step, key.clone(), json_str(value)?.to_string()));
+            }
+        }
+        parts.pinned = json_idxs(&snapshot["pinned"])?;
+        parts.output = json_idxs(&snapshot["output"])?;
+
+        parts.build(data_type_registry, function_registry)
+    }
+
+    fn inputs(&self) -> Vec<MemoryEntry> {
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        (0..num_inputs)
+            .map(|idx| self.memory.get(&idx).unwrap().clone())
+            .collect()
+    }
+
+    fn computed_names(&self) -> Vec<(usize, String)> {
+        // the names of the inputs, constants and hints are written together with their data
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let allocated = self
+            .trace
+            .iter()
+            .filter_map(|trace_entry| match trace_entry {
+                TraceEntry::AllocatedConstant(idx) | TraceEntry::AllocatedHint(idx) => Some(*idx),
+                _ => None,
+            })
+            .collect::<Vec<usize>>();
+
+        let mut names = self
+            .memory
+            .iter()
+            .filter(|(idx, _)| **idx >= num_inputs && !allocated.contains(*idx))
+            .filter_map(|(idx, entry)| entry.description.clone().map(|name| (*idx, name)))
+            .collect::<Vec<(usize, String)>>();
+        names.sort();
+        names
+    }
+
+    fn sorted_annotations(&self) -> Vec<(usize, BTreeMap<String, String>)> {
+        let mut annotations = self
+            .annotations
+            .iter()
+            .map(|(step, map)| (*step, map.clone()))
+            .collect::<Vec<_>>();
+        annotations.sort_by_key(|(step, _)| *step);
+        annotations
+    }
+}
+
+pub(crate) fn trace_to_json(trace: &[TraceEntry], allocations: &[MemoryEntry]) -> Vec<Value> {
+    // the constants and hints are given in the order they appear in the trace, as `rebuild` takes them
+    let mut allocations = allocations.iter();
+    trace
+        .iter()
+        .map(|trace_entry| match trace_entry {
+            TraceEntry::FunctionCall(name, inputs) => json!({
+                "kind": "call",
+                "function": name,
+                "inputs": inputs,
+            }),
+            TraceEntry::FunctionCallWithOptions(name, inputs, options) => json!({
+                "kind": "call",
+                "function": name,
+                "inputs": inputs,
+                "options": options_to_json(options),
+            }),
+            TraceEntry::AllocatedConstant(idx) | TraceEntry::AllocatedHint(idx) => {
+                let kind = if matches!(trace_entry, TraceEntry::AllocatedConstant(_)) {
+                    "constant"
+                } else {
+                    "hint"
+                };
+                let mut entry = memory_entry_to_json(allocations.next().unwrap());
+                entry["kind"] = json!(kind);
+                entry["idx"] = json!(idx);
+                entry
+            }
+            TraceEntry::EmbeddedScript(script, inputs, output_types) => json!({
+                "kind": "embed",
+                "script": to_hex(script.as_bytes()),
+                "inputs": inputs,
+                "output_types": output_types,
+            }),
+            TraceEntry::RelativeTimelock(sequence) => json!({
+                "kind": "relative_timelock",
+                "sequence": sequence,
+            }),
+            TraceEntry::AbsoluteTimelock(height_or_time) => json!({
+                "kind": "absolute_timelock",
+                "height_or_time": height_or_time,
+            }),
+        })
+        .collect()
+}
+
+pub(crate) fn trace_from_json(
+    values: &Value,
+    data_type_registry: &DataTypeRegistry,
+) -> Result<(Vec<TraceEntry>, Vec<MemoryEntry>)> {
+    let mut trace = vec![];
+    let mut allocations = vec![];
+    for entry in json_array(values)?.iter() {
+        let trace_entry = match entry["kind"].as_str() {
+            Some("call") => {
+                let name = json_str(&entry["function"])?.to_string();
+                let inputs = json_idxs(&entry["inputs"])?;
+                if entry["options"].is_null() {
+                    TraceEntry::FunctionCall(name, inputs)
+                } else {
+                    TraceEntry::FunctionCallWithOptions(name, inputs, options_from_json(&entry["options"])?)
+                }
+            }
+            Some(kind @ ("constant" | "hint")) => {
+                let idx = json_idx(&entry["idx"])?;
+                allocations.push(memory_entry_from_json(entry, data_type_registry)?);
+                if kind == "constant" {
+                    TraceEntry::AllocatedConstant(idx)
+                } else {
+                    TraceEntry::AllocatedHint(idx)
+                }
+            }
+            Some("embed") => TraceEntry::EmbeddedScript(
+                Script::from_bytes(from_hex(json_str(&entry["script"])?)?),
+                json_idxs(&entry["inputs"])?,
+                json_array(&entry["output_types"])?
+                    .iter()
+                    .map(|v| json_str(v).map(|s| s.to_string()))
+                    .collect::<Result<Vec<String>>>()?,
+            ),
+            Some("relative_timelock") => TraceEntry::RelativeTimelock(
+                u32::try_from(json_u64(&entry["sequence"])?)
+                    .map_err(|_| Error::msg("The relative timelock is out of range"))?,
+            ),
+            Some("absolute_timelock") => TraceEntry::AbsoluteTimelock(
+                u32::try_from(json_u64(&entry["height_or_time"])?)
+                    .map_err(|_| Error::msg("The absolute timelock is out of range"))?,
+            ),
+            _ => return Err(Error::msg("A trace entry has an unknown kind")),
+        };
+        trace.push(trace_entry);
+    }
+    Ok((trace, allocations))
+}
+
+pub fn structurally_equal(a: &DSL, b: &DSL) -> bool {
+    // the registries and the caches such as `interned` are not part of the structure
+    fn entries_equal(x: &MemoryEntry, y: &MemoryEntry) -> bool {
+        x.data_type == y.data_type && x.data == y.data && x.description == y.description
+    }
+
+    a.memory.len() == b.memory.len()
+        && a.memory.iter().all(|(idx, x)| b.memory.get(idx).is_some_and(|y| entries_equal(x, y)))
+        && a.memory_last_idx == b.memory_last_idx
+        && a.num_inputs == b.num_inputs
+        && a.trace == b.trace
+        && a.hint.len() == b.hint.len()
+        && a.hint.iter().zip(b.hint.iter()).all(|(x, y)| entries_equal(x, y))
+        && a.hint_origins == b.hint_origins
+        && a.output == b.output
+        && a.annotations == b.annotations
+        && a.pinned == b.pinned
+}
+
+#[derive(Default)]
+struct ProgramParts {
+    inputs: Vec<MemoryEntry>,
+    allocations: Vec<MemoryEntry>,
+    trace: Vec<TraceEntry>,
+    names: Vec<(usize, String)>,
+    annotations: Vec<(usize, String, String)>,
+    pinned: Vec<usize>,
+    output: Vec<usize>,
+}
+
+impl ProgramParts {
+    fn push_ir_line(&mut self, keyword: &str, args: &[Value], registry: &DataTypeRegistry) -> Result<()> {
+        match (keyword, args) {
+            ("input", [data_type, data]) | ("input", [data_type, data, _]) => {
+                let mut entry = memory_entry_from_parts(data_type, data, registry)?;
+                entry.description = args.get(2).map(json_str).transpose()?.map(|s| s.to_string());
+                self.inputs.push(entry);
+            }
+            ("constant" | "hint", [idx, data_type, data]) | ("constant" | "hint", [idx, data_type, data, _]) => {
+                let idx = json_idx(idx)?;
+                let mut entry = memory_entry_from_parts(data_type, data, registry)?;
+                entry.description = args.get(3).map(json_str).transpose()?.map(|s| s.to_string());
+                self.allocations.push(entry);
+                self.trace.push(if keyword == "constant" {
+                    TraceEntry::AllocatedConstant(idx)
+                } else {
+                    TraceEntry::AllocatedHint(idx)
+                });
+            }
+            ("call", [name, inputs]) => self
+                .trace
+                .push(TraceEntry::FunctionCall(json_str(name)?.to_string(), json_idxs(inputs)?)),
+            ("call", [name, inputs, options]) => self.trace.push(TraceEntry::FunctionCallWithOptions(
+                json_str(name)?.to_string(),
+                json_idxs(inputs)?,
+                options_from_json(options)?,
+            )),
+            ("embed", [script, inputs, output_types]) => self.trace.push(TraceEntry::EmbeddedScript(
+                Script::from_bytes(from_hex(json_str(script)?)?),
+                json_idxs(inputs)?,
+                json_array(output_types)?
+                    .iter()
+                    .map(|v| json_str(v).map(|s| s.to_string()))
+                    .collect::<Result<Vec<String>>>()?,
+            )),
+            ("relative_timelock", [sequence]) => self.trace.push(TraceEntry::RelativeTimelock(
+                u32::try_from(json_u64(sequence)?)
+                    .map_err(|_| Error::msg("The relative timelock is out of range"))?,
+            )),
+            ("absolute_timelock", [height_or_time]) => self.trace.push(TraceEntry::AbsoluteTimelock(
+                u32::try_from(json_u64(height_or_time)?)
+                    .map_err(|_| Error::msg("The absolute timelock is out of range"))?,
+            )),
+            ("name", [idx, name]) => self.names.push((json_idx(idx)?, json_str(name)?.to_string())),
+            ("annotate", [step, key, value]) => self.annotations.push((
+                json_idx(step)?,
+                json_str(key)?.to_string(),
+                json_str(value)?.to_string(),
+            )),
+            ("pin", [step]) => self.pinned.push(json_idx(step)?),
+            ("output", [output]) => self.output = json_idxs(output)?,
+            _ if IR_KEYWORDS.contains(&keyword) => {
+                return Err(Error::msg(format!(
+                    "The IR keyword `{}` has a wrong number of arguments",
+                    keyword
+                )));
+            }
+            _ => return Err(Error::msg(format!("The IR keyword `{}` is unknown", keyword))),
+        }
+        Ok(())
+    }
+
+    fn build(self, data_type_registry: DataTypeRegistry, function_registry: FunctionRegistry) -> Result<DSL> {
+        let mut dsl = DSL::rebuild(
+            self.trace,
+            self.inputs,
+            self.allocations,
+            self.output,
+            data_type_registry,
+            function_registry,
+        )?;
+
+        for (idx, name) in self.names.into_iter() {
+            dsl.set_name(idx, name)?;
+        }
+        for (step, key, value) in self.annotations.into_iter() {
+            dsl.annotate(step, key, value)?;
+        }
+        for step in self.pinned.into_iter() {
+            if step >= dsl.trace.len() {
+                return Err(Error::msg("The pinned trace entry does not exist"));
+            }
+            dsl.pinned.insert(step);
+        }
+        Ok(dsl)
+    }
+}
+
+fn element_to_json(data: &Element) -> Value {
+    match data {
+        Element::Num(v) => json!(v),
+        Element::ManyNum(v) => json!(v),
+        Element::Str(v) => json!(to_hex(v)),
+        Element::ManyStr(v) => json!(v.iter().map(|vv| to_hex(vv)).collect::<Vec<String>>()),
+    }
+}
+
+pub(crate) fn element_from_json(value: &Value, element_type: &ElementType) -> Result<Element> {
+    fn num(value: &Value) -> Result<i32> {
+        value
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .ok_or(Error::msg("The number is malformed or out of range"))
+    }
+
+    match element_type {
+        ElementType::Num => Ok(Element::Num(num(value)?)),
+        ElementType::ManyNum(_) => Ok(Element::ManyNum(
+            json_array(value)?.iter().map(num).collect::<Result<Vec<i32>>>()?,
+        )),
+        ElementType::Str => Ok(Element::Str(from_hex(json_str(value)?)?)),
+        ElementType::ManyStr(_) => Ok(Element::ManyStr(
+            json_array(value)?
+                .iter()
+                .map(|v| from_hex(json_str(v)?))
+                .collect::<Result<Vec<Vec<u8>>>>()?,
+        )),
+    }
+}
+
+fn memory_entry_from_parts(data_type: &Value, data: &Value, registry: &DataTypeRegistry) -> Result<MemoryEntry> {
+    let data_type = json_str(data_type)?;
+    let metadata = registry
+        .map
+        .get(data_type)
+        .ok_or(Error::msg("The data type has not been registered"))?;
+    Ok(MemoryEntry::new(data_type, element_from_json(data, &metadata.element_type)?))
+}
+
+pub(crate) fn memory_entry_from_json(value: &Value, registry: &DataTypeRegistry) -> Result<MemoryEntry> {
+    let mut entry = memory_entry_from_parts(&value["data_type"], &value["data"], registry)?;
+    if !value["description"].is_null() {
+        entry.description = Some(json_str(&value["description"])?.to_string());
+    }
+    Ok(entry)
+}
+
+fn options_to_json(options: &Options) -> Value {
+    let mut map = Map::new();
+    for (key, entry) in options.map.iter() {
+        let value = match entry {
+            OptionsEntry::String(v) => json!({ "string": v }),
+            OptionsEntry::Binary(v) => json!({ "binary": to_hex(v) }),
+            OptionsEntry::MultiBinary(v) => {
+                json!({ "multi_binary": v.iter().map(|vv| to_hex(vv)).collect::<Vec<String>>() })
+            }
+            OptionsEntry::U32(v) => json!({ "u32": v }),
+            OptionsEntry::MultiU32(v) => json!({ "multi_u32": v }),
+            OptionsEntry::U64(v) => json!({ "u64": v }),
+            OptionsEntry::MultiU64(v) => json!({ "multi_u64": v }),
+        };
+        map.insert(key.clone(), value);
+    }
+    Value::Object(map)
+}
+
+fn options_from_json(value: &Value) -> Result<Options> {
+    fn u32_of(value: &Value) -> Result<u32> {
+        u32::try_from(json_u64(value)?).map_err(|_| Error::msg("The option is out of range"))
+    }
+
+    let map = value
+        .as_object()
+        .ok_or(Error::msg("The options must be an object"))?;
+
+    let mut options = Options::new();
+    for (key, entry) in map.iter() {
+        let mut kinds = entry
+            .as_object()
+            .ok_or(Error::msg("Each option must be an object with a single kind"))?
+            .iter();
+        let (kind, v) = match (kinds.next(), kinds.next()) {
+            (Some((kind, v)), None) => (kind.as_str(), v),
+            _ => return Err(Error::msg("Each option must be an object with a single kind")),
+        };
+        let entry = match kind {
+            "string" => OptionsEntry::String(json_str(v)?.to_string()),
+            "binary" => OptionsEntry::Binary(from_hex(json_str(v)?)?),
+            "multi_binary" => OptionsEntry::MultiBinary(
+                json_array(v)?
+                    .iter()
+                    .map(|vv| from_hex(json_str(vv)?))
+                    .collect::<Result<Vec<Vec<u8>>>>()?,
+            ),
+            "u32" => OptionsEntry::U32(u32_of(v)?),
+            "multi_u32" => OptionsEntry::MultiU32(json_array(v)?.iter().map(u32_of).collect::<Result<Vec<u32>>>()?),
+            "u64" => OptionsEntry::U64(json_u64(v)?),
+            "multi_u64" => OptionsEntry::MultiU64(json_array(v)?.iter().map(json_u64).collect::<Result<Vec<u64>>>()?),
+            _ => return Err(Error::msg(format!("The option kind `{}` is unknown", kind))),
+        };
+        options = options.with_entry(key, entry);
+    }
+    Ok(options)
+}
+
+pub(crate) fn from_hex(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(Error::msg("The hex string has an odd length"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or(Error::msg("The hex string is malformed"))
+        })
+        .collect()
+}
+
+pub(crate) fn json_array(value: &Value) -> Result<&Vec<Value>> {
+    value.as_array().ok_or(Error::msg("Expected a JSON array"))
+}
+
+pub(crate) fn json_str(value: &Value) -> Result<&str> {
+    value.as_str().ok_or(Error::msg("Expected a JSON string"))
+}
+
+fn json_u64(value: &Value) -> Result<u64> {
+    value.as_u64().ok_or(Error::msg("Expected a non-negative JSON integer"))
+}
+
+fn json_idx(value: &Value) -> Result<usize> {
+    Ok(json_u64(value)? as usize)
+}
+
+pub(crate) fn json_idxs(value: &Value) -> Result<Vec<usize>> {
+    json_array(value)?.iter().map(json_idx).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::dsl::{Element, DSL};
+    use crate::ir::structurally_equal;
+
+    #[test]
+    fn test_ir_roundtrip() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.set_name(a, "a").unwrap();
+        let b = dsl.alloc_constant("num", Element::Num(100)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        dsl.set_name(c, "sum").unwrap();
+        dsl.set_program_output("num", c).unwrap();
+
+        let ir = dsl.to_ir();
+        assert_eq!(
+            ir,
+            "input \"num\" 1 \"a\"\nconstant 1 \"num\" 100\ncall \"add\" [0,1]\nname 2 \"sum\"\noutput [2]\n"
+        );
+
+        let reloaded = DSL::from_ir(
+            &ir,
+            dsl.data_type_registry.clone(),
+            dsl.function_registry.clone(),
+        )
+        .unwrap();
+        assert!(structurally_equal(&dsl, &reloaded));
+    }
+
+    #[test]
+    fn test_ir_errors() {
+        let dsl = new_dsl_with_add();
+        let load = |ir: &str| {
+            DSL::from_ir(ir, dsl.data_type_registry.clone(), dsl.function_registry.clone())
+        };
+
+        assert!(load("input \"num\" 1\nfrobnicate 3\n").is_err());
+        assert!(load("input \"num\"\n").is_err());
+        assert!(load("input \"str\" \"ab\"\n").is_err());
+        assert!(load("input \"num\" 1\noutput [1]\n").is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::compiler::Compiler;
-use crate::dsl::DSL;
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::script::CompiledProgram;
 use crate::treepp::Script;
 use anyhow::{Error, Result};
 use bitcoin::opcodes::OP_TRUE;
@@ -22,6 +23,18 @@ pub mod compiler;
 
 pub mod options;
 
+pub mod gadgets;
+
+pub mod passes;
+
+pub mod listing;
+
+pub mod ir;
+
+pub mod biguint;
+
+pub mod testing;
+
 pub(crate) mod treepp {
     pub use bitcoin_script::{define_pushable, script};
 
@@ -34,27 +47,84 @@ use crate::treepp::*;
 pub fn test_program(dsl: DSL, expected_stack: Script) -> Result<()> {
     let program = Compiler::compiler(dsl)?;
 
+    let expected_elements = convert_to_witness(expected_stack)
+        .map_err(|x| anyhow::Error::msg(format!("final stack parsing error: {:?}", x)))?;
+
+    // the types of the outputs tell which of the expected elements are numbers, as long as the
+    // expected stack has the shape of the outputs
+    let output_stack = expected_final_stack(&program.output);
+    let expected_final_stack = if output_stack.len() == expected_elements.len() {
+        expected_elements
+            .into_iter()
+            .zip(output_stack)
+            .map(|(elem, (_, numeric))| (elem, numeric))
+            .collect()
+    } else {
+        expected_elements.into_iter().map(|elem| (elem, false)).collect::<Vec<_>>()
+    };
+
+    execute_program(&program.script, &program.input, &program.hint, &expected_final_stack)
+}
+
+pub fn simulate(program: &CompiledProgram) -> Result<()> {
+    simulate_with_hints(program, &program.hint)
+}
+
+pub fn simulate_with_hints(program: &CompiledProgram, hints: &[MemoryEntry]) -> Result<()> {
+    // the script must leave exactly the outputs computed by the trace generators on the stack
+    let expected_final_stack = expected_final_stack(&program.output);
+    execute_program(&program.script, &program.input, hints, &expected_final_stack)
+}
+
+/// The stack elements of the outputs, each with whether it is a number. The numbers are expected in
+/// their minimal encoding, where zero is the empty vector pushed by `OP_0`.
+pub(crate) fn expected_final_stack(outputs: &[MemoryEntry]) -> Vec<(Vec<u8>, bool)> {
+    let mut res = vec![];
+    for entry in outputs.iter() {
+        let numeric = matches!(entry.data, Element::Num(_) | Element::ManyNum(_));
+        res.extend(entry.data.to_witness().into_iter().map(|elem| (elem, numeric)));
+    }
+    res
+}
+
+pub(crate) fn execute_program(
+    program_script: &Script,
+    inputs: &[MemoryEntry],
+    hints: &[MemoryEntry],
+    expected_final_stack: &[(Vec<u8>, bool)],
+) -> Result<()> {
     let mut script = script! {
-        for elem in program.hint.iter() {
+        for elem in hints.iter() {
             { elem }
         }
-        for elem in program.input.iter() {
+        for elem in inputs.iter() {
             { elem }
         }
     }
     .to_bytes();
-    script.extend_from_slice(program.script.as_bytes());
-
-    let expected_final_stack = convert_to_witness(expected_stack)
-        .map_err(|x| anyhow::Error::msg(format!("final stack parsing error: {:?}", x)))?;
-    for elem in expected_final_stack.iter().rev() {
-        script.extend_from_slice(
-            script! {
-                { elem.to_vec() }
-                OP_EQUALVERIFY
-            }
-            .as_bytes(),
-        );
+    script.extend_from_slice(program_script.as_bytes());
+
+    for (elem, numeric) in expected_final_stack.iter().rev() {
+        // a numeric zero may also be left as the negative zero `0x80`, which the arithmetic
+        // opcodes treat as zero
+        if *numeric && elem.is_empty() {
+            script.extend_from_slice(
+                script! {
+                    OP_DUP { vec![0x80u8] } OP_EQUAL
+                    OP_SWAP OP_0 OP_EQUAL
+                    OP_BOOLOR OP_VERIFY
+                }
+                .as_bytes(),
+            );
+        } else {
+            script.extend_from_slice(
+                script! {
+                    { elem.to_vec() }
+                    OP_EQUALVERIFY
+                }
+                .as_bytes(),
+            );
+        }
     }
 
     script.push(OP_TRUE.to_u8());
@@ -70,6 +140,9 @@ pub fn test_program(dsl: DSL, expected_stack: Script) -> Result<()> {
     if exec_result.success {
         Ok(())
     } else {
-        Err(Error::msg("Script execution is not successful"))
+        Err(Error::msg(format!(
+            "Script execution is not successful: {:?}",
+            exec_result.error
+        )))
     }
 }
@@ -0,0 +1,123 @@
+use crate::dsl::{TraceEntry, DSL};
+use crate::script::to_hex;
+use std::fmt::Write;
+
+impl DSL {
+    pub fn to_listing(&self) -> String {
+        // values are written as `%idx`, followed by their name if they have one
+        let mut res = String::new();
+
+        writeln!(res, "inputs:").unwrap();
+        for idx in 0..self.num_inputs.unwrap_or(self.memory_last_idx) {
+            if let Some(entry) = self.memory.get(&idx) {
+                writeln!(res, "  {}: {} = {:?}", self.value_name(idx), entry.data_type, entry.data).unwrap();
+            }
+        }
+
+        writeln!(res, "trace:").unwrap();
+        for (step, (trace_entry, outputs)) in self.trace.iter().zip(self.trace_outputs()).enumerate() {
+            let results = outputs
+                .iter()
+                .map(|&idx| format!("{}: {}", self.value_name(idx), self.memory.get(&idx).unwrap().data_type))
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            let operation = match trace_entry {
+                TraceEntry::FunctionCall(function_name, inputs) => {
+                    format!("{}({})", function_name, self.value_names(inputs))
+                }
+                TraceEntry::FunctionCallWithOptions(function_name, inputs, options) => {
+                    let mut keys = options.map.keys().collect::<Vec<&String>>();
+                    keys.sort();
+                    let options = keys
+                        .iter()
+                        .map(|&key| format!("{} = {:?}", key, options.map.get(key).unwrap()))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    format!("{}({}) [{}]", function_name, self.value_names(inputs), options)
+                }
+                TraceEntry::EmbeddedScript(script, inputs, _) => {
+                    format!("script {}({})", to_hex(script.as_bytes()), self.value_names(inputs))
+                }
+                TraceEntry::RelativeTimelock(sequence) => format!("relative timelock {}", sequence),
+                TraceEntry::AbsoluteTimelock(height_or_time) => {
+                    format!("absolute timelock {}", height_or_time)
+                }
+                TraceEntry::AllocatedConstant(idx) => {
+                    format!("constant {:?}", self.memory.get(idx).unwrap().data)
+                }
+                TraceEntry::AllocatedHint(idx) => {
+                    format!("hint {:?}", self.memory.get(idx).unwrap().data)
+                }
+            };
+
+            let annotations = match self.annotations.get(&step) {
+                Some(annotations) => {
+                    let annotations = annotations
+                        .iter()
+                        .map(|(key, value)| format!("{} = {}", key, value))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    format!("  # {}", annotations)
+                }
+                None => String::new(),
+            };
+
+            writeln!(res, "  {}: {} = {}{}", step, results, operation, annotations).unwrap();
+        }
+
+        writeln!(res, "outputs:").unwrap();
+        for &idx in self.output.iter() {
+            writeln!(res, "  {}", self.value_name(idx)).unwrap();
+        }
+
+        res
+    }
+
+    fn value_name(&self, idx: usize) -> String {
+        match self.memory.get(&idx).and_then(|entry| entry.description.as_ref()) {
+            Some(name) => format!("%{} {:?}", idx, name),
+            None => format!("%{}", idx),
+        }
+    }
+
+    fn value_names(&self, idxs: &[usize]) -> String {
+        idxs.iter()
+            .map(|&idx| self.value_name(idx))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::dsl::Element;
+
+    #[test]
+    fn test_to_listing() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.set_name(a, "a").unwrap();
+        let b = dsl.alloc_constant("num", Element::Num(100)).unwrap();
+        let c = dsl.alloc_named_hint("num", Element::Num(5), "c").unwrap();
+        let d = dsl.execute("add", &[a, b]).unwrap()[0];
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+
+        let expected = "\
+inputs:
+  %0 \"a\": num = Num(1)
+trace:
+  0: %1: num = constant Num(100)
+  1: %2 \"c\": num = hint Num(5)
+  2: %3: num = add(%0 \"a\", %1)
+  3: %4: num = add(%3, %2 \"c\")
+outputs:
+  %4
+";
+        assert_eq!(dsl.to_listing(), expected);
+        assert_eq!(dsl.clone().to_listing(), dsl.to_listing());
+    }
+}
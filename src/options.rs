@@ -1,12 +1,12 @@
 use std::collections::HashMap;
 use anyhow::{Error, Result};
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Options {
     pub map: HashMap<String, OptionsEntry>
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum OptionsEntry {
     String(String),
     Binary(Vec<u8>),
@@ -64,6 +64,15 @@ impl Options {
         self
     }
 
+    pub fn with_defaults(&self, defaults: &Options) -> Options {
+        // the entries that are set here override those of the defaults
+        let mut res = defaults.clone();
+        for (name, entry) in self.map.iter() {
+            res.map.insert(name.clone(), entry.clone());
+        }
+        res
+    }
+
     pub fn exists(&self, name: &str) -> bool {
         self.map.contains_key(name)
     }
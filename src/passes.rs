@@ -0,0 +1,729 @@
+use crate::dsl::{TraceEntry, DSL};
+use anyhow::{Error, Result};
+use std::collections::{BTreeSet, HashMap};
+
+/// A transformation of the trace that the compiler can run before generating the script.
+pub trait TracePass {
+    fn run(&self, dsl: &mut DSL) -> Result<()>;
+}
+
+pub struct DeadCodeElimination;
+
+impl TracePass for DeadCodeElimination {
+    fn run(&self, dsl: &mut DSL) -> Result<()> {
+        dsl.eliminate_dead_code()
+    }
+}
+
+pub struct ConstantFolding;
+
+impl TracePass for ConstantFolding {
+    fn run(&self, dsl: &mut DSL) -> Result<()> {
+        dsl.fold_constants()
+    }
+}
+
+pub struct CommonSubexpressionElimination;
+
+impl TracePass for CommonSubexpressionElimination {
+    fn run(&self, dsl: &mut DSL) -> Result<()> {
+        dsl.eliminate_common_subexpressions()
+    }
+}
+
+impl DSL {
+    pub fn eliminate_dead_code(&mut self) -> Result<()> {
+        // the inputs are always kept
+        let (keep, _) = self.liveness();
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let order = (0..self.trace.len()).filter(|&step| keep[step]).collect::<Vec<usize>>();
+        self.reorder(&(0..num_inputs).collect::<Vec<usize>>(), &order)
+    }
+
+    pub fn minimal_inputs(&self) -> Vec<usize> {
+        // the inputs that the entries kept by the dead code elimination use, or that are outputs
+        let (_, live) = self.liveness();
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        (0..num_inputs).filter(|&idx| live[idx]).collect()
+    }
+
+    pub fn hoistable_constants(&self) -> Vec<usize> {
+        // The outputs of the function calls whose inputs are all constants, or such outputs in
+        // turn, which a setup could compute once and provide as inputs, as `fold_constants` would
+        // turn them into constants. Embedded scripts may check anything, so they are left out.
+        let trace_outputs = self.trace_outputs();
+        let mut is_constant = vec![false; self.memory_last_idx];
+        let mut res = vec![];
+        for (step, trace_entry) in self.trace.iter().enumerate() {
+            let input_idxs = trace_entry.input_idxs();
+            match trace_entry {
+                TraceEntry::AllocatedConstant(idx) => is_constant[*idx] = true,
+                TraceEntry::FunctionCall(..) | TraceEntry::FunctionCallWithOptions(..)
+                    if !input_idxs.is_empty() && input_idxs.iter().all(|&idx| is_constant[idx]) =>
+                {
+                    for &idx in trace_outputs[step].iter() {
+                        is_constant[idx] = true;
+                        res.push(idx);
+                    }
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+
+    pub fn subprogram(&self, outputs: &[usize]) -> Result<DSL> {
+        // Unlike the dead code elimination, only the entries that the given outputs depend on are
+        // kept, so checks and pinned entries are dropped, and so are the inputs that are not used.
+        for idx in outputs.iter() {
+            if !self.memory.contains_key(idx) {
+                return Err(Error::msg("Could not find the memory entry with the given index"));
+            }
+        }
+        let trace_outputs = self.trace_outputs();
+
+        let mut live = vec![false; self.memory_last_idx];
+        for &idx in outputs.iter() {
+            live[idx] = true;
+        }
+        let mut keep = vec![false; self.trace.len()];
+        for step in (0..self.trace.len()).rev() {
+            if trace_outputs[step].iter().any(|&idx| live[idx]) {
+                keep[step] = true;
+                for &idx in self.trace[step].input_idxs().iter() {
+                    live[idx] = true;
+                }
+            }
+        }
+
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let inputs = (0..num_inputs).filter(|&idx| live[idx]).collect::<Vec<usize>>();
+        let order = (0..self.trace.len()).filter(|&step| keep[step]).collect::<Vec<usize>>();
+
+        let mut dsl = self.clone();
+        dsl.output = outputs.to_vec();
+        dsl.reorder(&inputs, &order)?;
+        Ok(dsl)
+    }
+
+    fn liveness(&self) -> (Vec<bool>, Vec<bool>) {
+        // An entry is kept if one of its values is used by a kept entry or is an output, if it
+        // produces nothing, as it then only checks something, or if it is pinned. Returns the kept
+        // entries and the values used by them or output.
+        let num_steps = self.trace.len();
+        let trace_outputs = self.trace_outputs();
+
+        let mut live = vec![false; self.memory_last_idx];
+        for &idx in self.output.iter() {
+            live[idx] = true;
+        }
+
+        let mut keep = vec![false; num_steps];
+        for step in (0..num_steps).rev() {
+            let outputs = &trace_outputs[step];
+            if outputs.is_empty() || outputs.iter().any(|&idx| live[idx]) || self.pinned.contains(&step) {
+                keep[step] = true;
+                for &idx in self.trace[step].input_idxs().iter() {
+                    live[idx] = true;
+                }
+            }
+        }
+        (keep, live)
+    }
+
+    pub fn canonicalize_inputs(&mut self) -> Result<()> {
+        // A structural transform to run before compiling: the inputs are sorted by their data type
+        // and then their name, keeping the allocation order among equal ones, and the trace is
+        // re-indexed, so that programs that only differ in the order of allocating the inputs end
+        // up with the same witness layout.
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        let mut inputs = (0..num_inputs).collect::<Vec<usize>>();
+        inputs.sort_by_key(|idx| {
+            let entry = self.memory.get(idx).unwrap();
+            (entry.data_type.clone(), entry.description.clone())
+        });
+
+        let order = (0..self.trace.len()).collect::<Vec<usize>>();
+        self.reorder(&inputs, &order)
+    }
+
+    pub fn fold_constants(&mut self) -> Result<()> {
+        // A function call whose inputs are all constants is replaced by the constants of its
+        // outputs, which keep their indices, and its hints are dropped. Calls without outputs are
+        // kept, as they only check something.
+        let trace_outputs = self.trace_outputs();
+
+        let mut hints_by_step = vec![vec![]; self.trace.len()];
+        for (hint, &origin) in self.hint.iter().zip(self.hint_origins.iter()) {
+            hints_by_step[origin].push(hint.clone());
+        }
+
+        let mut is_constant = vec![false; self.memory_last_idx];
+        let mut trace = vec![];
+        let mut hint = vec![];
+        let mut hint_origins = vec![];
+        let mut annotations = HashMap::new();
+        let mut pinned = BTreeSet::new();
+        for (step, trace_entry) in self.trace.iter().enumerate() {
+            if let Some(entry_annotations) = self.annotations.get(&step) {
+                annotations.insert(trace.len(), entry_annotations.clone());
+            }
+            if self.pinned.contains(&step) {
+                pinned.insert(trace.len());
+            }
+
+            let input_idxs = trace_entry.input_idxs();
+            let foldable = matches!(
+                trace_entry,
+                TraceEntry::FunctionCall(..) | TraceEntry::FunctionCallWithOptions(..)
+            ) && !input_idxs.is_empty()
+                && !trace_outputs[step].is_empty()
+                && !self.pinned.contains(&step)
+                && input_idxs.iter().all(|&idx| is_constant[idx]);
+
+            if foldable {
+                for &idx in trace_outputs[step].iter() {
+                    is_constant[idx] = true;
+                    trace.push(TraceEntry::AllocatedConstant(idx));
+                }
+            } else {
+                if let TraceEntry::AllocatedConstant(idx) = trace_entry {
+                    is_constant[*idx] = true;
+                }
+                for entry in hints_by_step[step].drain(..) {
+                    hint.push(entry);
+                    hint_origins.push(trace.len());
+                }
+                trace.push(trace_entry.clone());
+            }
+        }
+
+        self.trace = trace;
+        self.hint = hint;
+        self.hint_origins = hint_origins;
+        self.annotations = annotations;
+        self.pinned = pinned;
+        Ok(())
+    }
+
+    pub fn eliminate_common_subexpressions(&mut self) -> Result<()> {
+        // A function call with the same function, inputs and options as an earlier one is dropped,
+        // and its outputs are replaced by those of the earlier call. Calls that produce hints or no
+        // outputs are kept, and so are pinned ones, which later calls are not merged into either.
+        let trace_outputs = self.trace_outputs();
+        let mut has_hints = vec![false; self.trace.len()];
+        for &origin in self.hint_origins.iter() {
+            has_hints[origin] = true;
+        }
+
+        let mut replaced = HashMap::new();
+        let resolve = |replaced: &HashMap<usize, usize>, idxs: &[usize]| {
+            idxs.iter().map(|idx| *replaced.get(idx).unwrap_or(idx)).collect::<Vec<usize>>()
+        };
+
+        // the earlier calls by their inputs, whose entries are then compared as a whole
+        let mut calls = HashMap::<Vec<usize>, Vec<usize>>::new();
+        let mut trace = vec![];
+        let mut order = vec![];
+        for (step, trace_entry) in self.trace.iter().enumerate() {
+            let trace_entry = match trace_entry {
+                TraceEntry::FunctionCall(function_name, input_idxs) => {
+                    TraceEntry::FunctionCall(function_name.clone(), resolve(&replaced, input_idxs))
+                }
+                TraceEntry::FunctionCallWithOptions(function_name, input_idxs, options) => {
+                    TraceEntry::FunctionCallWithOptions(
+                        function_name.clone(),
+                        resolve(&replaced, input_idxs),
+                        options.clone(),
+                    )
+                }
+                TraceEntry::EmbeddedScript(script, input_idxs, output_types) => TraceEntry::EmbeddedScript(
+                    script.clone(),
+                    resolve(&replaced, input_idxs),
+                    output_types.clone(),
+                ),
+                _ => trace_entry.clone(),
+            };
+
+            let mergeable = matches!(
+                trace_entry,
+                TraceEntry::FunctionCall(..) | TraceEntry::FunctionCallWithOptions(..)
+            ) && !trace_outputs[step].is_empty()
+                && !has_hints[step]
+                && !self.pinned.contains(&step);
+            if mergeable {
+                let earlier_calls = calls.entry(trace_entry.input_idxs().to_vec()).or_default();
+                if let Some(&earlier) = earlier_calls.iter().find(|&&earlier| trace[earlier] == trace_entry) {
+                    for (&idx, &earlier_idx) in trace_outputs[step].iter().zip(trace_outputs[earlier].iter()) {
+                        replaced.insert(idx, earlier_idx);
+                    }
+                    trace.push(trace_entry);
+                    continue;
+                }
+                earlier_calls.push(step);
+            }
+            order.push(step);
+            trace.push(trace_entry);
+        }
+
+        // the dropped calls are no longer used by anything, so reordering leaves them out
+        self.trace = trace;
+        self.output = resolve(&replaced, &self.output);
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        self.reorder(&(0..num_inputs).collect::<Vec<usize>>(), &order)
+    }
+
+    pub fn schedule_for_stack_depth(&mut self) -> Result<()> {
+        // The order from `schedule` is kept only if it lowers the peak.
+        if !self.pinned.is_empty() {
+            return Ok(());
+        }
+        let order = self.schedule();
+        let peak_before = self.peak_live_values();
+
+        let mut scheduled_dsl = self.clone();
+        scheduled_dsl.apply_schedule(&order)?;
+
+        if scheduled_dsl.peak_live_values() < peak_before {
+            *self = scheduled_dsl;
+        }
+        Ok(())
+    }
+
+    pub fn schedule(&self) -> Vec<usize> {
+        // Greedy list scheduling over the data dependencies of the trace: among the entries whose
+        // inputs are available, pick the one that frees the most values and produces the fewest,
+        // breaking ties by the original order. Pinned entries must keep their place, so a trace
+        // with any of them keeps its order.
+        let num_steps = self.trace.len();
+        if !self.pinned.is_empty() {
+            return (0..num_steps).collect();
+        }
+        let trace_outputs = self.trace_outputs();
+        let trace_inputs = self
+            .trace
+            .iter()
+            .map(|trace_entry| trace_entry.input_idxs().to_vec())
+            .collect::<Vec<Vec<usize>>>();
+
+        let mut producer = vec![None; self.memory_last_idx];
+        for (step, idxs) in trace_outputs.iter().enumerate() {
+            for &idx in idxs.iter() {
+                producer[idx] = Some(step);
+            }
+        }
+
+        let mut remaining_uses = vec![0usize; self.memory_last_idx];
+        for inputs in trace_inputs.iter() {
+            for &idx in inputs.iter() {
+                remaining_uses[idx] += 1;
+            }
+        }
+
+        let mut scheduled = vec![false; num_steps];
+        let mut order = vec![];
+        while order.len() < num_steps {
+            let mut best: Option<(isize, usize)> = None;
+            for step in 0..num_steps {
+                if scheduled[step] {
+                    continue;
+                }
+                let ready = trace_inputs[step]
+                    .iter()
+                    .all(|&idx| match producer[idx] {
+                        Some(p) => scheduled[p],
+                        None => true,
+                    });
+                if !ready {
+                    continue;
+                }
+
+                let mut distinct_inputs = trace_inputs[step].clone();
+                distinct_inputs.sort();
+                distinct_inputs.dedup();
+
+                let mut freed = 0;
+                for &idx in distinct_inputs.iter() {
+                    let uses_here = trace_inputs[step].iter().filter(|&&i| i == idx).count();
+                    if remaining_uses[idx] == uses_here && !self.output.contains(&idx) {
+                        freed += 1;
+                    }
+                }
+                let score = freed - trace_outputs[step].len() as isize;
+
+                let is_better = match best {
+                    Some((best_score, _)) => score > best_score,
+                    None => true,
+                };
+                if is_better {
+                    best = Some((score, step));
+                }
+            }
+
+            let (_, step) = best.unwrap();
+            scheduled[step] = true;
+            for &idx in trace_inputs[step].iter() {
+                remaining_uses[idx] -= 1;
+            }
+            order.push(step);
+        }
+        order
+    }
+
+    pub fn apply_schedule(&mut self, order: &[usize]) -> Result<()> {
+        // the order must have every trace step once, after the steps producing its inputs, and
+        // leave the pinned steps in place
+        let num_steps = self.trace.len();
+        let mut seen = vec![false; num_steps];
+        for (new_step, &step) in order.iter().enumerate() {
+            if step >= num_steps || seen[step] {
+                return Err(Error::msg("The schedule must have every trace step exactly once"));
+            }
+            seen[step] = true;
+            if self.pinned.contains(&step) && new_step != step {
+                return Err(Error::msg(format!(
+                    "The schedule moves the pinned trace step {}",
+                    step
+                )));
+            }
+        }
+        if order.len() != num_steps {
+            return Err(Error::msg("The schedule must have every trace step exactly once"));
+        }
+
+        // reordering fails, before changing anything, on a value used before the step producing it
+        let num_inputs = self.num_inputs.unwrap_or(self.memory_last_idx);
+        self.reorder(&(0..num_inputs).collect::<Vec<usize>>(), order)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{Compiler, OptimizeFor};
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::dsl::{Element, ElementType, TraceEntry, DSL};
+    use crate::passes::{CommonSubexpressionElimination, ConstantFolding, DeadCodeElimination, TracePass};
+    use crate::testing::assert_equivalent;
+    use crate::{simulate, test_program};
+    use crate::treepp::*;
+    use anyhow::Result;
+    use std::collections::HashMap;
+
+    fn build_wide_program() -> (DSL, i32) {
+        let mut dsl = new_dsl_with_add();
+
+        // compute all the terms first, and only then sum them up
+        let x = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let mut terms = vec![];
+        for _ in 0..4 {
+            terms.push(dsl.execute("add", &[x, x]).unwrap()[0]);
+        }
+        let mut sum = terms[0];
+        for &term in terms.iter().skip(1) {
+            sum = dsl.execute("add", &[sum, term]).unwrap()[0];
+        }
+        dsl.set_program_output("num", sum).unwrap();
+
+        (dsl, 24)
+    }
+
+    #[test]
+    fn test_schedule_for_stack_depth() {
+        let (mut dsl, expected) = build_wide_program();
+        let peak_before = dsl.peak_live_values();
+        assert_eq!(peak_before, 5);
+
+        dsl.schedule_for_stack_depth().unwrap();
+        let peak_after = dsl.peak_live_values();
+        assert_eq!(peak_after, 4);
+
+        let output = dsl.output[0];
+        assert_eq!(dsl.get_num(output).unwrap(), expected);
+        test_program(dsl, script! { { expected } }).unwrap();
+    }
+
+    #[test]
+    fn test_schedule() {
+        let (mut dsl, expected) = build_wide_program();
+        let num_steps = dsl.trace.len();
+        let order = dsl.schedule();
+        assert_ne!(order, (0..num_steps).collect::<Vec<usize>>());
+
+        let reversed = (0..num_steps).rev().collect::<Vec<usize>>();
+        assert!(dsl.clone().apply_schedule(&reversed).is_err());
+        assert!(dsl.clone().apply_schedule(&order[1..]).is_err());
+        let mut repeated = order.clone();
+        repeated[1] = repeated[0];
+        assert!(dsl.clone().apply_schedule(&repeated).is_err());
+
+        let original = dsl.clone();
+        dsl.apply_schedule(&order).unwrap();
+        assert_eq!(dsl.peak_live_values(), 4);
+        assert_equivalent(&original, &dsl, &[vec![Element::Num(-2)], vec![Element::Num(5)]]);
+        test_program(dsl, script! { { expected } }).unwrap();
+    }
+
+    #[test]
+    fn test_compile_optimized_for_stack_depth() {
+        let (dsl, _) = build_wide_program();
+        let program = Compiler::compile_optimized(dsl, OptimizeFor::StackDepth).unwrap();
+
+        let (mut scheduled, _) = build_wide_program();
+        scheduled.schedule_for_stack_depth().unwrap();
+        assert_eq!(program.script, Compiler::compiler(scheduled).unwrap().script);
+
+        let (dsl, _) = build_wide_program();
+        assert_ne!(program.script, Compiler::compiler(dsl).unwrap().script);
+    }
+
+    #[test]
+    fn test_eliminate_dead_code() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let a = dsl.execute("add", &[x, y]).unwrap()[0];
+        let c = dsl.alloc_constant("num", Element::Num(5)).unwrap();
+        let b = dsl.execute("add", &[a, c]).unwrap()[0];
+        dsl.execute("add", &[b, b]).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+
+        let original = dsl.clone();
+        dsl.eliminate_dead_code().unwrap();
+        assert_eq!(dsl.trace.len(), 1);
+        assert_eq!(dsl.memory_last_idx, 3);
+        assert_equivalent(
+            &original,
+            &dsl,
+            &[vec![Element::Num(0), Element::Num(0)], vec![Element::Num(-3), Element::Num(8)]],
+        );
+        test_program(dsl, script! { 3 }).unwrap();
+    }
+
+    #[test]
+    fn test_minimal_inputs() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let z = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let _w = dsl.alloc_input("num", Element::Num(4)).unwrap();
+        let a = dsl.execute("add", &[x, z]).unwrap()[0];
+        // y is only used by a dead entry, and w not at all
+        dsl.execute("add", &[y, y]).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+        dsl.set_program_output("num", z).unwrap();
+
+        assert_eq!(dsl.minimal_inputs(), vec![x, z]);
+    }
+
+    #[test]
+    fn test_hoistable_constants() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(5)).unwrap();
+        let d = dsl.alloc_constant("num", Element::Num(6)).unwrap();
+        let e = dsl.execute("add", &[c, d]).unwrap()[0];
+        let f = dsl.execute("add", &[e, e]).unwrap()[0];
+        let g = dsl.execute("add", &[f, x]).unwrap()[0];
+        dsl.set_program_output("num", g).unwrap();
+
+        // the constants allocated as such and the values that depend on the input are not hoisted
+        assert_eq!(dsl.hoistable_constants(), vec![e, f]);
+    }
+
+    #[test]
+    fn test_subprogram() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let z = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        let a = dsl.execute("add", &[x, y]).unwrap()[0];
+        let b = dsl.execute("add", &[z, z]).unwrap()[0];
+        let k = dsl.alloc_constant("num", Element::Num(5)).unwrap();
+        let c = dsl.execute("add", &[a, k]).unwrap()[0];
+        dsl.execute("add", &[c, c]).unwrap();
+        dsl.set_program_output("num", b).unwrap();
+        dsl.set_program_output("num", c).unwrap();
+
+        // z, b, and the unused sum c + c are not needed for c
+        let sub = dsl.subprogram(&[c]).unwrap();
+        assert_eq!(sub.num_inputs, Some(2));
+        assert_eq!(sub.trace.len(), 3);
+        assert_eq!(sub.get_num(sub.output[0]).unwrap(), 8);
+        assert!(dsl.subprogram(&[dsl.memory_last_idx]).is_err());
+
+        let mut expected = new_dsl_with_add();
+        let x = expected.alloc_input("num", Element::Num(1)).unwrap();
+        let y = expected.alloc_input("num", Element::Num(2)).unwrap();
+        let a = expected.execute("add", &[x, y]).unwrap()[0];
+        let k = expected.alloc_constant("num", Element::Num(5)).unwrap();
+        let c = expected.execute("add", &[a, k]).unwrap()[0];
+        expected.set_program_output("num", c).unwrap();
+
+        let program = Compiler::compiler(sub).unwrap();
+        assert_eq!(program.script, Compiler::compiler(expected).unwrap().script);
+        simulate(&program).unwrap();
+    }
+
+    #[test]
+    fn test_pinned() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let a = dsl.execute("add", &[x, y]).unwrap()[0];
+        dsl.execute("add", &[x, x]).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(5)).unwrap();
+        dsl.execute_pinned("add", &[y, y]).unwrap();
+        dsl.execute_pinned("add", &[c, c]).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+
+        // only the unpinned dead entry is removed, and the pinned function of a constant is not folded
+        dsl.fold_constants().unwrap();
+        dsl.eliminate_dead_code().unwrap();
+        assert_eq!(dsl.trace.len(), 4);
+        assert_eq!(dsl.pinned.iter().copied().collect::<Vec<usize>>(), vec![2, 3]);
+        assert!(matches!(&dsl.trace[2], TraceEntry::FunctionCall(_, inputs) if inputs == &vec![y, y]));
+        assert!(matches!(&dsl.trace[3], TraceEntry::FunctionCall(..)));
+        test_program(dsl, script! { 3 }).unwrap();
+    }
+
+    #[test]
+    fn test_eliminate_common_subexpressions() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let a = dsl.execute("add", &[x, y]).unwrap()[0];
+        let b = dsl.execute("add", &[x, y]).unwrap()[0];
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        // the same as c once b is replaced by a
+        let d = dsl.execute("add", &[b, a]).unwrap()[0];
+        let p = dsl.execute_pinned("add", &[x, y]).unwrap()[0];
+        let e = dsl.execute("add", &[c, d]).unwrap()[0];
+        let f = dsl.execute("add", &[e, p]).unwrap()[0];
+        dsl.set_program_output("num", f).unwrap();
+
+        // the pinned call is kept, even though it computes a again
+        let original = dsl.clone();
+        CommonSubexpressionElimination.run(&mut dsl).unwrap();
+        assert_eq!(dsl.trace.len(), 5);
+        assert_eq!(dsl.trace[1], TraceEntry::FunctionCall("add".to_string(), vec![a, a]));
+        assert_eq!(dsl.pinned.iter().copied().collect::<Vec<usize>>(), vec![2]);
+        assert_eq!(dsl.trace[2], TraceEntry::FunctionCall("add".to_string(), vec![x, y]));
+        assert_equivalent(
+            &original,
+            &dsl,
+            &[vec![Element::Num(0), Element::Num(0)], vec![Element::Num(-3), Element::Num(8)]],
+        );
+        test_program(dsl, script! { 15 }).unwrap();
+    }
+
+    #[test]
+    fn test_canonicalize_inputs() {
+        fn build(reversed: bool) -> DSL {
+            let mut dsl = new_dsl_with_add();
+            dsl.add_data_type("str", ElementType::Str).unwrap();
+
+            let mut allocations = vec![
+                ("num", "x", Element::Num(1)),
+                ("str", "s", Element::Str(b"abc".to_vec())),
+                ("num", "y", Element::Num(2)),
+            ];
+            if reversed {
+                allocations.reverse();
+            }
+            let mut idxs = HashMap::new();
+            for (data_type, name, data) in allocations {
+                let idx = dsl.alloc_input(data_type, data).unwrap();
+                dsl.set_name(idx, name).unwrap();
+                idxs.insert(name, idx);
+            }
+
+            let (x, y, s) = (idxs["x"], idxs["y"], idxs["s"]);
+            let z = dsl.execute("add", &[x, y]).unwrap()[0];
+            let w = dsl.execute("add", &[z, x]).unwrap()[0];
+            dsl.set_program_output("num", w).unwrap();
+            dsl.set_program_output("str", s).unwrap();
+            dsl
+        }
+
+        let mut a = build(false);
+        let mut b = build(true);
+        assert_ne!(
+            Compiler::compiler(a.clone()).unwrap().script,
+            Compiler::compiler(b.clone()).unwrap().script
+        );
+
+        a.canonicalize_inputs().unwrap();
+        b.canonicalize_inputs().unwrap();
+        let names = (0..3)
+            .map(|idx| a.memory.get(&idx).unwrap().description.clone().unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["x", "y", "s"]);
+        assert_eq!(a.trace, b.trace);
+
+        let program_a = Compiler::compiler(a).unwrap();
+        let program_b = Compiler::compiler(b).unwrap();
+        for (input_a, input_b) in program_a.input.iter().zip(program_b.input.iter()) {
+            assert_eq!(input_a.description, input_b.description);
+            assert_eq!(input_a.data, input_b.data);
+        }
+        assert_eq!(program_a.script, program_b.script);
+        simulate(&program_a).unwrap();
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(2)).unwrap();
+        let d = dsl.alloc_constant("num", Element::Num(3)).unwrap();
+        let e = dsl.execute("add", &[c, d]).unwrap()[0];
+        let f = dsl.execute("add", &[e, e]).unwrap()[0];
+        let g = dsl.execute("add", &[x, f]).unwrap()[0];
+        dsl.set_program_output("num", g).unwrap();
+
+        let original = dsl.clone();
+        dsl.fold_constants().unwrap();
+        assert_eq!(dsl.trace[2], TraceEntry::AllocatedConstant(e));
+        assert_eq!(dsl.trace[3], TraceEntry::AllocatedConstant(f));
+
+        // the constants that were folded into others are no longer needed
+        dsl.eliminate_dead_code().unwrap();
+        assert_eq!(dsl.trace.len(), 2);
+        assert_equivalent(&original, &dsl, &[vec![Element::Num(-10)], vec![Element::Num(7)]]);
+        test_program(dsl, script! { 11 }).unwrap();
+    }
+
+    struct RemoveStep(usize);
+
+    impl TracePass for RemoveStep {
+        fn run(&self, dsl: &mut DSL) -> Result<()> {
+            let num_inputs = dsl.num_inputs.unwrap_or(dsl.memory_last_idx);
+            let order = (0..dsl.trace.len())
+                .filter(|&step| step != self.0)
+                .collect::<Vec<usize>>();
+            dsl.reorder(&(0..num_inputs).collect::<Vec<usize>>(), &order)
+        }
+    }
+
+    #[test]
+    fn test_compile_with_passes() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(2)).unwrap();
+        let a = dsl.execute("add", &[x, c]).unwrap()[0];
+        dsl.execute("add", &[a, a]).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+
+        let mut expected = dsl.clone();
+        expected.trace.pop();
+        expected.memory_last_idx -= 1;
+
+        let passes: Vec<Box<dyn TracePass>> = vec![
+            Box::new(RemoveStep(2)),
+            Box::new(ConstantFolding),
+            Box::new(DeadCodeElimination),
+        ];
+        let program = Compiler::compile_with_passes(dsl, &passes).unwrap();
+        assert_eq!(program.script, Compiler::compiler(expected).unwrap().script);
+    }
+}
@@ -1,8 +1,1289 @@
-use crate::dsl::MemoryEntry;
+use crate::compiler::{encode_entry, CompileDecision};
+use crate::data_type::{decoded_data_type, DataTypeRegistry};
+use crate::dsl::{Element, ElementType, MemoryEntry, TraceEntry, DSL};
+use crate::functions::FunctionRegistry;
+use crate::ir::{element_from_json, json_array, json_idxs, json_str, trace_from_json, trace_to_json};
+use crate::{execute_program, expected_final_stack};
+use crate::gadgets::commitment::{commitment, commitment_script};
 use crate::treepp::Script;
+use anyhow::{Error, Result};
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::Instruction;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
 
 pub struct CompiledProgram {
     pub input: Vec<MemoryEntry>,
     pub script: Script,
     pub hint: Vec<MemoryEntry>,
+    pub output: Vec<MemoryEntry>,
+    pub metadata: Option<Value>,
+    pub report: CompileReport,
+    /// the range of bytes of the script generated by each trace entry, after the trace passes
+    pub trace_byte_ranges: Vec<(usize, usize)>,
+    /// the annotations of the trace entries, after the trace passes
+    pub annotations: HashMap<usize, BTreeMap<String, String>>,
+    /// the trace after the trace passes, with its constants and hints in their original form
+    pub trace: Vec<TraceEntry>,
+    pub allocations: Vec<MemoryEntry>,
+    pub output_idxs: Vec<usize>,
+    /// whether the outputs are left in the altstack instead of being recovered onto the stack
+    pub output_in_altstack: bool,
+    /// the decisions of the compiler, if recorded
+    pub decisions: Vec<CompileDecision>,
+    /// the kinds of opcodes emitted by the script of each function, by its name, if recorded
+    pub coverage: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CompileReport {
+    pub script_size: usize,
+    pub num_inputs: usize,
+    pub num_constants: usize,
+    pub num_hints: usize,
+    /// the peak number of elements in the compiler's stack model, excluding what gadgets use internally
+    pub max_stack_depth: usize,
+    /// the deepest position that is picked, rolled, or passed by reference
+    pub max_access_depth: usize,
+    pub num_shuffle_ops: usize,
+    /// per value: the inputs of function calls that are copied and that are moved, respectively,
+    /// however many elements they have and whichever opcodes do it
+    pub picked_values: usize,
+    pub rolled_values: usize,
+    /// per opcode: the OP_PICK and OP_ROLL that the compiler emits, for the inputs, spills and
+    /// outputs, excluding those in gadgets
+    pub op_pick_count: usize,
+    pub op_roll_count: usize,
+    pub num_spills: usize,
+    /// the elements in the altstack right before the outputs are recovered from it
+    pub final_altstack_depth: usize,
+    /// the offsets where the segments end, with `CompilerOptions::segment_size`, where the
+    /// markers start if there are any
+    pub segment_boundaries: Vec<usize>,
+    pub opcode_histogram: BTreeMap<String, usize>,
+}
+
+impl Display for CompileReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "script size: {} bytes", self.script_size)?;
+        writeln!(f, "inputs: {}", self.num_inputs)?;
+        writeln!(f, "constants: {}", self.num_constants)?;
+        writeln!(f, "hints: {}", self.num_hints)?;
+        writeln!(f, "max stack depth: {}", self.max_stack_depth)?;
+        writeln!(f, "max access depth: {}", self.max_access_depth)?;
+        writeln!(f, "shuffle ops: {}", self.num_shuffle_ops)?;
+        writeln!(f, "picked values: {}", self.picked_values)?;
+        writeln!(f, "rolled values: {}", self.rolled_values)?;
+        writeln!(f, "OP_PICK opcodes: {}", self.op_pick_count)?;
+        writeln!(f, "OP_ROLL opcodes: {}", self.op_roll_count)?;
+        writeln!(f, "spills: {}", self.num_spills)?;
+        writeln!(f, "final altstack depth: {}", self.final_altstack_depth)?;
+        writeln!(f, "opcode histogram:")?;
+        for (name, count) in self.opcode_histogram.iter() {
+            writeln!(f, "  {}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// A metric of two programs `a` and `b`, where the delta is from `a` to `b`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetricDelta {
+    pub a: usize,
+    pub b: usize,
+}
+
+impl MetricDelta {
+    pub fn delta(&self) -> isize {
+        self.b as isize - self.a as isize
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramComparison {
+    pub script_size: MetricDelta,
+    /// the bytes of the hints in the witness, without the length prefixes
+    pub hint_bytes: MetricDelta,
+    pub sigops: MetricDelta,
+    pub max_stack_depth: MetricDelta,
+    /// the witness weight when spent as a P2WSH
+    pub weight: MetricDelta,
+}
+
+pub fn compare(a: &CompiledProgram, b: &CompiledProgram) -> ProgramComparison {
+    let metric = |f: fn(&CompiledProgram) -> usize| MetricDelta { a: f(a), b: f(b) };
+    ProgramComparison {
+        script_size: metric(|program| program.script.len()),
+        hint_bytes: metric(|program| {
+            program
+                .hint
+                .iter()
+                .flat_map(|entry| entry.data.to_witness())
+                .map(|element| element.len())
+                .sum()
+        }),
+        sigops: metric(|program| program.num_sigops()),
+        max_stack_depth: metric(|program| program.report.max_stack_depth),
+        weight: metric(|program| program.weight()),
+    }
+}
+
+impl Display for ProgramComparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<16} {:>10} {:>10} {:>10}", "metric", "a", "b", "delta")?;
+        for (name, metric) in [
+            ("script size", &self.script_size),
+            ("hint bytes", &self.hint_bytes),
+            ("sigops", &self.sigops),
+            ("max stack depth", &self.max_stack_depth),
+            ("weight", &self.weight),
+        ] {
+            writeln!(
+                f,
+                "{:<16} {:>10} {:>10} {:>+10}",
+                name,
+                metric.a,
+                metric.b,
+                metric.delta()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+const SHUFFLE_OPCODES: [Opcode; 18] = [
+    OP_TOALTSTACK,
+    OP_FROMALTSTACK,
+    OP_2DROP,
+    OP_2DUP,
+    OP_3DUP,
+    OP_2OVER,
+    OP_2ROT,
+    OP_2SWAP,
+    OP_IFDUP,
+    OP_DROP,
+    OP_DUP,
+    OP_NIP,
+    OP_OVER,
+    OP_PICK,
+    OP_ROLL,
+    OP_ROT,
+    OP_SWAP,
+    OP_TUCK,
+];
+
+/// How the script is spent, which decides the witness items that come after the stack.
+/// Weights only count the witness, in which every byte is one weight unit; the rest of the
+/// transaction input (outpoint, sequence, and the empty script sig) is not included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpendType {
+    /// P2WSH, where the witness ends with the script.
+    WitnessScript,
+    /// Taproot script path, where the witness ends with the script and the control block of a
+    /// leaf at the given depth of the script tree, without an annex.
+    TaprootScriptPath { merkle_depth: usize },
+}
+
+/// Limits that the compiled program is checked against, by default none.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptPolicy {
+    pub max_script_size: Option<usize>,
+    /// signature operations, where a multisig counts as 20, as in the legacy counting
+    pub max_sigops: Option<usize>,
+    pub disallowed_opcodes: Vec<Opcode>,
+    /// the peak depth of the compiler's stack model, as in the compile report
+    pub max_stack_depth: Option<usize>,
+}
+
+impl ScriptPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_script_size(mut self, max_script_size: usize) -> Self {
+        self.max_script_size = Some(max_script_size);
+        self
+    }
+
+    pub fn with_max_sigops(mut self, max_sigops: usize) -> Self {
+        self.max_sigops = Some(max_sigops);
+        self
+    }
+
+    pub fn with_disallowed_opcode(mut self, opcode: Opcode) -> Self {
+        self.disallowed_opcodes.push(opcode);
+        self
+    }
+
+    pub fn with_max_stack_depth(mut self, max_stack_depth: usize) -> Self {
+        self.max_stack_depth = Some(max_stack_depth);
+        self
+    }
+}
+
+impl CompiledProgram {
+    pub fn weight(&self) -> usize {
+        self.weight_for(SpendType::WitnessScript)
+    }
+
+    pub fn weight_for(&self, spend_type: SpendType) -> usize {
+        // the witness stack is the hints, then the inputs, then the script itself
+        let mut items = vec![];
+        for entry in self.hint.iter().chain(self.input.iter()) {
+            items.extend(entry.data.to_witness());
+        }
+        items.push(self.script.to_bytes());
+        if let SpendType::TaprootScriptPath { merkle_depth } = spend_type {
+            // the leaf version and internal key, followed by the merkle path
+            items.push(vec![0u8; 33 + 32 * merkle_depth]);
+        }
+
+        let mut weight = compact_size_len(items.len());
+        for item in items.iter() {
+            weight += compact_size_len(item.len()) + item.len();
+        }
+        weight
+    }
+
+    pub fn output_sizes(&self) -> Vec<usize> {
+        // the number of stack elements of each output, in their encoded form
+        self.output.iter().map(|entry| entry.data.to_witness().len()).collect()
+    }
+
+    pub fn output_byte_sizes(&self) -> Vec<usize> {
+        // the bytes of the stack elements of each output for the values of this compilation,
+        // without any length prefixes, as the outputs stay on the stack
+        self.output
+            .iter()
+            .map(|entry| entry.data.to_witness().iter().map(|element| element.len()).sum())
+            .collect()
+    }
+
+    pub fn hint_names(&self) -> Vec<Option<&str>> {
+        self.hint
+            .iter()
+            .map(|entry| entry.description.as_deref())
+            .collect()
+    }
+
+    pub fn opcode_histogram(&self) -> BTreeMap<String, usize> {
+        // data pushes are counted together, regardless of their length
+        let mut histogram = BTreeMap::new();
+        for instruction in self.script.instructions() {
+            *histogram.entry(instruction_kind(&instruction)).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    pub fn num_shuffle_ops(&self) -> usize {
+        self.script
+            .instructions()
+            .filter(|instruction| match instruction {
+                Ok(Instruction::Op(opcode)) => SHUFFLE_OPCODES.contains(opcode),
+                _ => false,
+            })
+            .count()
+    }
+
+    pub fn num_sigops(&self) -> usize {
+        self.script
+            .instructions()
+            .map(|instruction| match instruction {
+                Ok(Instruction::Op(OP_CHECKSIG))
+                | Ok(Instruction::Op(OP_CHECKSIGVERIFY))
+                | Ok(Instruction::Op(OP_CHECKSIGADD)) => 1,
+                Ok(Instruction::Op(OP_CHECKMULTISIG))
+                | Ok(Instruction::Op(OP_CHECKMULTISIGVERIFY)) => 20,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn max_push_size(&self) -> usize {
+        // the length of the largest data push, which standardness limits to 520 bytes
+        self.script
+            .instructions()
+            .map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => bytes.len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn check_policy(&self, policy: &ScriptPolicy) -> Result<()> {
+        if let Some(max_script_size) = policy.max_script_size {
+            if self.script.len() > max_script_size {
+                return Err(Error::msg(format!(
+                    "The script has {} bytes, over the limit of {} bytes",
+                    self.script.len(),
+                    max_script_size
+                )));
+            }
+        }
+
+        if let Some(max_sigops) = policy.max_sigops {
+            let num_sigops = self.num_sigops();
+            if num_sigops > max_sigops {
+                return Err(Error::msg(format!(
+                    "The script has {} signature operations, over the limit of {}",
+                    num_sigops, max_sigops
+                )));
+            }
+        }
+
+        for (offset, instruction) in self.script.instruction_indices() {
+            if let Ok(Instruction::Op(opcode)) = instruction {
+                if policy.disallowed_opcodes.contains(&opcode) {
+                    return Err(Error::msg(format!(
+                        "The script has the disallowed opcode {:?} at byte {}",
+                        opcode, offset
+                    )));
+                }
+            }
+        }
+
+        if let Some(max_stack_depth) = policy.max_stack_depth {
+            if self.report.max_stack_depth > max_stack_depth {
+                return Err(Error::msg(format!(
+                    "The stack reaches a depth of {}, over the limit of {}",
+                    self.report.max_stack_depth, max_stack_depth
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn repeated_fragments(&self, min_len: usize) -> Vec<(Vec<u8>, Vec<usize>)> {
+        // Group the offsets by the `min_len` bytes that start there, and extend every group to the
+        // right as long as all its occurrences agree. A group whose occurrences all agree on the
+        // preceding byte is skipped, since it is part of a longer fragment that starts earlier.
+        let bytes = self.script.as_bytes();
+        if min_len == 0 || bytes.len() < min_len {
+            return vec![];
+        }
+
+        let mut groups = HashMap::<&[u8], Vec<usize>>::new();
+        for pos in 0..=bytes.len() - min_len {
+            groups.entry(&bytes[pos..pos + min_len]).or_default().push(pos);
+        }
+
+        let mut res = vec![];
+        for positions in groups.into_values() {
+            if positions.len() < 2 {
+                continue;
+            }
+            let left_extendable = positions[0] > 0
+                && positions
+                    .iter()
+                    .all(|&pos| pos > 0 && bytes[pos - 1] == bytes[positions[0] - 1]);
+            if left_extendable {
+                continue;
+            }
+
+            let mut len = min_len;
+            while positions.iter().all(|&pos| {
+                pos + len < bytes.len() && bytes[pos + len] == bytes[positions[0] + len]
+            }) {
+                len += 1;
+            }
+            res.push((bytes[positions[0]..positions[0] + len].to_vec(), positions));
+        }
+
+        res.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.1[0].cmp(&b.1[0])));
+        res
+    }
+
+    pub fn commit_output(mut self) -> Result<Self> {
+        // replace the outputs on the stack by a single 32-byte commitment, which requires OP_CAT
+        if self.output_in_altstack {
+            return Err(Error::msg(
+                "The outputs left in the altstack cannot be committed to",
+            ));
+        }
+        let mut elements = vec![];
+        for entry in self.output.iter() {
+            elements.extend(entry.data.to_witness());
+        }
+        if elements.is_empty() {
+            return Err(Error::msg("The program has no output to commit to"));
+        }
+        // every element but the last is concatenated with a 32-byte hash, within the 520-byte limit
+        if let Some(i) = elements[..elements.len() - 1].iter().position(|element| element.len() > 488) {
+            return Err(Error::msg(format!(
+                "The output element {} has {} bytes, over the 488 bytes that can be concatenated with a hash",
+                i,
+                elements[i].len()
+            )));
+        }
+
+        // the indices of the outputs are kept, as they still name the values that are committed to
+        let mut script = self.script.to_bytes();
+        script.extend_from_slice(commitment_script(elements.len()).as_bytes());
+        self.script = Script::from_bytes(script);
+        self.output = vec![MemoryEntry::new("str", Element::Str(commitment(&elements)))];
+
+        self.report.script_size = self.script.len();
+        self.report.num_shuffle_ops = self.num_shuffle_ops();
+        self.report.opcode_histogram = self.opcode_histogram();
+        Ok(self)
+    }
+
+    pub fn split_at_op(&self, op_index: usize) -> Result<(Script, Script)> {
+        // the instruction at `op_index` starts the second half, and splitting at the number of
+        // instructions leaves the second half empty
+        let mut offset = None;
+        let mut num_instructions = 0;
+        for (i, (byte_offset, instruction)) in self.script.instruction_indices().enumerate() {
+            if instruction.is_err() {
+                return Err(Error::msg(format!(
+                    "The script cannot be decoded at byte {}",
+                    byte_offset
+                )));
+            }
+            if i == op_index {
+                offset = Some(byte_offset);
+            }
+            num_instructions += 1;
+        }
+        let offset = match offset {
+            Some(offset) => offset,
+            None if op_index == num_instructions => self.script.len(),
+            None => {
+                return Err(Error::msg(format!(
+                    "The script has {} instructions, so it cannot be split at instruction {}",
+                    num_instructions, op_index
+                )))
+            }
+        };
+
+        let bytes = self.script.as_bytes();
+        Ok((
+            Script::from_bytes(bytes[..offset].to_vec()),
+            Script::from_bytes(bytes[offset..].to_vec()),
+        ))
+    }
+
+    pub fn source_map(&self) -> Vec<(usize, usize, usize)> {
+        // (byte_start, byte_end, trace_index), ordered by position in the script, while the checks
+        // at the start and the handling of the outputs at the end belong to no trace entry
+        let mut res = self
+            .trace_byte_ranges
+            .iter()
+            .enumerate()
+            .filter(|(_, (start, end))| start < end)
+            .map(|(trace_idx, &(start, end))| (start, end, trace_idx))
+            .collect::<Vec<(usize, usize, usize)>>();
+        res.sort();
+        res
+    }
+
+    pub fn to_artifact_json(&self) -> Value {
+        json!({
+            "script": to_hex(self.script.as_bytes()),
+            "input": self.input.iter().map(memory_entry_to_json).collect::<Vec<Value>>(),
+            "hint": self.hint.iter().map(memory_entry_to_json).collect::<Vec<Value>>(),
+            "metadata": self.metadata.clone().unwrap_or(Value::Null),
+            "source_map": self.source_map(),
+            "annotations": self.annotations.iter().collect::<BTreeMap<_, _>>(),
+            "trace": trace_to_json(&self.trace, &self.allocations),
+            "output_idxs": self.output_idxs,
+        })
+    }
+
+    pub fn to_dsl_skeleton(
+        &self,
+        data_type_registry: &DataTypeRegistry,
+        function_registry: &FunctionRegistry,
+    ) -> Result<DSL> {
+        dsl_skeleton_from_artifact(&self.to_artifact_json(), data_type_registry, function_registry)
+    }
+
+    pub fn to_miniscript(&self) -> Result<String> {
+        // Only spending conditions are recognized: timelocks, and embedded scripts that each
+        // consume one input and verify it as a hash preimage or a signature. They all leave
+        // nothing on the stack, so they are V-type, as `v:older`, `v:sha256` or `v:pk`, and are
+        // joined in the order of the trace with `and_v`, which requires all of them. The result is
+        // thus V-type as well, and encodes to the script of the program.
+        if !self.output.is_empty() || !self.hint.is_empty() {
+            return Err(Error::msg(
+                "The program has outputs or hints, which are not expressible in Miniscript",
+            ));
+        }
+
+        let mut conditions = vec![];
+        let mut consumed = vec![];
+        for (step, trace_entry) in self.trace.iter().enumerate() {
+            let condition = match trace_entry {
+                // Miniscript has no timelocks of zero, which the compiler drops instead of verifying
+                TraceEntry::RelativeTimelock(sequence) if *sequence != 0 => {
+                    Some(format!("v:older({})", sequence))
+                }
+                TraceEntry::AbsoluteTimelock(height_or_time) if *height_or_time != 0 => {
+                    Some(format!("v:after({})", height_or_time))
+                }
+                TraceEntry::EmbeddedScript(script, inputs, outputs)
+                    if inputs.len() == 1 && outputs.is_empty() =>
+                {
+                    consumed.push(inputs[0]);
+                    miniscript_fragment(script)
+                }
+                _ => None,
+            };
+            match condition {
+                Some(condition) => conditions.push(condition),
+                None => {
+                    return Err(Error::msg(format!(
+                        "The trace step {} is not expressible in Miniscript",
+                        step
+                    )))
+                }
+            }
+        }
+
+        consumed.sort_unstable();
+        if conditions.is_empty() || consumed != (0..self.input.len()).collect::<Vec<usize>>() {
+            return Err(Error::msg(
+                "The inputs are not each checked by one condition, which is not expressible in Miniscript",
+            ));
+        }
+
+        let mut res = conditions.pop().unwrap();
+        while let Some(condition) = conditions.pop() {
+            res = format!("and_v({},{})", condition, res);
+        }
+        Ok(res)
+    }
+
+    pub fn self_check(&self) -> Result<()> {
+        // the program must run on its own inputs and hints and leave exactly its outputs, where the
+        // outputs left in the altstack are first brought back
+        let mut script = self.script.to_bytes();
+        if self.output_in_altstack {
+            let num_elements = self.output.iter().map(|entry| entry.data.len()).sum::<usize>();
+            script.extend(std::iter::repeat(OP_FROMALTSTACK.to_u8()).take(num_elements));
+        }
+        execute_program(
+            &Script::from_bytes(script),
+            &self.input,
+            &self.hint,
+            &expected_final_stack(&self.output),
+        )
+        .map_err(|e| e.context("The program does not run on its own inputs and hints"))
+    }
+
+    pub fn fuzz_check(&self, dsl: &DSL, iterations: usize) -> Result<()> {
+        // The trace generators are the reference. For random inputs of the same types and sizes
+        // as those in `dsl`, which the program is compiled from, the trace is replayed and the
+        // script must reach the replayed outputs from the replayed hints. Inputs rejected by the
+        // reference are skipped, and constants and allocated hints keep their values.
+        let num_inputs = dsl.num_inputs.unwrap_or(dsl.memory_last_idx);
+        if num_inputs != self.input.len() || dsl.hint.len() != self.hint.len() {
+            return Err(Error::msg(
+                "The program is not compiled from the given DSL",
+            ));
+        }
+
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+        let mut num_checked = 0;
+        for iteration in 0..iterations {
+            let inputs = (0..num_inputs)
+                .map(|idx| {
+                    let entry = dsl.memory.get(&idx).ok_or(Error::msg(
+                        "Could not find the memory entry with the given index",
+                    ))?;
+                    Ok(MemoryEntry {
+                        data: random_element(&mut prng, &entry.data),
+                        ..entry.clone()
+                    })
+                })
+                .collect::<Result<Vec<MemoryEntry>>>()?;
+
+            let replay = match dsl.replay_with_inputs(inputs.clone()) {
+                Ok(replay) => replay,
+                Err(_) => continue,
+            };
+            if replay.hint.len() != self.hint.len() {
+                return Err(Error::msg(format!(
+                    "The number of hints changes in iteration {} on the inputs {:?}",
+                    iteration,
+                    inputs.iter().map(|entry| &entry.data).collect::<Vec<&Element>>()
+                )));
+            }
+
+            let input = inputs
+                .iter()
+                .map(|entry| encode_entry(&replay, entry))
+                .collect::<Result<Vec<MemoryEntry>>>()?;
+            let hint = replay
+                .hint
+                .iter()
+                .map(|entry| encode_entry(&replay, entry))
+                .collect::<Result<Vec<MemoryEntry>>>()?;
+            let outputs = replay
+                .output
+                .iter()
+                .map(|idx| encode_entry(&replay, replay.memory.get(idx).unwrap()))
+                .collect::<Result<Vec<MemoryEntry>>>()?;
+            let expected_final_stack = expected_final_stack(&outputs);
+
+            if execute_program(&self.script, &input, &hint, &expected_final_stack).is_err() {
+                return Err(Error::msg(format!(
+                    "The script disagrees with the reference in iteration {} on the inputs {:?}",
+                    iteration,
+                    inputs.iter().map(|entry| &entry.data).collect::<Vec<&Element>>()
+                )));
+            }
+            num_checked += 1;
+        }
+
+        if iterations > 0 && num_checked == 0 {
+            return Err(Error::msg(
+                "None of the random inputs are accepted by the reference",
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn instruction_kind(
+    instruction: &std::result::Result<Instruction, bitcoin::script::Error>,
+) -> String {
+    // data pushes are one kind, regardless of their length
+    match instruction {
+        Ok(Instruction::Op(opcode)) => format!("{:?}", opcode),
+        Ok(Instruction::PushBytes(_)) => "OP_PUSHBYTES".to_string(),
+        Err(_) => "INVALID".to_string(),
+    }
+}
+
+fn miniscript_fragment(script: &Script) -> Option<String> {
+    // A hash check is only a Miniscript fragment behind `OP_SIZE 32 OP_EQUALVERIFY`, which
+    // Miniscript emits so that the preimage has a fixed size. Only the VERIFY forms are
+    // recognized, as the forms that leave the result on the stack would leave a value that the
+    // embedded script does not declare.
+    let instructions = bitcoin::Script::from_bytes(script.as_bytes())
+        .instructions()
+        .collect::<std::result::Result<Vec<Instruction>, _>>()
+        .ok()?;
+
+    match instructions.as_slice() {
+        [Instruction::Op(OP_SIZE), Instruction::PushBytes(size), Instruction::Op(OP_EQUALVERIFY), Instruction::Op(hash), Instruction::PushBytes(digest), Instruction::Op(OP_EQUALVERIFY)]
+            if size.as_bytes() == [32] =>
+        {
+            let (name, len) = match *hash {
+                OP_SHA256 => ("sha256", 32),
+                OP_HASH256 => ("hash256", 32),
+                OP_RIPEMD160 => ("ripemd160", 20),
+                OP_HASH160 => ("hash160", 20),
+                _ => return None,
+            };
+            if digest.len() != len {
+                return None;
+            }
+            Some(format!("v:{}({})", name, to_hex(digest.as_bytes())))
+        }
+        // x-only keys, as in Tapscript
+        [Instruction::PushBytes(key), Instruction::Op(OP_CHECKSIGVERIFY)] if key.len() == 32 => {
+            Some(format!("v:pk({})", to_hex(key.as_bytes())))
+        }
+        _ => None,
+    }
+}
+
+fn random_element(prng: &mut ChaCha20Rng, like: &Element) -> Element {
+    // numbers are within 30 bits so that the sum of two is still a valid script number
+    fn random_num(prng: &mut ChaCha20Rng) -> i32 {
+        prng.gen_range(-(1 << 30)..(1 << 30))
+    }
+    fn random_str(prng: &mut ChaCha20Rng, len: usize) -> Vec<u8> {
+        (0..len).map(|_| prng.gen()).collect()
+    }
+
+    match like {
+        Element::Num(_) => Element::Num(random_num(prng)),
+        Element::ManyNum(v) => Element::ManyNum(v.iter().map(|_| random_num(prng)).collect()),
+        Element::Str(v) => Element::Str(random_str(prng, v.len())),
+        Element::ManyStr(v) => {
+            Element::ManyStr(v.iter().map(|vv| random_str(prng, vv.len())).collect())
+        }
+    }
+}
+
+pub fn dsl_skeleton_from_artifact(
+    artifact: &Value,
+    data_type_registry: &DataTypeRegistry,
+    function_registry: &FunctionRegistry,
+) -> Result<DSL> {
+    // The inputs are zeros of the same sizes, except those of types with a custom encoding,
+    // which are decoded from the artifact. The trace generators then run on these placeholders,
+    // so a trace that rejects them cannot be rebuilt.
+    let mut inputs = vec![];
+    for entry in json_array(&artifact["input"])?.iter() {
+        let encoded_type = json_str(&entry["data_type"])?;
+        let data_type = decoded_data_type(encoded_type).unwrap_or(encoded_type);
+        let data_type_metadata = data_type_registry.map.get(data_type).ok_or(
+            Error::msg("The data type of an input is not in the registry"),
+        )?;
+        let data = match (data_type_metadata.decoder, decoded_data_type(encoded_type)) {
+            (Some(decoder), Some(_)) => {
+                let elements = match data_type_metadata.element_type.len() {
+                    1 => element_from_json(&entry["data"], &ElementType::Str)?,
+                    len => element_from_json(&entry["data"], &ElementType::ManyStr(len))?,
+                };
+                decoder(&elements.to_witness())?
+            }
+            _ => match element_from_json(&entry["data"], &data_type_metadata.element_type)? {
+                Element::Num(_) => Element::Num(0),
+                Element::ManyNum(v) => Element::ManyNum(vec![0; v.len()]),
+                Element::Str(v) => Element::Str(vec![0; v.len()]),
+                Element::ManyStr(v) => {
+                    Element::ManyStr(v.iter().map(|vv| vec![0; vv.len()]).collect())
+                }
+            },
+        };
+        let mut input = MemoryEntry::new(data_type, data);
+        if let Some(description) = entry["description"].as_str() {
+            input.description = Some(description.to_string());
+        }
+        inputs.push(input);
+    }
+
+    let (trace, allocations) = trace_from_json(&artifact["trace"], data_type_registry)?;
+    DSL::rebuild(
+        trace,
+        inputs,
+        allocations,
+        json_idxs(&artifact["output_idxs"])?,
+        data_type_registry.clone(),
+        function_registry.clone(),
+    )
+}
+
+pub(crate) fn memory_entry_to_json(entry: &MemoryEntry) -> Value {
+    let data = match &entry.data {
+        Element::Num(v) => json!(v),
+        Element::ManyNum(v) => json!(v),
+        Element::Str(v) => json!(to_hex(v)),
+        Element::ManyStr(v) => json!(v.iter().map(|vv| to_hex(vv)).collect::<Vec<String>>()),
+    };
+    json!({
+        "data_type": entry.data_type,
+        "data": data,
+        "description": entry.description,
+    })
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn compact_size_len(n: usize) -> usize {
+    if n < 0xfd {
+        1
+    } else if n <= 0xffff {
+        3
+    } else if n <= 0xffffffff {
+        5
+    } else {
+        9
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::{Compiler, CompilerOptions};
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::dsl::{Element, ElementType, MemoryEntry, DSL};
+    use crate::functions::{FunctionMetadata, FunctionOutput};
+    use crate::ir::from_hex;
+    use crate::script::{
+        compare, dsl_skeleton_from_artifact, to_hex, MetricDelta, ScriptPolicy, SpendType,
+    };
+    use crate::simulate;
+    use crate::treepp::*;
+    use anyhow::Result;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::opcodes::all::{OP_1ADD, OP_CAT};
+    use serde_json::json;
+
+    fn add1(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a + 1))],
+            new_hints: vec![MemoryEntry::new("num", Element::Num(-200))],
+        })
+    }
+
+    fn add1_gadget(_: &[usize]) -> Result<Script> {
+        Ok(script! {
+            OP_DEPTH OP_1SUB OP_ROLL OP_DROP
+            OP_1ADD
+        })
+    }
+
+    fn build_program() -> DSL {
+        let mut dsl = DSL::new();
+        dsl.add_data_type("num", ElementType::Num).unwrap();
+        dsl.add_function(
+            "add1",
+            FunctionMetadata::new(add1, add1_gadget, vec!["num"], vec!["num"]),
+        )
+        .unwrap();
+
+        let a = dsl.alloc_input("num", Element::Num(1000)).unwrap();
+        let res = dsl.execute("add1", &[a]).unwrap();
+        dsl.set_program_output("num", res[0]).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_output_sizes() {
+        let mut dsl = DSL::new();
+        dsl.add_data_type("num", ElementType::Num).unwrap();
+        dsl.add_data_type("strs", ElementType::ManyStr(3)).unwrap();
+        let a = dsl.alloc_input("num", Element::Num(1000)).unwrap();
+        let b = dsl
+            .alloc_input("strs", Element::ManyStr(vec![vec![1; 4], vec![], vec![2; 32]]))
+            .unwrap();
+        dsl.set_program_output("num", a).unwrap();
+        dsl.set_program_output("strs", b).unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        assert_eq!(program.output_sizes(), vec![1, 3]);
+        // 1000 is the two bytes e8 03
+        assert_eq!(program.output_byte_sizes(), vec![2, 36]);
+    }
+
+    #[test]
+    fn test_max_push_size() {
+        let mut program = Compiler::compiler(build_program()).unwrap();
+        program.script = script! { OP_DUP { vec![7u8; 40] } OP_DROP 1000 { vec![1u8; 75] } OP_2DROP };
+        assert_eq!(program.max_push_size(), 75);
+
+        program.script = script! { OP_DUP OP_ADD 16 };
+        assert_eq!(program.max_push_size(), 0);
+    }
+
+    #[test]
+    fn test_split_at_op() {
+        let mut program = Compiler::compiler(build_program()).unwrap();
+        program.script = script! { OP_DUP { vec![7u8; 40] } OP_DROP 1000 OP_ADD };
+
+        let (a, b) = program.split_at_op(2).unwrap();
+        assert_eq!(a, script! { OP_DUP { vec![7u8; 40] } });
+        assert_eq!(b, script! { OP_DROP 1000 OP_ADD });
+
+        let (a, b) = program.split_at_op(0).unwrap();
+        assert!(a.is_empty());
+        assert_eq!(b, program.script);
+        let (a, b) = program.split_at_op(5).unwrap();
+        assert_eq!(a, program.script);
+        assert!(b.is_empty());
+        assert!(program.split_at_op(6).is_err());
+    }
+
+    #[test]
+    fn test_weight() {
+        let dsl = build_program();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        assert!(program.script.len() < 0xfd);
+
+        // three witness items: the hint -200 (0xc8 0x80), the input 1000 (0xe8 0x03), and the script
+        let expected = 1 + (1 + 2) + (1 + 2) + (1 + program.script.len());
+        assert_eq!(program.weight(), expected);
+    }
+
+    #[test]
+    fn test_taproot_weight() {
+        let dsl = build_program();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        let witness_script_weight = program.weight_for(SpendType::WitnessScript);
+        assert_eq!(witness_script_weight, program.weight());
+
+        // one more witness item: the control block of 33 + 32 * depth bytes
+        assert_eq!(
+            program.weight_for(SpendType::TaprootScriptPath { merkle_depth: 0 }),
+            witness_script_weight + 1 + 33
+        );
+        assert_eq!(
+            program.weight_for(SpendType::TaprootScriptPath { merkle_depth: 2 }),
+            witness_script_weight + 1 + 97
+        );
+    }
+
+    #[test]
+    fn test_artifact_metadata() {
+        let dsl = build_program();
+
+        let program = Compiler::compile_with(
+            dsl,
+            CompilerOptions::new().with_metadata(json!({ "circuit_id": "add1", "version": 2 })),
+        )
+        .unwrap();
+
+        let artifact = program.to_artifact_json();
+        assert_eq!(artifact["metadata"]["circuit_id"], "add1");
+        assert_eq!(artifact["metadata"]["version"], 2);
+        assert_eq!(artifact["input"][0]["data"], 1000);
+        assert_eq!(artifact["hint"][0]["data"], -200);
+    }
+
+    #[test]
+    fn test_source_map() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_hint("num", Element::Num(2)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(3)).unwrap();
+        let d = dsl.execute("add", &[a, b]).unwrap()[0];
+        dsl.require_relative_timelock(10);
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+        let num_steps = dsl.trace.len();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        let source_map = program.source_map();
+        assert_eq!(source_map.len(), num_steps);
+
+        // the timelock comes first, and then the entries follow each other up to the outputs
+        assert_eq!(source_map[0].0, 0);
+        assert_eq!(source_map[0].2, 3);
+        for (range, next) in source_map.iter().zip(source_map.iter().skip(1)) {
+            assert_eq!(range.1, next.0);
+        }
+        assert!(source_map.iter().all(|&(_, end, trace_idx)| {
+            end <= program.script.len() && trace_idx < num_steps
+        }));
+
+        let artifact = program.to_artifact_json();
+        assert_eq!(artifact["source_map"][0][2], 3);
+    }
+
+    #[test]
+    fn test_hint_names() {
+        let mut dsl = new_dsl_with_add();
+
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_named_hint("num", Element::Num(2), "b").unwrap();
+        let c = dsl.alloc_hint("num", Element::Num(3)).unwrap();
+        let d = dsl.execute("add", &[a, b]).unwrap()[0];
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        assert_eq!(program.hint_names(), vec![Some("b"), None]);
+    }
+
+    #[test]
+    fn test_repeated_fragments() {
+        let mut program = Compiler::compiler(build_program()).unwrap();
+        program.script = Script::from_bytes(vec![9, 1, 2, 3, 4, 8, 1, 2, 3, 4, 7, 1, 2, 3, 4]);
+
+        assert_eq!(
+            program.repeated_fragments(3),
+            vec![(vec![1, 2, 3, 4], vec![1, 6, 11])]
+        );
+        assert!(program.repeated_fragments(5).is_empty());
+    }
+
+    #[test]
+    fn test_check_policy() {
+        let dsl = build_program();
+        let mut program = Compiler::compiler(dsl).unwrap();
+        let script_size = program.script.len();
+        let max_stack_depth = program.report.max_stack_depth;
+
+        let policy = ScriptPolicy::new()
+            .with_max_script_size(script_size)
+            .with_max_sigops(0)
+            .with_disallowed_opcode(OP_CAT)
+            .with_max_stack_depth(max_stack_depth);
+        program.check_policy(&policy).unwrap();
+
+        let err = program
+            .check_policy(&policy.clone().with_max_script_size(script_size - 1))
+            .unwrap_err();
+        assert!(err.to_string().contains("over the limit"));
+
+        let err = program
+            .check_policy(&policy.clone().with_disallowed_opcode(OP_1ADD))
+            .unwrap_err();
+        assert!(err.to_string().contains("OP_1ADD"));
+
+        assert!(program
+            .check_policy(&policy.clone().with_max_stack_depth(max_stack_depth - 1))
+            .is_err());
+
+        // only the script counts for the sigops
+        program.script = script! { OP_CHECKSIGVERIFY OP_CHECKMULTISIG };
+        assert_eq!(program.num_sigops(), 21);
+        program.check_policy(&ScriptPolicy::new().with_max_sigops(21)).unwrap();
+        assert!(program.check_policy(&ScriptPolicy::new().with_max_sigops(20)).is_err());
+    }
+
+    #[test]
+    fn test_to_dsl_skeleton() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.alloc_constant("num", Element::Num(100)).unwrap();
+        let d = dsl.execute("add", &[a, b]).unwrap()[0];
+        let e = dsl.execute("add", &[d, c]).unwrap()[0];
+        dsl.set_program_output("num", e).unwrap();
+
+        let data_type_registry = dsl.data_type_registry.clone();
+        let function_registry = dsl.function_registry.clone();
+        let program = Compiler::compiler(dsl).unwrap();
+
+        let skeleton = program
+            .to_dsl_skeleton(&data_type_registry, &function_registry)
+            .unwrap();
+        assert_eq!(skeleton.memory.get(&a).unwrap().data, Element::Num(0));
+        assert_eq!(skeleton.memory.get(&e).unwrap().data, Element::Num(100));
+
+        let recompiled = Compiler::compiler(skeleton).unwrap();
+        assert_eq!(recompiled.script, program.script);
+
+        // the artifact alone carries the trace, through its text form
+        let artifact = program.to_artifact_json().to_string();
+        let artifact: serde_json::Value = serde_json::from_str(&artifact).unwrap();
+        assert_eq!(artifact["output_idxs"], json!([e]));
+        let skeleton =
+            dsl_skeleton_from_artifact(&artifact, &data_type_registry, &function_registry).unwrap();
+        assert_eq!(skeleton.trace, program.trace);
+        let recompiled = Compiler::compiler(skeleton).unwrap();
+        assert_eq!(recompiled.script, program.script);
+    }
+
+    #[test]
+    fn test_self_check() {
+        let dsl = build_program();
+        let mut program = Compiler::compiler(dsl.clone()).unwrap();
+        program.self_check().unwrap();
+        Compiler::compile_with(dsl, CompilerOptions::new().with_self_check()).unwrap();
+
+        // dropping the witness first underflows the stack
+        let mut script = script! { OP_2DROP OP_DROP }.to_bytes();
+        script.extend_from_slice(program.script.as_bytes());
+        program.script = Script::from_bytes(script);
+        let err = format!("{:#}", program.self_check().unwrap_err());
+        assert!(err.starts_with("The program does not run on its own inputs and hints"));
+        assert!(err.contains("Script execution is not successful"));
+    }
+
+    #[test]
+    fn test_compare() {
+        let a = Compiler::compiler(build_program()).unwrap();
+
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let y = dsl.execute("add", &[x, x]).unwrap()[0];
+        dsl.set_program_output("num", y).unwrap();
+        let b = Compiler::compiler(dsl).unwrap();
+
+        let comparison = compare(&a, &b);
+        assert_eq!(comparison.script_size.a, a.script.len());
+        assert_eq!(
+            comparison.script_size.delta(),
+            b.script.len() as isize - a.script.len() as isize
+        );
+        // the hint -200 takes two bytes
+        assert_eq!(comparison.hint_bytes, MetricDelta { a: 2, b: 0 });
+        assert_eq!(comparison.hint_bytes.delta(), -2);
+        assert_eq!(comparison.sigops.delta(), 0);
+        assert_eq!(comparison.weight.delta(), b.weight() as isize - a.weight() as isize);
+
+        let table = comparison.to_string();
+        assert!(table.starts_with("metric"));
+        assert!(table.contains(&format!("{:<16} {:>10} {:>10} {:>10}", "hint bytes", 2, 0, -2)));
+        assert!(table.contains(&format!("{:<16} {:>10} {:>10} {:>10}", "sigops", 0, 0, "+0")));
+    }
+
+    #[test]
+    fn test_commit_output() {
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        dsl.set_program_output("num", b).unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap().commit_output().unwrap();
+
+        // SHA256(3 || SHA256(2))
+        let expected = sha256::Hash::hash(
+            &[vec![3], sha256::Hash::hash(&[2]).to_byte_array().to_vec()].concat(),
+        )
+        .to_byte_array()
+        .to_vec();
+        assert_eq!(program.output[0].data, Element::Str(expected));
+        simulate(&program).unwrap();
+
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.set_program_output("num", a).unwrap();
+        let options = CompilerOptions::new().with_output_left_in_altstack();
+        let program = Compiler::compile_with(dsl, options).unwrap();
+        assert!(program.commit_output().is_err());
+
+        // an element concatenated with a hash must stay within 520 bytes, unlike the last one
+        let build = |len: usize| {
+            let mut dsl = new_dsl_with_add();
+            dsl.add_data_type("str", ElementType::Str).unwrap();
+            let s = dsl.alloc_input("str", Element::Str(vec![7; len])).unwrap();
+            let t = dsl.alloc_input("str", Element::Str(vec![8; 520])).unwrap();
+            dsl.set_program_output("str", s).unwrap();
+            dsl.set_program_output("str", t).unwrap();
+            Compiler::compiler(dsl).unwrap()
+        };
+        let program = build(488).commit_output().unwrap();
+        assert_eq!(program.output_idxs, vec![0, 1]);
+        simulate(&program).unwrap();
+        let err = build(489).commit_output().unwrap_err();
+        assert!(err.to_string().contains("The output element 0 has 489 bytes"));
+    }
+
+    #[test]
+    fn test_compile_report() {
+        let dsl = build_program();
+
+        let (program, report) = Compiler::compile_report(dsl).unwrap();
+        assert!(report.contains(&format!("script size: {} bytes", program.script.len())));
+        assert!(report.contains("inputs: 1"));
+        assert!(report.contains("hints: 1"));
+        assert!(report.contains("OP_1ADD: 1"));
+    }
+
+    fn broken_add(dsl: &mut DSL, inputs: &[usize]) -> Result<FunctionOutput> {
+        let a = dsl.get_num(inputs[0])?;
+        let b = dsl.get_num(inputs[1])?;
+        Ok(FunctionOutput {
+            new_elements: vec![MemoryEntry::new("num", Element::Num(a + b))],
+            new_hints: vec![],
+        })
+    }
+
+    fn broken_add_gadget(_: &[usize]) -> Result<Script> {
+        // agrees with the trace generator only when the second input is zero
+        Ok(script! {
+            OP_SUB
+        })
+    }
+
+    #[test]
+    fn test_fuzz_check() {
+        let dsl = build_program();
+        let program = Compiler::compiler(dsl.clone()).unwrap();
+        program.fuzz_check(&dsl, 20).unwrap();
+
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(2)).unwrap();
+        let c = dsl.execute("add", &[a, b]).unwrap()[0];
+        let d = dsl.execute("add", &[c, a]).unwrap()[0];
+        dsl.set_program_output("num", d).unwrap();
+        let program = Compiler::compiler(dsl.clone()).unwrap();
+        program.fuzz_check(&dsl, 20).unwrap();
+
+        // the example inputs pass, but random ones do not
+        let mut dsl = new_dsl_with_add();
+        dsl.add_function(
+            "broken_add",
+            FunctionMetadata::new(broken_add, broken_add_gadget, vec!["num", "num"], vec!["num"]),
+        )
+        .unwrap();
+        let a = dsl.alloc_input("num", Element::Num(5)).unwrap();
+        let b = dsl.alloc_input("num", Element::Num(0)).unwrap();
+        let c = dsl.execute("broken_add", &[a, b]).unwrap()[0];
+        dsl.set_program_output("num", c).unwrap();
+        let program = Compiler::compiler(dsl.clone()).unwrap();
+        simulate(&program).unwrap();
+
+        let err = program.fuzz_check(&dsl, 20).unwrap_err();
+        assert!(err.to_string().contains("iteration 0"));
+    }
+
+    #[test]
+    fn test_to_miniscript() {
+        fn encode_miniscript(ms: &str) -> Vec<u8> {
+            // a V-type expression is not accepted at the top by the parsers, so its tree is parsed
+            use miniscript::expression::{FromTree, Tree};
+            let tree = Tree::from_str(ms).unwrap();
+            miniscript::Miniscript::<bitcoin::XOnlyPublicKey, miniscript::Tap>::from_tree(&tree)
+                .unwrap()
+                .encode()
+                .to_bytes()
+        }
+
+        let preimage = vec![7u8; 32];
+        let digest = sha256::Hash::hash(&preimage).to_byte_array().to_vec();
+        let key = from_hex("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+
+        // the last input is on the top, so it is checked first
+        let mut dsl = DSL::new();
+        dsl.add_data_type("str", ElementType::Str).unwrap();
+        let secret = dsl.alloc_input("str", Element::Str(preimage.clone())).unwrap();
+        let signature = dsl.alloc_input("str", Element::Str(vec![1u8; 64])).unwrap();
+        dsl.require_relative_timelock(144);
+        dsl.embed_script(script! { { key.clone() } OP_CHECKSIGVERIFY }, &[signature], vec![])
+            .unwrap();
+        dsl.embed_script(
+            script! { OP_SIZE 32 OP_EQUALVERIFY OP_SHA256 { digest.clone() } OP_EQUALVERIFY },
+            &[secret],
+            vec![],
+        )
+        .unwrap();
+
+        let program = Compiler::compiler(dsl).unwrap();
+        let ms = program.to_miniscript().unwrap();
+        assert_eq!(
+            ms,
+            format!(
+                "and_v(v:older(144),and_v(v:pk({}),v:sha256({})))",
+                to_hex(&key),
+                to_hex(&digest)
+            )
+        );
+        assert_eq!(encode_miniscript(&ms), program.script.to_bytes());
+
+        // the forms that leave the result on the stack are not those of the compiled programs
+        let mut dsl = DSL::new();
+        dsl.add_data_type("str", ElementType::Str).unwrap();
+        let secret = dsl.alloc_input("str", Element::Str(preimage.clone())).unwrap();
+        dsl.embed_script(
+            script! { OP_SIZE 32 OP_EQUALVERIFY OP_SHA256 { digest.clone() } OP_EQUAL },
+            &[secret],
+            vec![],
+        )
+        .unwrap();
+        let program = Compiler::compiler(dsl).unwrap();
+        let err = program.to_miniscript().err().unwrap();
+        assert!(err.to_string().contains("not expressible"));
+
+        // without the size check, the preimage may have any size, which Miniscript does not express
+        let mut dsl = DSL::new();
+        dsl.add_data_type("str", ElementType::Str).unwrap();
+        let secret = dsl.alloc_input("str", Element::Str(preimage)).unwrap();
+        dsl.embed_script(
+            script! { OP_SHA256 { digest.clone() } OP_EQUALVERIFY },
+            &[secret],
+            vec![],
+        )
+        .unwrap();
+        let program = Compiler::compiler(dsl).unwrap();
+        let err = program.to_miniscript().err().unwrap();
+        assert!(err.to_string().contains("not expressible"));
+
+        // a computation is not a spending condition
+        let program = Compiler::compiler(build_program()).unwrap();
+        let err = program.to_miniscript().err().unwrap();
+        assert!(err.to_string().contains("not expressible"));
+    }
 }
@@ -8,18 +8,24 @@ pub enum StackElementStatus {
     PULLED,
 }
 
+// The elements are ordered by the time they are pushed to the stack, which is tracked by slots.
+// Without restoring, entries are pushed in the order of their indices, so slots follow the indices.
 pub struct Stack {
     pub bitmap: Vec<StackElementStatus>,
+    pub slots: Vec<usize>,
     pub fenwick_tree: FenwickTree<isize>,
     pub size: usize,
+    pub num_slots: usize,
 }
 
 impl Stack {
     pub fn new(size: usize) -> Self {
         Self {
             bitmap: vec![StackElementStatus::ABSENT; size],
+            slots: vec![0; size],
             fenwick_tree: FenwickTree::with_len(size),
             size,
+            num_slots: 0,
         }
     }
 
@@ -29,8 +35,38 @@ impl Stack {
                 "The stack seems to already have these elements.",
             ));
         }
+        self.push_to_new_slot(idx, num_elements)
+    }
+
+    pub fn restore_to_stack(&mut self, idx: usize, num_elements: usize) -> Result<()> {
+        if self.bitmap[idx] != StackElementStatus::PULLED {
+            return Err(Error::msg(
+                "Only elements pulled aside can be restored to the stack.",
+            ));
+        }
+        self.push_to_new_slot(idx, num_elements)
+    }
+
+    fn push_to_new_slot(&mut self, idx: usize, num_elements: usize) -> Result<()> {
+        if self.num_slots == self.size {
+            // restored elements take additional slots, so the tree may need to grow
+            let size = self.size * 2 + 1;
+            let mut fenwick_tree = FenwickTree::with_len(size);
+            for (i, status) in self.bitmap.iter().enumerate() {
+                if let StackElementStatus::PRESENT(n) = status {
+                    fenwick_tree.add(self.slots[i], *n as isize)?;
+                }
+            }
+            self.fenwick_tree = fenwick_tree;
+            self.size = size;
+        }
+
+        let slot = self.num_slots;
+        self.num_slots += 1;
+
         self.bitmap[idx] = StackElementStatus::PRESENT(num_elements);
-        self.fenwick_tree.add(idx, num_elements as isize)?;
+        self.slots[idx] = slot;
+        self.fenwick_tree.add(slot, num_elements as isize)?;
         Ok(())
     }
 
@@ -42,7 +78,7 @@ impl Stack {
         match self.bitmap[idx] {
             StackElementStatus::PRESENT(num_elements) => {
                 self.bitmap[idx] = StackElementStatus::PULLED;
-                self.fenwick_tree.add(idx, -(num_elements as isize))?;
+                self.fenwick_tree.add(self.slots[idx], -(num_elements as isize))?;
 
                 Ok(())
             }
@@ -56,7 +92,7 @@ impl Stack {
         if !matches!(self.bitmap[idx], StackElementStatus::PRESENT(_)) {
             return Err(Error::msg("Only elements in the stack can have the relative position to the top of the stack."));
         }
-        let sum = self.fenwick_tree.sum(idx..self.size)?;
+        let sum = self.fenwick_tree.sum(self.slots[idx]..self.size)?;
         Ok((sum - 1) as usize)
     }
 
@@ -74,6 +110,23 @@ impl Stack {
     pub fn get_num_elements_in_stack(&self) -> Result<usize> {
         Ok(self.fenwick_tree.sum(0..self.size)? as usize)
     }
+
+    pub fn duplicate(&self) -> Result<Stack> {
+        // the tree is rebuilt from the slots of the present elements
+        let mut fenwick_tree = FenwickTree::with_len(self.size);
+        for (i, status) in self.bitmap.iter().enumerate() {
+            if let StackElementStatus::PRESENT(n) = status {
+                fenwick_tree.add(self.slots[i], *n as isize)?;
+            }
+        }
+        Ok(Stack {
+            bitmap: self.bitmap.clone(),
+            slots: self.slots.clone(),
+            fenwick_tree,
+            size: self.size,
+            num_slots: self.num_slots,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -132,4 +185,38 @@ mod test {
         assert_eq!(stack.get_relative_position(3).unwrap(), 19);
         assert_eq!(stack.get_relative_position(4).unwrap(), 17);
     }
+
+    #[test]
+    fn stack_restore_test() {
+        let mut stack = Stack::new(3);
+        stack.push_to_stack(0, 2).unwrap();
+        stack.push_to_stack(1, 3).unwrap();
+        stack.push_to_stack(2, 1).unwrap();
+        assert!(stack.restore_to_stack(1, 3).is_err());
+
+        // a restored element is placed at the top, even if it has a smaller index
+        stack.pull(0).unwrap();
+        stack.restore_to_stack(0, 2).unwrap();
+        assert_eq!(stack.get_relative_position(0).unwrap(), 1);
+        assert_eq!(stack.get_relative_position(1).unwrap(), 5);
+        assert_eq!(stack.get_relative_position(2).unwrap(), 2);
+        assert_eq!(stack.get_num_elements_in_stack().unwrap(), 6);
+    }
+
+    #[test]
+    fn stack_duplicate_test() {
+        let mut stack = Stack::new(3);
+        stack.push_to_stack(0, 2).unwrap();
+        stack.push_to_stack(1, 3).unwrap();
+        stack.pull(0).unwrap();
+        stack.restore_to_stack(0, 2).unwrap();
+
+        // the duplicate changes independently of the original
+        let mut duplicate = stack.duplicate().unwrap();
+        duplicate.pull(1).unwrap();
+        assert_eq!(duplicate.get_relative_position(0).unwrap(), 1);
+        assert!(duplicate.get_relative_position(1).is_err());
+        assert_eq!(stack.get_relative_position(1).unwrap(), 4);
+        assert_eq!(stack.get_num_elements_in_stack().unwrap(), 5);
+    }
 }
@@ -0,0 +1,252 @@
+use crate::compiler::Compiler;
+use crate::dsl::{Element, MemoryEntry, DSL};
+use crate::ir::structurally_equal;
+use crate::script::to_hex;
+use crate::simulate;
+use anyhow::{Error, Result};
+use bitcoin::script::Instruction;
+use bitcoin::ScriptBuf;
+
+const CONTEXT: usize = 5;
+
+pub fn assert_scripts_eq(a: &ScriptBuf, b: &ScriptBuf) {
+    if a != b {
+        panic!("the scripts differ\n{}", script_diff(a, b));
+    }
+}
+
+pub fn script_diff(a: &ScriptBuf, b: &ScriptBuf) -> String {
+    // a side-by-side listing of the opcodes around the first difference
+    let a = disassemble(a);
+    let b = disassemble(b);
+
+    let first_diff = a
+        .iter()
+        .zip(b.iter())
+        .position(|(x, y)| x != y)
+        .unwrap_or(a.len().min(b.len()));
+    let start = first_diff.saturating_sub(CONTEXT);
+    let end = (first_diff + CONTEXT + 1).min(a.len().max(b.len()));
+
+    let mut res = String::new();
+    for i in start..end {
+        let left = a.get(i).map(|v| v.as_str()).unwrap_or("");
+        let right = b.get(i).map(|v| v.as_str()).unwrap_or("");
+        let marker = if i == first_diff { " <-- first difference" } else { "" };
+        res.push_str(&format!("{:>6} {:<24} {:<24}{}\n", i, left, right, marker).trim_end());
+        res.push('\n');
+    }
+    res
+}
+
+pub fn assert_equivalent(a: &DSL, b: &DSL, test_vectors: &[Vec<Element>]) {
+    // both programs are replayed on each test vector, and their scripts checked against the replay
+    for (i, inputs) in test_vectors.iter().enumerate() {
+        let outputs_a = run_test_vector(a, inputs)
+            .unwrap_or_else(|err| panic!("the first program fails on the test vector {}: {}", i, err));
+        let outputs_b = run_test_vector(b, inputs)
+            .unwrap_or_else(|err| panic!("the second program fails on the test vector {}: {}", i, err));
+        if outputs_a != outputs_b {
+            panic!(
+                "the programs disagree on the test vector {}\n{:?}\n{:?}",
+                i, outputs_a, outputs_b
+            );
+        }
+    }
+}
+
+pub fn assert_formats_equivalent(dsl: &DSL) {
+    // the program is reloaded from both formats with its own registries
+    let from_ir = DSL::from_ir(
+        &dsl.to_ir(),
+        dsl.data_type_registry.clone(),
+        dsl.function_registry.clone(),
+    )
+    .unwrap_or_else(|err| panic!("the program cannot be reloaded from the IR: {:?}", err));
+    let from_snapshot = DSL::from_snapshot(
+        &dsl.to_snapshot(),
+        dsl.data_type_registry.clone(),
+        dsl.function_registry.clone(),
+    )
+    .unwrap_or_else(|err| panic!("the program cannot be reloaded from the snapshot: {:?}", err));
+
+    if !structurally_equal(&from_ir, &from_snapshot) {
+        panic!(
+            "the programs reloaded from the IR and the snapshot differ\n{}\n{}",
+            from_ir.to_ir(),
+            from_snapshot.to_ir()
+        );
+    }
+    if !structurally_equal(dsl, &from_ir) {
+        panic!(
+            "the reloaded program differs from the original\n{}\n{}",
+            dsl.to_ir(),
+            from_ir.to_ir()
+        );
+    }
+}
+
+fn run_test_vector(dsl: &DSL, inputs: &[Element]) -> Result<Vec<Element>> {
+    let num_inputs = dsl.num_inputs.unwrap_or(dsl.memory_last_idx);
+    if inputs.len() != num_inputs {
+        return Err(Error::msg(format!(
+            "The test vector has {} inputs, but the program takes {}",
+            inputs.len(),
+            num_inputs
+        )));
+    }
+    let inputs = inputs
+        .iter()
+        .enumerate()
+        .map(|(idx, data)| MemoryEntry::new(&dsl.memory.get(&idx).unwrap().data_type, data.clone()))
+        .collect();
+
+    let replay = dsl.replay_with_inputs(inputs)?;
+    let outputs = replay
+        .output
+        .iter()
+        .map(|idx| replay.memory.get(idx).unwrap().data.clone())
+        .collect();
+
+    let program = Compiler::compiler(replay)?;
+    simulate(&program)?;
+    Ok(outputs)
+}
+
+fn disassemble(script: &ScriptBuf) -> Vec<String> {
+    script
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::Op(opcode)) => format!("{:?}", opcode),
+            Ok(Instruction::PushBytes(bytes)) => format!("<{}>", to_hex(bytes.as_bytes())),
+            Err(_) => "INVALID".to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::Compiler;
+    use crate::dsl::test::new_dsl_with_add;
+    use crate::dsl::{Element, DSL};
+    use crate::gadgets::load_data_types;
+    use crate::testing::{assert_equivalent, assert_formats_equivalent, assert_scripts_eq, script_diff};
+    use crate::treepp::*;
+    use crate::{simulate, test_program};
+
+    #[test]
+    fn test_script_diff() {
+        let a = script! { OP_DUP OP_ADD { vec![0xab, 0xcd] } OP_EQUAL };
+        let b = script! { OP_DUP OP_SUB { vec![0xab, 0xcd] } };
+
+        let expected = "\
+     0 OP_DUP                   OP_DUP
+     1 OP_ADD                   OP_SUB                   <-- first difference
+     2 <abcd>                   <abcd>
+     3 OP_EQUAL
+";
+        assert_eq!(script_diff(&a, &b), expected);
+        assert_scripts_eq(&a, &a.clone());
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference")]
+    fn test_assert_scripts_eq() {
+        assert_scripts_eq(&script! { 1 }, &script! { 2 });
+    }
+
+    #[test]
+    fn test_negative_zero_output() {
+        let new_dsl = |data_type: &str, data: Element| {
+            let mut dsl = DSL::new();
+            load_data_types(&mut dsl).unwrap();
+            let a = dsl.alloc_input(data_type, data).unwrap();
+            dsl.set_program_output(data_type, a).unwrap();
+            dsl
+        };
+
+        // a numeric zero left as `0x80` matches the zero computed by the trace
+        let mut program = Compiler::compiler(new_dsl("num", Element::Num(0))).unwrap();
+        simulate(&program).unwrap();
+        program.script = script! { OP_DROP { vec![0x80u8] } };
+        simulate(&program).unwrap();
+        program.script = script! { OP_DROP 1 };
+        assert!(simulate(&program).is_err());
+
+        test_program(new_dsl("num", Element::Num(0)), script! { 0 }).unwrap();
+
+        // a string is compared byte by byte
+        let mut program = Compiler::compiler(new_dsl("str", Element::Str(vec![]))).unwrap();
+        simulate(&program).unwrap();
+        program.script = script! { OP_DROP { vec![0x80u8] } };
+        assert!(simulate(&program).is_err());
+    }
+
+    fn double(via_add: bool) -> DSL {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        let res = if via_add {
+            dsl.execute("add", &[x, x]).unwrap()[0]
+        } else {
+            let c = dsl.alloc_constant("num", Element::Num(0)).unwrap();
+            let y = dsl.execute("add", &[x, c]).unwrap()[0];
+            dsl.execute("add", &[y, x]).unwrap()[0]
+        };
+        dsl.set_program_output("num", res).unwrap();
+        dsl
+    }
+
+    #[test]
+    fn test_assert_equivalent() {
+        let test_vectors = vec![vec![Element::Num(0)], vec![Element::Num(-7)], vec![Element::Num(100)]];
+        assert_equivalent(&double(true), &double(false), &test_vectors);
+    }
+
+    #[test]
+    #[should_panic(expected = "disagree on the test vector 1")]
+    fn test_assert_equivalent_mismatch() {
+        let mut dsl = new_dsl_with_add();
+        let x = dsl.alloc_input("num", Element::Num(1)).unwrap();
+        dsl.set_program_output("num", x).unwrap();
+
+        // the identity and the doubling only agree on zero
+        assert_equivalent(
+            &dsl,
+            &double(true),
+            &[vec![Element::Num(0)], vec![Element::Num(1)]],
+        );
+    }
+
+    #[test]
+    fn test_assert_formats_equivalent() {
+        assert_formats_equivalent(&double(false));
+
+        // names, hints, annotations, pinned steps and timelocks
+        let mut dsl = new_dsl_with_add();
+        let a = dsl.alloc_input("num", Element::Num(3)).unwrap();
+        dsl.set_name(a, "a \"quoted\" input").unwrap();
+        let h = dsl.alloc_named_hint("num", Element::Num(-4), "h").unwrap();
+        let sum = dsl.execute_pinned("add", &[a, h]).unwrap()[0];
+        dsl.set_name(sum, "sum").unwrap();
+        dsl.annotate(dsl.trace.len() - 1, "note", "the sum").unwrap();
+        dsl.require_relative_timelock(10);
+        dsl.require_absolute_timelock(500000);
+        let res = dsl.execute("add", &[sum, sum]).unwrap()[0];
+        dsl.set_program_output("num", res).unwrap();
+        assert_formats_equivalent(&dsl);
+
+        // functions with options, strings and registered types
+        let mut dsl = DSL::new();
+        load_data_types(&mut dsl).unwrap();
+        crate::gadgets::to_bits::load_functions(&mut dsl).unwrap();
+        crate::gadgets::eq::load_functions(&mut dsl).unwrap();
+        let x = dsl.alloc_input("num", Element::Num(5)).unwrap();
+        let s = dsl.alloc_input("str", Element::Str(vec![0xab, 0xcd])).unwrap();
+        let t = dsl.alloc_constant("str", Element::Str(vec![])).unwrap();
+        let bits = crate::gadgets::to_bits::to_bits(&mut dsl, x, 4).unwrap();
+        let res = crate::gadgets::eq::eq(&mut dsl, s, t).unwrap();
+        dsl.set_program_output("num[4]", bits).unwrap();
+        dsl.set_program_output("num", res).unwrap();
+        assert_formats_equivalent(&dsl);
+    }
+}